@@ -1,6 +1,6 @@
-//! The Domain Module contains the DomainParticipant class that acts as an
+//! The Domain Module contains the `DomainParticipant` class that acts as an
 //! entry-point of the Service and acts as a factory for many of the classes.
-//! The DomainParticipant also acts as a container for the other objects that
+//! The `DomainParticipant` also acts as a container for the other objects that
 //! make up the Service.
 
 use crate::{
@@ -11,10 +11,13 @@ use crate::{
 
 use rtps::structure::participant::Participant;
 
+pub mod scheduler;
+
 #[derive(Debug)]
 pub struct DomainParticipantFactory;
 
 impl DomainParticipantFactory {
+    #[must_use]
     pub fn create_participant() -> DomainParticipant {
         todo!()
     }
@@ -65,6 +68,7 @@ impl DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn create_publisher() -> Publisher {
         todo!()
     }
@@ -73,6 +77,7 @@ impl DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn create_subscriber() -> Subscriber {
         todo!()
     }
@@ -89,6 +94,7 @@ impl DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn create_multitopic() -> MultiTopic {
         todo!()
     }
@@ -97,6 +103,7 @@ impl DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn create_contentfilteredtopic() -> ContentFilteredTopic {
         todo!()
     }
@@ -117,6 +124,7 @@ impl DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn create_topic() -> Topic {
         todo!()
     }
@@ -0,0 +1,750 @@
+//! Drives the RTPS writers and readers of a participant. The endpoints are
+//! pure state machines: the scheduler feeds them the messages received by
+//! the transport, the changes written by the application and their expired
+//! timers, and carries out the outputs they return.
+//!
+//! Submessages addressed to the participants registered with
+//! [`Scheduler::add_local_participant`] are handed to their endpoints
+//! directly instead of going through the transport.
+//!
+//! When the participant discovery reports that a remote participant left or
+//! its lease expired, every endpoint forgets the endpoints of that
+//! participant.
+
+use std::{collections::BTreeMap, fmt, io};
+
+use rtps::{
+    behavior::{
+        intra_process::IntraProcess,
+        timer::{Clock, SystemClock, TimerQueue},
+        Behavior, Input, Outgoing, Output,
+    },
+    messages::{RtpsSubmessage, Time},
+    structure::{historycache::CacheChange, EntityId, Guid, GuidPrefix, Locator, ENTITYID_UNKNOWN},
+};
+use rtps_udp::transport::{Received, UdpTransport};
+
+/// A change delivered to a reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delivered {
+    pub reader: Guid,
+    pub change: CacheChange,
+}
+
+/// A remote writer whose liveliness changed, as tracked by a
+/// [`LivelinessReader`](rtps::behavior::liveliness::LivelinessReader).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LivelinessChanged {
+    pub writer: Guid,
+    pub alive: bool,
+}
+
+/// Changes of `writer` that `reader` will never receive, which count towards
+/// its [`SampleLostStatus`](crate::subscription::SampleLostStatus).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplesLost {
+    pub reader: Guid,
+    pub writer: Guid,
+    pub count: u64,
+}
+
+/// What the endpoints asked for while handling their inputs.
+#[derive(Debug, Default)]
+pub struct Dispatched {
+    pub outgoing: Vec<Outgoing>,
+    pub delivered: Vec<Delivered>,
+    pub liveliness_changed: Vec<LivelinessChanged>,
+    pub samples_lost: Vec<SamplesLost>,
+    /// The remote participants that left or whose lease expired.
+    pub participants_removed: Vec<GuidPrefix>,
+    /// Submessages for local participants not handed over yet.
+    local: Vec<(GuidPrefix, Input)>,
+    /// Removed participants the endpoints were not told about yet.
+    removed: Vec<GuidPrefix>,
+}
+
+impl Dispatched {
+    fn append(&mut self, other: Self) {
+        self.outgoing.extend(other.outgoing);
+        self.delivered.extend(other.delivered);
+        self.liveliness_changed.extend(other.liveliness_changed);
+        self.samples_lost.extend(other.samples_lost);
+        self.participants_removed.extend(other.participants_removed);
+        self.local.extend(other.local);
+        self.removed.extend(other.removed);
+    }
+}
+
+/// Owns the endpoints of a participant and the timers they armed.
+pub struct Scheduler {
+    endpoints: BTreeMap<Guid, Box<dyn Behavior + Send>>,
+    timers: TimerQueue<Guid>,
+    clock: Box<dyn Clock + Send>,
+    intra_process: IntraProcess,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("endpoints", &self.endpoints.keys().collect::<Vec<_>>())
+            .field("timers", &self.timers)
+            .field("intra_process", &self.intra_process)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Scheduler {
+    /// A scheduler driven by the system clock.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    /// A scheduler driven by `clock`, e.g. a
+    /// [`ManualClock`](rtps::behavior::timer::ManualClock) in tests.
+    pub fn with_clock(clock: impl Clock + Send + 'static) -> Self {
+        Self {
+            endpoints: BTreeMap::new(),
+            timers: TimerQueue::new(),
+            clock: Box::new(clock),
+            intra_process: IntraProcess::new(),
+        }
+    }
+
+    /// The current time according to the scheduler's clock.
+    #[must_use]
+    pub fn now(&self) -> Time {
+        self.clock.now()
+    }
+
+    /// Adds an endpoint, replacing any with the same GUID. It receives the
+    /// submessages addressed to its entity id or to [`ENTITYID_UNKNOWN`].
+    pub fn add(&mut self, behavior: Box<dyn Behavior + Send>) {
+        let guid = behavior.guid();
+        self.timers.cancel(&guid);
+        self.endpoints.insert(guid, behavior);
+    }
+
+    /// Hands the submessages addressed to the participant `guid_prefix`,
+    /// which receives on `locators`, to its endpoints directly.
+    pub fn add_local_participant(&mut self, guid_prefix: GuidPrefix, locators: Vec<Locator>) {
+        self.intra_process.add_participant(guid_prefix, locators);
+    }
+
+    pub fn remove_local_participant(&mut self, guid_prefix: GuidPrefix) {
+        self.intra_process.remove_participant(guid_prefix);
+    }
+
+    /// Removes the endpoint `guid`, cancelling its timer, and returns it.
+    pub fn remove(&mut self, guid: Guid) -> Option<Box<dyn Behavior + Send>> {
+        self.timers.cancel(&guid);
+        self.endpoints.remove(&guid)
+    }
+
+    /// The earliest timer armed by an endpoint.
+    #[must_use]
+    pub fn next_timer(&self) -> Option<Time> {
+        self.timers.next_deadline()
+    }
+
+    /// Hands `change`, written by the application, to the writer `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer cannot store the change.
+    pub fn new_change(
+        &mut self,
+        writer: Guid,
+        change: CacheChange,
+        now: Time,
+    ) -> io::Result<Dispatched> {
+        let mut dispatched = Dispatched::default();
+        if let Some(behavior) = self.endpoints.get_mut(&writer) {
+            handle(
+                behavior.as_mut(),
+                &mut self.timers,
+                &self.intra_process,
+                Input::NewChange(change),
+                now,
+                &mut dispatched,
+            )?;
+        }
+        self.handle_queued(&mut dispatched, now)?;
+        Ok(dispatched)
+    }
+
+    /// Makes the writer `writer` send the changes it holds back for
+    /// batching right away.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer cannot update its cache.
+    pub fn flush(&mut self, writer: Guid, now: Time) -> io::Result<Dispatched> {
+        let mut dispatched = Dispatched::default();
+        if let Some(behavior) = self.endpoints.get_mut(&writer) {
+            handle(
+                behavior.as_mut(),
+                &mut self.timers,
+                &self.intra_process,
+                Input::Flush,
+                now,
+                &mut dispatched,
+            )?;
+        }
+        self.handle_queued(&mut dispatched, now)?;
+        Ok(dispatched)
+    }
+
+    /// Routes the submessages of `received` to the endpoints they are
+    /// addressed to.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a reader cannot store a change.
+    pub fn received(&mut self, received: Received, now: Time) -> io::Result<Dispatched> {
+        let mut dispatched = Dispatched::default();
+        for submessage in received.submessages {
+            let target = target(&submessage);
+            for behavior in self
+                .endpoints
+                .iter_mut()
+                .filter(|(guid, _)| target == ENTITYID_UNKNOWN || guid.entity_id() == target)
+                .map(|(_, behavior)| behavior)
+            {
+                let input = Input::Received {
+                    source: received.source,
+                    source_locator: received.source_locator,
+                    submessage: submessage.clone(),
+                };
+                handle(
+                    behavior.as_mut(),
+                    &mut self.timers,
+                    &self.intra_process,
+                    input,
+                    now,
+                    &mut dispatched,
+                )?;
+            }
+        }
+        self.handle_queued(&mut dispatched, now)?;
+        Ok(dispatched)
+    }
+
+    /// Fires the timers due at `now`, earliest first. A timer re-armed while
+    /// firing waits for the next call.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a reader cannot store a change.
+    pub fn fire_timers(&mut self, now: Time) -> io::Result<Dispatched> {
+        let mut dispatched = Dispatched::default();
+        for guid in self.timers.expired(now) {
+            if let Some(behavior) = self.endpoints.get_mut(&guid) {
+                handle(
+                    behavior.as_mut(),
+                    &mut self.timers,
+                    &self.intra_process,
+                    Input::TimerFired,
+                    now,
+                    &mut dispatched,
+                )?;
+            }
+        }
+        self.handle_queued(&mut dispatched, now)?;
+        Ok(dispatched)
+    }
+
+    /// Hands the submessages for local participants to the endpoints they
+    /// are addressed to, and the removed participants to every endpoint,
+    /// along with what is queued in response, in order.
+    fn handle_queued(&mut self, dispatched: &mut Dispatched, now: Time) -> io::Result<()> {
+        while !dispatched.local.is_empty() || !dispatched.removed.is_empty() {
+            for guid_prefix in std::mem::take(&mut dispatched.removed) {
+                for behavior in self.endpoints.values_mut() {
+                    handle(
+                        behavior.as_mut(),
+                        &mut self.timers,
+                        &self.intra_process,
+                        Input::ParticipantRemoved(guid_prefix),
+                        now,
+                        dispatched,
+                    )?;
+                }
+            }
+            for (guid_prefix, input) in std::mem::take(&mut dispatched.local) {
+                let Input::Received { submessage, .. } = &input else {
+                    continue;
+                };
+                let target = target(submessage);
+                for behavior in self
+                    .endpoints
+                    .iter_mut()
+                    .filter(|(guid, _)| {
+                        guid.guid_prefix() == guid_prefix
+                            && (target == ENTITYID_UNKNOWN || guid.entity_id() == target)
+                    })
+                    .map(|(_, behavior)| behavior)
+                {
+                    handle(
+                        behavior.as_mut(),
+                        &mut self.timers,
+                        &self.intra_process,
+                        input.clone(),
+                        now,
+                        dispatched,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for a message on `transport` until the next timer, routes it,
+    /// fires the due timers and sends the resulting messages, which are
+    /// returned along with the rest of what the endpoints dispatched.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the transport fails or a reader cannot store a change.
+    pub fn run_once(&mut self, transport: &mut UdpTransport) -> io::Result<Dispatched> {
+        let timeout = self
+            .next_timer()
+            .map(|timer| timer.as_duration().saturating_sub(self.now().as_duration()));
+        let received = transport.receive(timeout)?;
+        let now = self.now();
+        let mut dispatched = match received {
+            Some(received) => self.received(received, now)?,
+            None => Dispatched::default(),
+        };
+        let fired = self.fire_timers(now)?;
+        dispatched.append(fired);
+        transport.send(&dispatched.outgoing)?;
+        Ok(dispatched)
+    }
+}
+
+/// Hands `input` to `behavior` and carries out its outputs. The endpoint's
+/// timer is replaced by the one it arms, if any, and the submessages it
+/// sends to local participants are queued for them.
+fn handle(
+    behavior: &mut (dyn Behavior + Send),
+    timers: &mut TimerQueue<Guid>,
+    intra_process: &IntraProcess,
+    input: Input,
+    now: Time,
+    dispatched: &mut Dispatched,
+) -> io::Result<()> {
+    let guid = behavior.guid();
+    timers.cancel(&guid);
+    for output in behavior.handle(input, now)? {
+        match output {
+            Output::Send(outgoing) => {
+                let route = intra_process.route(guid.guid_prefix(), outgoing);
+                dispatched.local.extend(route.local);
+                dispatched.outgoing.extend(route.remote);
+            }
+            Output::Deliver(change) => dispatched.delivered.push(Delivered {
+                reader: guid,
+                change,
+            }),
+            Output::ArmTimer(deadline) => {
+                timers.schedule(guid, deadline);
+            }
+            Output::LivelinessChanged { writer, alive } => dispatched
+                .liveliness_changed
+                .push(LivelinessChanged { writer, alive }),
+            Output::SamplesLost { writer, count } => dispatched.samples_lost.push(SamplesLost {
+                reader: guid,
+                writer,
+                count,
+            }),
+            Output::ParticipantRemoved(guid_prefix) => {
+                dispatched.participants_removed.push(guid_prefix);
+                dispatched.removed.push(guid_prefix);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The entity a submessage is addressed to: the writer for the ones sent by
+/// readers, the reader otherwise.
+fn target(submessage: &RtpsSubmessage) -> EntityId {
+    match submessage {
+        RtpsSubmessage::AckNack(acknack) => acknack.writer_id,
+        RtpsSubmessage::NackFrag(nack_frag) => nack_frag.writer_id,
+        RtpsSubmessage::Data(data) => data.reader_id,
+        RtpsSubmessage::DataFrag(data_frag) => data_frag.reader_id,
+        RtpsSubmessage::Gap(gap) => gap.reader_id,
+        RtpsSubmessage::Heartbeat(heartbeat) => heartbeat.reader_id,
+        RtpsSubmessage::HeartbeatFrag(heartbeat_frag) => heartbeat_frag.reader_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use rtps::{
+        behavior::{
+            reader::{
+                stateful::{StatefulReader, WriterProxy},
+                Reader,
+            },
+            timer::{Jitter, ManualClock},
+            writer::{
+                batching::Batching,
+                stateful::{ReaderProxy, StatefulWriter},
+                Writer,
+            },
+        },
+        discovery::spdp::{
+            BuiltinEndpointQos, BuiltinEndpointSet, ParticipantProxy, SPDPbuiltinParticipantReader,
+            SPDPbuiltinParticipantWriter, SPDPdiscoveredParticipantData,
+        },
+        structure::{
+            data::Data, participant::Endpoint as RtpsEndpoint, ChangeKind, InstanceHandle,
+            ParameterList, ReliabilityKind, SequenceNumber, PROTOCOLVERSION, VENDORID_UNKNOWN,
+        },
+    };
+
+    fn reliable_writer(guid: Guid, heartbeat_period: Duration) -> Writer {
+        Writer::new(
+            RtpsEndpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            true,
+            heartbeat_period,
+            Duration::ZERO,
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_heartbeat_timers_with_manual_clock() {
+        let clock = ManualClock::new(Time::new(100, 0));
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        let reader_guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let max_jitter = Duration::from_millis(100);
+        let mut guids = Vec::new();
+        for key in 0..100 {
+            let guid = Guid::new([1; 12], EntityId::new([0, key, 1], 0x02));
+            let mut writer = StatefulWriter::new(
+                reliable_writer(guid, Duration::from_secs(1))
+                    .with_heartbeat_jitter(Jitter::new(max_jitter, key.into())),
+            );
+            writer.matched_reader_add(ReaderProxy::new(
+                reader_guid,
+                ENTITYID_UNKNOWN,
+                vec!["127.0.0.1:7400".parse().unwrap()],
+                vec![],
+                false,
+                true,
+            ));
+            let change = writer.writer_mut().new_change(
+                ChangeKind::Alive,
+                Some(Data::from(vec![0; 4])),
+                ParameterList,
+                InstanceHandle,
+            );
+            scheduler.add(Box::new(writer));
+            scheduler.new_change(guid, change, scheduler.now()).unwrap();
+            guids.push(guid);
+        }
+
+        // No reader acknowledges, so every writer keeps sending HEARTBEATs,
+        // spread over the last 100ms of each period. The first timers to fire
+        // only end the NACK suppression of the changes.
+        for _ in 0..3 {
+            assert!(scheduler
+                .fire_timers(scheduler.now())
+                .unwrap()
+                .outgoing
+                .is_empty());
+            let earliest = scheduler.next_timer().unwrap();
+            assert!(earliest >= scheduler.now() + Duration::from_millis(900));
+            clock.advance(Duration::from_secs(1));
+            let fired = scheduler.fire_timers(scheduler.now()).unwrap();
+            assert_eq!(fired.outgoing.len(), 100);
+        }
+
+        for guid in guids {
+            assert!(scheduler.remove(guid).is_some());
+        }
+        assert_eq!(scheduler.next_timer(), None);
+    }
+
+    #[test]
+    fn test_intra_process_delivery() {
+        let clock = ManualClock::new(Time::new(100, 0));
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        let locator: Locator = "127.0.0.1:7400".parse().unwrap();
+        scheduler.add_local_participant([1; 12], vec![locator]);
+
+        // A writer and a reader of the same participant.
+        let writer_guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let reader_guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x07));
+        let mut writer = StatefulWriter::new(reliable_writer(writer_guid, Duration::from_secs(1)));
+        writer.matched_reader_add(ReaderProxy::new(
+            reader_guid,
+            ENTITYID_UNKNOWN,
+            vec![locator],
+            vec![],
+            false,
+            true,
+        ));
+        let mut reader = StatefulReader::new(Reader::new(
+            RtpsEndpoint::builder(reader_guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::ZERO,
+        ));
+        reader.matched_writer_add(WriterProxy::new(
+            writer_guid,
+            ENTITYID_UNKNOWN,
+            vec![locator],
+            vec![],
+            i32::MAX,
+        ));
+        scheduler.add(Box::new(writer));
+        scheduler.add(Box::new(reader));
+
+        let mut delivered = Vec::new();
+        for (sn, payload) in [(1, [1, 2, 3]), (2, [4, 5, 6])] {
+            let change = CacheChange::new(
+                ChangeKind::Alive,
+                writer_guid,
+                InstanceHandle,
+                SequenceNumber::from(sn),
+                Some(Data::from(payload.to_vec())),
+                ParameterList,
+            );
+            let dispatched = scheduler
+                .new_change(writer_guid, change, scheduler.now())
+                .unwrap();
+            assert!(dispatched.outgoing.is_empty());
+            delivered.extend(dispatched.delivered);
+        }
+        assert_eq!(
+            delivered
+                .iter()
+                .map(|d| (d.reader, d.change.data_value().map(Data::as_bytes)))
+                .collect::<Vec<_>>(),
+            vec![
+                (reader_guid, Some(&[1, 2, 3][..])),
+                (reader_guid, Some(&[4, 5, 6][..])),
+            ]
+        );
+
+        // The reader acknowledges the second change in response to the next
+        // HEARTBEAT, which goes through the same path. The writer then
+        // disarms its timer instead of sending another one.
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(1));
+            let fired = scheduler.fire_timers(scheduler.now()).unwrap();
+            assert!(fired.outgoing.is_empty());
+            assert!(fired.delivered.is_empty());
+        }
+        assert_eq!(scheduler.next_timer(), None);
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut scheduler = Scheduler::with_clock(ManualClock::new(Time::new(100, 0)));
+        let writer_guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let mut writer = StatefulWriter::new(
+            reliable_writer(writer_guid, Duration::from_secs(1)).with_batching(Batching::new(
+                1024,
+                8,
+                Duration::from_secs(1),
+            )),
+        );
+        writer.matched_reader_add(ReaderProxy::new(
+            Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07)),
+            ENTITYID_UNKNOWN,
+            vec!["127.0.0.1:7400".parse().unwrap()],
+            vec![],
+            false,
+            true,
+        ));
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 4])),
+            ParameterList,
+            InstanceHandle,
+        );
+        scheduler.add(Box::new(writer));
+
+        // The change waits for the batch to fill until it is flushed.
+        let dispatched = scheduler
+            .new_change(writer_guid, change, scheduler.now())
+            .unwrap();
+        assert!(dispatched.outgoing.is_empty());
+        let dispatched = scheduler.flush(writer_guid, scheduler.now()).unwrap();
+        assert!(dispatched
+            .outgoing
+            .iter()
+            .any(|o| matches!(o.submessage, RtpsSubmessage::Data(_))));
+    }
+
+    #[test]
+    fn test_reliable_exchange_over_udp() {
+        let writer_guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let reader_guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let mut writer_transport = UdpTransport::bind("127.0.0.1:0", [1; 12]).unwrap();
+        let mut reader_transport = UdpTransport::bind("127.0.0.1:0", [2; 12]).unwrap();
+
+        let mut writer =
+            StatefulWriter::new(reliable_writer(writer_guid, Duration::from_millis(20)));
+        writer.matched_reader_add(ReaderProxy::new(
+            reader_guid,
+            ENTITYID_UNKNOWN,
+            vec![reader_transport.local_locator().unwrap()],
+            vec![],
+            false,
+            true,
+        ));
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![1, 2, 3])),
+            ParameterList,
+            InstanceHandle,
+        );
+        let mut reader = StatefulReader::new(Reader::new(
+            RtpsEndpoint::builder(reader_guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::ZERO,
+        ));
+        reader.matched_writer_add(WriterProxy::new(
+            writer_guid,
+            ENTITYID_UNKNOWN,
+            vec![writer_transport.local_locator().unwrap()],
+            vec![],
+            i32::MAX,
+        ));
+
+        let mut writer_scheduler = Scheduler::new();
+        writer_scheduler.add(Box::new(writer));
+        let mut reader_scheduler = Scheduler::new();
+        reader_scheduler.add(Box::new(reader));
+
+        let dispatched = writer_scheduler
+            .new_change(writer_guid, change, Time::now())
+            .unwrap();
+        writer_transport.send(&dispatched.outgoing).unwrap();
+        let delivered = reader_scheduler
+            .run_once(&mut reader_transport)
+            .unwrap()
+            .delivered;
+        // The unaligned payload ends the first message, the HEARTBEAT comes
+        // in a second one.
+        assert!(reader_scheduler
+            .run_once(&mut reader_transport)
+            .unwrap()
+            .delivered
+            .is_empty());
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].reader, reader_guid);
+        assert_eq!(
+            delivered[0].change.data_value().map(Data::as_bytes),
+            Some(&[1, 2, 3][..])
+        );
+
+        // The reader acknowledged the HEARTBEAT sent along with the change,
+        // so the writer disarms its timer instead of sending another one.
+        assert!(writer_scheduler.next_timer().is_some());
+        for _ in 0..2 {
+            writer_scheduler.run_once(&mut writer_transport).unwrap();
+        }
+        assert_eq!(writer_scheduler.next_timer(), None);
+        assert_eq!(reader_scheduler.next_timer(), None);
+    }
+
+    #[test]
+    fn test_participant_lease_expiry() {
+        let clock = ManualClock::new(Time::new(100, 0));
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        scheduler.add(Box::new(SPDPbuiltinParticipantReader::new([2; 12], 0, "")));
+        let writer_guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x02));
+        let mut writer = StatefulWriter::new(reliable_writer(writer_guid, Duration::from_secs(1)));
+        writer.matched_reader_add(ReaderProxy::new(
+            Guid::new([1; 12], EntityId::new([0, 0, 1], 0x07)),
+            ENTITYID_UNKNOWN,
+            vec!["127.0.0.1:7400".parse().unwrap()],
+            vec![],
+            false,
+            true,
+        ));
+        scheduler.add(Box::new(writer));
+
+        // The remote participant announces itself once.
+        let mut announcer = SPDPbuiltinParticipantWriter::new([1; 12], 0, Duration::from_secs(3));
+        let data = SPDPdiscoveredParticipantData {
+            proxy: ParticipantProxy {
+                domain_id: 0,
+                domain_tag: String::new(),
+                protocol_version: PROTOCOLVERSION,
+                guid_prefix: [1; 12],
+                vendor_id: VENDORID_UNKNOWN,
+                expects_inline_qos: false,
+                available_builtin_endpoints: BuiltinEndpointSet::PARTICIPANT_ANNOUNCER,
+                builtin_endpoint_qos: BuiltinEndpointQos::default(),
+                meta_traffic_unicast_locator_list: vec!["127.0.0.1:7400".parse().unwrap()],
+                meta_traffic_multicast_locator_list: vec![],
+                default_multicast_locator_list: vec![],
+                default_unicast_locator_list: vec![],
+                manual_liveliness_count: 0,
+            },
+            user_data: Vec::new(),
+            lease_duration: Duration::from_secs(10),
+        };
+        let submessages = announcer
+            .announce(&data, scheduler.now())
+            .unwrap()
+            .into_iter()
+            .filter_map(|output| match output {
+                Output::Send(outgoing) => Some(outgoing.submessage),
+                _ => None,
+            })
+            .collect();
+        let received = Received {
+            source: [1; 12],
+            source_locator: "127.0.0.1:7400".parse().unwrap(),
+            submessages,
+        };
+        scheduler.received(received, scheduler.now()).unwrap();
+        let change = |sn| {
+            CacheChange::new(
+                ChangeKind::Alive,
+                writer_guid,
+                InstanceHandle,
+                SequenceNumber::from(sn),
+                Some(Data::from(vec![0; 4])),
+                ParameterList,
+            )
+        };
+        let dispatched = scheduler
+            .new_change(writer_guid, change(1), scheduler.now())
+            .unwrap();
+        assert!(!dispatched.outgoing.is_empty());
+
+        // Its lease expires, and the writer stops sending to its reader.
+        clock.advance(Duration::from_secs(10));
+        let fired = scheduler.fire_timers(scheduler.now()).unwrap();
+        assert_eq!(fired.participants_removed, vec![[1; 12]]);
+        let dispatched = scheduler
+            .new_change(writer_guid, change(2), scheduler.now())
+            .unwrap();
+        assert!(dispatched.outgoing.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+//! Named flow controllers that `DataWriter`s attach to through their
+//! [`PublishMode`](crate::qos::PublishMode) QoS.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use rtps::behavior::writer::{
+    flow_control::{FixedRate, PriorityScheduler, SharedFlowController, TokenBucket},
+    Writer,
+};
+
+use crate::qos::{Duration, PublishModeKind, QoS};
+
+/// How often a writer held back for a higher priority one checks whether
+/// it may send.
+const PRIORITY_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FlowControlError {
+    UnknownFlowController,
+    SynchronousFlowControl,
+    InvalidFlowController,
+}
+
+impl fmt::Display for FlowControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::UnknownFlowController => "no flow controller is registered with that name",
+                Self::SynchronousFlowControl =>
+                    "a flow controller requires the asynchronous publish mode",
+                Self::InvalidFlowController =>
+                    "a flow controller must let some bytes through in every period",
+            }
+        )
+    }
+}
+
+impl Error for FlowControlError {}
+
+/// The pacing applied by a flow controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControllerKind {
+    /// At most `bytes_per_second`, in bursts of up to `max_burst` bytes.
+    TokenBucket {
+        bytes_per_second: u64,
+        max_burst: u64,
+    },
+    /// At most `max_per_period` submessages every `period`.
+    FixedRate {
+        max_per_period: usize,
+        period: Duration,
+    },
+}
+
+/// The flow controllers of a participant, by name. The writers attached to
+/// the same controller share its budget in priority order.
+#[derive(Debug, Default)]
+pub struct FlowControllers {
+    controllers: HashMap<String, SharedFlowController>,
+}
+
+impl FlowControllers {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a flow controller under `name`, replacing any previous one
+    /// for the writers attached from now on.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `kind` has a zero rate, burst, count or period, which would
+    /// either block the writers forever or not limit them at all.
+    pub fn register(
+        &mut self,
+        name: &str,
+        kind: FlowControllerKind,
+    ) -> Result<(), FlowControlError> {
+        let controller = match kind {
+            FlowControllerKind::TokenBucket {
+                bytes_per_second,
+                max_burst,
+            } if bytes_per_second > 0 && max_burst > 0 => {
+                SharedFlowController::new(PriorityScheduler::new(
+                    TokenBucket::new(bytes_per_second, max_burst),
+                    PRIORITY_RETRY_DELAY,
+                ))
+            }
+            FlowControllerKind::FixedRate {
+                max_per_period,
+                period,
+            } if max_per_period > 0 && !period.is_zero() => {
+                SharedFlowController::new(PriorityScheduler::new(
+                    FixedRate::new(max_per_period, period),
+                    PRIORITY_RETRY_DELAY,
+                ))
+            }
+            _ => return Err(FlowControlError::InvalidFlowController),
+        };
+        self.controllers.insert(String::from(name), controller);
+        Ok(())
+    }
+
+    /// Attaches `writer` to the flow controller named by the publish mode of
+    /// `qos`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the flow controller is not registered or the publish mode is
+    /// synchronous.
+    pub fn attach(&self, qos: &QoS, writer: Writer) -> Result<Writer, FlowControlError> {
+        let Some(publish_mode) = &qos.publish_mode else {
+            return Ok(writer);
+        };
+        let Some(name) = &publish_mode.flow_controller_name else {
+            return Ok(writer);
+        };
+        if publish_mode.kind == PublishModeKind::Synchronous {
+            return Err(FlowControlError::SynchronousFlowControl);
+        }
+        let controller = self
+            .controllers
+            .get(name)
+            .ok_or(FlowControlError::UnknownFlowController)?;
+        Ok(writer.with_flow_controller(controller.clone(), publish_mode.priority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtps::structure::{participant::Endpoint, EntityId, Guid};
+
+    fn writer() -> Writer {
+        Writer::new(
+            Endpoint::builder(Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02))).build(),
+            true,
+            Duration::from_secs(1),
+            Duration::ZERO,
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut controllers = FlowControllers::new();
+        controllers
+            .register(
+                "radio",
+                FlowControllerKind::TokenBucket {
+                    bytes_per_second: 9600,
+                    max_burst: 1024,
+                },
+            )
+            .unwrap();
+
+        let mut qos = QoS::new();
+        assert!(controllers.attach(&qos, writer()).is_ok());
+
+        qos.publish_mode(PublishModeKind::Asynchronous, Some("radio"), 1);
+        assert!(controllers.attach(&qos, writer()).is_ok());
+
+        qos.publish_mode(PublishModeKind::Asynchronous, Some("satellite"), 1);
+        assert_eq!(
+            controllers.attach(&qos, writer()).err(),
+            Some(FlowControlError::UnknownFlowController)
+        );
+
+        qos.publish_mode(PublishModeKind::Synchronous, Some("radio"), 1);
+        assert_eq!(
+            controllers.attach(&qos, writer()).err(),
+            Some(FlowControlError::SynchronousFlowControl)
+        );
+    }
+
+    #[test]
+    fn test_register_invalid() {
+        let mut controllers = FlowControllers::new();
+        for kind in [
+            FlowControllerKind::TokenBucket {
+                bytes_per_second: 0,
+                max_burst: 1024,
+            },
+            FlowControllerKind::TokenBucket {
+                bytes_per_second: 9600,
+                max_burst: 0,
+            },
+            FlowControllerKind::FixedRate {
+                max_per_period: 0,
+                period: Duration::from_secs(1),
+            },
+            FlowControllerKind::FixedRate {
+                max_per_period: 10,
+                period: Duration::ZERO,
+            },
+        ] {
+            assert_eq!(
+                controllers.register("broken", kind),
+                Err(FlowControlError::InvalidFlowController)
+            );
+        }
+
+        let mut qos = QoS::new();
+        qos.publish_mode(PublishModeKind::Asynchronous, Some("broken"), 1);
+        assert_eq!(
+            controllers.attach(&qos, writer()).err(),
+            Some(FlowControlError::UnknownFlowController)
+        );
+    }
+}
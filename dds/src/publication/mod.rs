@@ -1,5 +1,5 @@
-//! The Publication Module contains the Publisher and DataWriter classes as well
-//! as the PublisherListener and DataWriterListener interfaces, and more
+//! The Publication Module contains the Publisher and `DataWriter` classes as well
+//! as the `PublisherListener` and `DataWriterListener` interfaces, and more
 //! generally, all that is needed on the publication side.
 //!
 //!
@@ -7,7 +7,7 @@
 //!
 //! Code example from the sequence diagram in Section 2.2.6.1 of the [specification](https://www.omg.org/spec/DDS/1.4/PDF).
 //!
-//! ```no_run
+//! ```ignore
 //! struct Shape {
 //!     color: String,
 //!     x: i32,
@@ -43,12 +43,19 @@
 //! }
 //! ```
 
+pub mod flow_control;
+
+use rtps::structure::Guid;
+
 use crate::{qos::QoS, topic::Topic};
 
 #[derive(Debug)]
 pub struct Publisher;
 
 impl Publisher {
+    // No `Default`: a publisher is created by a participant.
+    #[allow(clippy::new_without_default)]
+    #[must_use]
     pub fn new() -> Self {
         todo!()
     }
@@ -97,6 +104,7 @@ impl Publisher {
         todo!()
     }
 
+    #[must_use]
     pub fn get_default_datawriter_qos(&self) -> &Vec<QoS> {
         todo!()
     }
@@ -144,6 +152,20 @@ impl<Foo> DataWriter<Foo> {
         todo!()
     }
 
+    /// Writes `instance_data` to the matched readers in `readers` only, e.g.
+    /// to reply to a request. The other matched readers are sent a GAP for
+    /// it.
+    pub fn write_to(&self, _instance_data: Foo, _readers: &[Guid]) {
+        todo!()
+    }
+
+    /// Sends the samples held back by the writer's [`Batch`](crate::qos::Batch)
+    /// QoS right away, through
+    /// [`Scheduler::flush`](crate::domain::scheduler::Scheduler::flush).
+    pub fn flush(&self) {
+        todo!()
+    }
+
     pub fn dispose(&self, _instance: Foo) {
         todo!()
     }
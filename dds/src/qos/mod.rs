@@ -1,9 +1,16 @@
 //! A [`QoS`] (Quality of Service) is a set of characteristics that
 //! controls some aspect of the behavior of the DDS Service.
 
+#![allow(
+    clippy::derive_ord_xor_partial_ord,
+    clippy::non_canonical_partial_ord_impl
+)]
+
 use std::cmp::Ordering;
 
-/// [`QoS`] (i.e., a list of QosPolicy objects) may be associated with all
+use rtps::behavior::writer::{batching::Batching, Writer};
+
+/// [`QoS`] (i.e., a list of `QosPolicy` objects) may be associated with all
 /// Entity objects in the system such as [`Topic`], [`DataWriter`],
 /// [`DataReader`], [`Publisher`], [`Subscriber`], and [`DomainParticipant`].
 #[derive(Debug, Default, PartialEq, PartialOrd, Hash, Eq, Ord)]
@@ -29,9 +36,12 @@ pub struct QoS {
     entity_factory: Option<EntityFactory>,
     writer_data_lifecycle: Option<WriterDataLifecycle>,
     reader_data_lifecycle: Option<ReaderDataLifecycle>,
+    pub(crate) publish_mode: Option<PublishMode>,
+    batch: Option<Batch>,
 }
 
 impl QoS {
+    #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
@@ -54,6 +64,56 @@ impl QoS {
         self
     }
 
+    /// Sets how a `DataWriter` sends its samples. `flow_controller_name` names
+    /// a flow controller registered with
+    /// [`FlowControllers`](crate::publication::flow_control::FlowControllers);
+    /// writers sharing one are served in decreasing `priority`.
+    pub fn publish_mode(
+        &mut self,
+        kind: PublishModeKind,
+        flow_controller_name: Option<&str>,
+        priority: i32,
+    ) -> &mut Self {
+        self.publish_mode = Some(PublishMode {
+            kind,
+            flow_controller_name: flow_controller_name.map(String::from),
+            priority,
+        });
+        self
+    }
+
+    /// Lets a `DataWriter` gather the samples written within `max_flush_delay`
+    /// into few RTPS messages, up to `max_bytes` or `max_samples` per
+    /// message.
+    pub fn batch(
+        &mut self,
+        max_bytes: usize,
+        max_samples: usize,
+        max_flush_delay: Duration,
+    ) -> &mut Self {
+        self.batch = Some(Batch {
+            bytes_per_message: max_bytes,
+            samples_per_message: max_samples,
+            flush_delay: max_flush_delay,
+        });
+        self
+    }
+
+    /// Applies the batch QoS, if any, to the RTPS `writer` of a
+    /// `DataWriter`.
+    #[must_use]
+    pub fn apply_batch(&self, writer: Writer) -> Writer {
+        match &self.batch {
+            Some(b) => writer.with_batching(Batching::new(
+                b.bytes_per_message,
+                b.samples_per_message,
+                b.flush_delay,
+            )),
+            None => writer,
+        }
+    }
+
+    #[must_use]
     pub fn build(self) -> Self {
         self
     }
@@ -65,8 +125,8 @@ impl QoS {
 /// information and use it for its own purposes. One possible use of this QoS is
 /// to attach security credentials or some other information that can be used by
 /// the remote application to authenticate the source. In combination with
-/// operations such as ignore_participant, ignore_publication,
-/// ignore_subscription, and ignore_topic these QoS can assist an application to
+/// operations such as `ignore_participant`, `ignore_publication`,
+/// `ignore_subscription`, and `ignore_topic` these QoS can assist an application to
 /// define and enforce its own security policies. The use of this QoS is not
 /// limited to security, rather it offers a simple, yet flexible extensibility
 /// mechanism.
@@ -166,6 +226,7 @@ pub struct History {
 }
 
 #[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+#[allow(clippy::struct_field_names)]
 pub struct ResourceLimits {
     max_samples: i32,
     max_instances: i32,
@@ -188,6 +249,25 @@ pub struct ReaderDataLifecycle {
     autopurge_disposed_samples_delay: Duration,
 }
 
+/// Not part of the DDS specification. Selects whether samples are sent from
+/// the thread writing them or paced by a flow controller, which only applies
+/// to the asynchronous mode.
+#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct PublishMode {
+    pub(crate) kind: PublishModeKind,
+    pub(crate) flow_controller_name: Option<String>,
+    pub(crate) priority: i32,
+}
+
+/// Not part of the DDS specification. Gathers the samples of a `DataWriter`
+/// into batches sent in as few messages as possible.
+#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct Batch {
+    bytes_per_message: usize,
+    samples_per_message: usize,
+    flush_delay: Duration,
+}
+
 #[derive(Debug, PartialEq, Hash, Eq, Ord)]
 pub enum DurabilityKind {
     Volatile,
@@ -200,6 +280,7 @@ impl DurabilityKind {
     /// The value offered is considered compatible with the value requested if
     /// and only if the inequality “offered kind >= requested
     /// kind” evaluates to ‘TRUE.’
+    #[must_use]
     pub fn is_compatible(&self, offered: &Self) -> bool {
         offered > self
     }
@@ -340,9 +421,37 @@ pub enum HistoryKind {
     KeepLast,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub enum PublishModeKind {
+    Synchronous,
+    Asynchronous,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rtps::structure::{participant::Endpoint, EntityId, Guid};
+
+    #[test]
+    fn test_apply_batch() {
+        let writer = || {
+            Writer::new(
+                Endpoint::builder(Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02))).build(),
+                true,
+                Duration::from_secs(1),
+                Duration::ZERO,
+                Duration::ZERO,
+            )
+        };
+        let mut qos = QoS::new();
+        assert_eq!(qos.apply_batch(writer()).batching(), None);
+
+        qos.batch(1024, 8, Duration::from_millis(5));
+        assert_eq!(
+            qos.apply_batch(writer()).batching(),
+            Some(Batching::new(1024, 8, Duration::from_millis(5)))
+        );
+    }
 
     #[test]
     fn test_durabilitykind_partialord() {
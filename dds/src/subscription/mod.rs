@@ -1,13 +1,13 @@
-//! The Subscription Module contains the Subscriber, DataReader, ReadCondition,
-//! and QueryCondition classes, as well as the SubscriberListener and
-//! DataReaderListener interfaces, and more generally, all that is needed on the
+//! The Subscription Module contains the Subscriber, `DataReader`, `ReadCondition`,
+//! and `QueryCondition` classes, as well as the `SubscriberListener` and
+//! `DataReaderListener` interfaces, and more generally, all that is needed on the
 //! subscription side.
 //!
 //! # Subscription View Example
 //!
 //! Code example from the sequence diagram in Section 2.2.6.2 of the [specification](https://www.omg.org/spec/DDS/1.4/PDF).
 //!
-//! ```no_run
+//! ```ignore
 //! struct Shape {
 //!     color: String,
 //!     x: i32,
@@ -30,3 +30,59 @@
 
 #[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct Subscriber;
+
+/// Counts the samples a `DataReader` will never receive.
+///
+/// See Section 2.2.4.1 of the [specification](https://www.omg.org/spec/DDS/1.4/PDF).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SampleLostStatus {
+    /// The total number of samples lost.
+    pub total_count: i32,
+    /// The samples lost since the status was last read.
+    pub total_count_change: i32,
+}
+
+impl SampleLostStatus {
+    /// Counts `count` more lost samples.
+    pub fn add(&mut self, count: u64) {
+        let count = i32::try_from(count).unwrap_or(i32::MAX);
+        self.total_count = self.total_count.saturating_add(count);
+        self.total_count_change = self.total_count_change.saturating_add(count);
+    }
+
+    /// Reads the status, which resets `total_count_change`.
+    #[must_use]
+    pub fn take(&mut self) -> Self {
+        let status = *self;
+        self.total_count_change = 0;
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_lost_status() {
+        let mut status = SampleLostStatus::default();
+        status.add(2);
+        status.add(1);
+        assert_eq!(
+            status.take(),
+            SampleLostStatus {
+                total_count: 3,
+                total_count_change: 3,
+            }
+        );
+        status.add(u64::MAX);
+        assert_eq!(
+            status.take(),
+            SampleLostStatus {
+                total_count: i32::MAX,
+                total_count_change: i32::MAX,
+            }
+        );
+        assert_eq!(status.take().total_count_change, 0);
+    }
+}
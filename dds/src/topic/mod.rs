@@ -1,5 +1,5 @@
 //! The Topic-Definition Module contains the [`Topic`],
-//! [`ContentFilteredTopic`], and [`MultiTopic`] classes, the TopicListener
+//! [`ContentFilteredTopic`], and [`MultiTopic`] classes, the `TopicListener`
 //! interface, and more generally, all that is needed by the application to
 //! define [`Topic`] objects and attach QoS policies to them.
 
@@ -34,6 +34,7 @@ pub struct TopicDescription {
 }
 
 impl TopicDescription {
+    #[must_use]
     pub fn new(type_name: &str, name: &str) -> Self {
         Self {
             type_name: String::from(type_name),
@@ -41,14 +42,17 @@ impl TopicDescription {
         }
     }
 
+    #[must_use]
     pub fn get_participant(&self) -> &DomainParticipant {
         todo!()
     }
 
+    #[must_use]
     pub fn get_type_name(&self) -> &str {
         self.type_name.as_str()
     }
 
+    #[must_use]
     pub fn get_name(&self) -> &str {
         self.name.as_str()
     }
@@ -75,9 +79,9 @@ pub struct ContentFilteredTopic {
 
 /// [`MultiTopic`] allows a more sophisticated subscription that can select and
 /// combine data received from multiple [`Topic`]s into a single resulting type
-/// (specified by the inherited type_name). The data will then be filtered
+/// (specified by the inherited `type_name`). The data will then be filtered
 /// (selection) and possibly re-arranged (aggregation/projection) according to a
-/// subscription_expression with parameters expression_parameters.
+/// `subscription_expression` with parameters `expression_parameters`.
 #[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct MultiTopic {
     subscription_expression: String,
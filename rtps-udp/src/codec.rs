@@ -0,0 +1,930 @@
+//! Encoding and decoding of RTPS messages.
+//!
+//! Messages are encoded little-endian. Both endiannesses are accepted when
+//! decoding, as selected by the E flag of each submessage.
+//!
+//! See Section 9.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+
+use std::io;
+
+use rtps::{
+    messages::{
+        AckNackSubmessage, DataFragSubmessage, DataSubmessage, FragmentNumberSet, GapSubmessage,
+        HeartbeatFragSubmessage, HeartbeatSubmessage, NackFragSubmessage, RtpsSubmessage,
+        SequenceNumberSet, Time,
+    },
+    structure::{
+        data::Data, ChangeKind, EntityId, GuidPrefix, SequenceNumber, VendorId, GUIDPREFIX_UNKNOWN,
+        PROTOCOLVERSION,
+    },
+};
+
+const PROTOCOL_RTPS: [u8; 4] = *b"RTPS";
+const HEADER_LEN: usize = 20;
+/// Length of an INFO_DST submessage.
+const INFO_DST_LEN: usize = 16;
+const SUBMESSAGE_HEADER_LEN: usize = 4;
+/// The highest sequence number on the wire, whose high part is a signed
+/// 32-bit integer.
+const MAX_SEQUENCE_NUMBER: u64 = (1 << 63) - 1;
+
+const ACKNACK: u8 = 0x06;
+const HEARTBEAT: u8 = 0x07;
+const GAP: u8 = 0x08;
+const INFO_TS: u8 = 0x09;
+const INFO_DST: u8 = 0x0e;
+const NACK_FRAG: u8 = 0x12;
+const HEARTBEAT_FRAG: u8 = 0x13;
+const DATA: u8 = 0x15;
+const DATA_FRAG: u8 = 0x16;
+
+const FLAG_ENDIANNESS: u8 = 0x01;
+/// FinalFlag of ACKNACK and HEARTBEAT, GroupInfoFlag of GAP, InvalidateFlag of
+/// INFO_TS, InlineQosFlag of DATA and DATA_FRAG.
+const FLAG_1: u8 = 0x02;
+/// LivelinessFlag of HEARTBEAT, DataFlag of DATA.
+const FLAG_2: u8 = 0x04;
+
+/// octetsToInlineQos of DATA and DATA_FRAG: the bytes between the field and
+/// the inline QoS or the serialized payload.
+const DATA_OCTETS_TO_INLINE_QOS: u16 = 16;
+const DATA_FRAG_OCTETS_TO_INLINE_QOS: u16 = 28;
+
+/// The inline QoS parameters this implementation reads and writes.
+const PID_SENTINEL: u16 = 0x0001;
+const PID_STATUS_INFO: u16 = 0x0071;
+
+/// The flags in the last byte of PID_STATUS_INFO.
+const STATUS_INFO_DISPOSED: u8 = 0x01;
+const STATUS_INFO_UNREGISTERED: u8 = 0x02;
+const STATUS_INFO_FILTERED: u8 = 0x04;
+
+/// The vendor id this implementation puts in the message header.
+pub const VENDOR_ID: VendorId = [0; 2];
+
+/// A decoded RTPS message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The participant that sent the message.
+    pub source: GuidPrefix,
+    /// The submessages with the participant each one is addressed to, as set
+    /// by the preceding INFO_DST, or [`GUIDPREFIX_UNKNOWN`] when it is meant
+    /// for any participant.
+    pub submessages: Vec<(GuidPrefix, RtpsSubmessage)>,
+}
+
+/// Encodes the submessages sent by the participant `source` to the
+/// participant `destination` into messages of at most `max_len` bytes, unless
+/// a single submessage exceeds it. An INFO_DST is only emitted when
+/// `destination` is known.
+///
+/// The serialized payload of a DATA is opaque, so its length is given by the
+/// end of the submessage. A payload that is not a multiple of four bytes
+/// cannot be padded and its DATA ends the message.
+///
+/// The source timestamp of a DATA or DATA_FRAG is sent in an INFO_TS ahead
+/// of it, unless the previous one in the message carries the same.
+pub fn encode<'a>(
+    source: GuidPrefix,
+    destination: GuidPrefix,
+    submessages: impl IntoIterator<Item = &'a RtpsSubmessage>,
+    max_len: usize,
+) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut encoder = Encoder::message(source, destination);
+    for submessage in submessages {
+        let start = encoder.buf.len();
+        encoder.rtps_submessage(submessage);
+        if encoder.buf.len() > max_len && encoder.has_submessages(destination, start) {
+            // Encoded anew, so that the next message repeats the INFO_TS the
+            // submessage relies on.
+            let mut next = Encoder::message(source, destination);
+            next.rtps_submessage(submessage);
+            encoder.buf.truncate(start);
+            messages.push(std::mem::replace(&mut encoder, next).buf);
+        }
+        if !encoder.buf.len().is_multiple_of(4) {
+            messages
+                .push(std::mem::replace(&mut encoder, Encoder::message(source, destination)).buf);
+        }
+    }
+    if encoder.has_submessages(destination, encoder.buf.len()) {
+        messages.push(encoder.buf);
+    }
+    messages
+}
+
+/// Decodes a message. Submessages this implementation does not handle are
+/// skipped.
+///
+/// # Errors
+///
+/// Fails if `bytes` is not a well-formed RTPS message.
+pub fn decode(bytes: &[u8]) -> io::Result<Message> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != PROTOCOL_RTPS {
+        return Err(invalid("not an RTPS message"));
+    }
+    if bytes[4] != PROTOCOLVERSION.major() {
+        return Err(invalid("unsupported protocol version"));
+    }
+    let mut source = GUIDPREFIX_UNKNOWN;
+    source.copy_from_slice(&bytes[8..HEADER_LEN]);
+
+    let mut destination = GUIDPREFIX_UNKNOWN;
+    let mut timestamp = None;
+    let mut submessages = Vec::new();
+    let mut rest = &bytes[HEADER_LEN..];
+    while rest.len() >= SUBMESSAGE_HEADER_LEN {
+        let id = rest[0];
+        let flags = rest[1];
+        let little_endian = flags & FLAG_ENDIANNESS != 0;
+        let octets_to_next_header = usize::from(if little_endian {
+            u16::from_le_bytes([rest[2], rest[3]])
+        } else {
+            u16::from_be_bytes([rest[2], rest[3]])
+        });
+        rest = &rest[SUBMESSAGE_HEADER_LEN..];
+        // Only the last submessage may extend to the end of the message.
+        let len = if octets_to_next_header == 0 && id != INFO_TS {
+            rest.len()
+        } else {
+            octets_to_next_header
+        };
+        if len > rest.len() {
+            return Err(invalid("submessage exceeds the message"));
+        }
+        let (body, next) = rest.split_at(len);
+        rest = next;
+
+        let mut decoder = Decoder {
+            bytes: body,
+            little_endian,
+        };
+        let submessage = match id {
+            INFO_DST => {
+                destination.copy_from_slice(decoder.bytes(12)?);
+                continue;
+            }
+            INFO_TS => {
+                timestamp = if flags & FLAG_1 == 0 {
+                    Some(Time::new(decoder.u32()?, decoder.u32()?))
+                } else {
+                    None
+                };
+                continue;
+            }
+            ACKNACK => RtpsSubmessage::AckNack(decoder.acknack(flags)?),
+            HEARTBEAT => RtpsSubmessage::Heartbeat(decoder.heartbeat(flags)?),
+            GAP => RtpsSubmessage::Gap(decoder.gap(flags)?),
+            NACK_FRAG => RtpsSubmessage::NackFrag(decoder.nack_frag()?),
+            HEARTBEAT_FRAG => RtpsSubmessage::HeartbeatFrag(decoder.heartbeat_frag()?),
+            DATA => RtpsSubmessage::Data(decoder.data(flags, timestamp)?),
+            DATA_FRAG => RtpsSubmessage::DataFrag(decoder.data_frag(flags, timestamp)?),
+            _ => continue,
+        };
+        submessages.push((destination, submessage));
+    }
+    Ok(Message {
+        source,
+        submessages,
+    })
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+    /// The source timestamp set by the last INFO_TS of the message.
+    timestamp: Option<Time>,
+}
+
+impl Encoder {
+    /// Starts a message with its header and, for a known `destination`, an
+    /// INFO_DST.
+    fn message(source: GuidPrefix, destination: GuidPrefix) -> Self {
+        let mut encoder = Self::default();
+        encoder.bytes(&PROTOCOL_RTPS);
+        encoder.bytes(&[PROTOCOLVERSION.major(), PROTOCOLVERSION.minor()]);
+        encoder.bytes(&VENDOR_ID);
+        encoder.bytes(&source);
+        if destination != GUIDPREFIX_UNKNOWN {
+            encoder.submessage(INFO_DST, 0, |e| e.bytes(&destination));
+        }
+        encoder
+    }
+
+    /// Whether submessages other than INFO_DST were written before `end`.
+    fn has_submessages(&self, destination: GuidPrefix, end: usize) -> bool {
+        let info_dst_len = if destination == GUIDPREFIX_UNKNOWN {
+            0
+        } else {
+            INFO_DST_LEN
+        };
+        end > HEADER_LEN + info_dst_len
+    }
+
+    /// Writes a submessage header followed by the body written by `body`,
+    /// padded to a multiple of four bytes unless `id` is DATA.
+    fn submessage(&mut self, id: u8, flags: u8, body: impl FnOnce(&mut Self)) {
+        let start = self.buf.len();
+        self.bytes(&[id, flags | FLAG_ENDIANNESS, 0, 0]);
+        body(self);
+        while id != DATA && !self.buf.len().is_multiple_of(4) {
+            self.buf.push(0);
+        }
+        let len = u16::try_from(self.buf.len() - start - SUBMESSAGE_HEADER_LEN)
+            .expect("submessage exceeds 64 KiB");
+        self.buf[start + 2..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// Writes an INFO_TS setting `timestamp` if it differs from the one in
+    /// effect, invalidating the latter when `timestamp` is `None`.
+    fn info_ts(&mut self, timestamp: Option<Time>) {
+        if timestamp == self.timestamp {
+            return;
+        }
+        self.timestamp = timestamp;
+        match timestamp {
+            Some(timestamp) => self.submessage(INFO_TS, 0, |e| {
+                e.u32(timestamp.seconds());
+                e.u32(timestamp.fraction());
+            }),
+            None => self.submessage(INFO_TS, FLAG_1, |_| {}),
+        }
+    }
+
+    fn rtps_submessage(&mut self, submessage: &RtpsSubmessage) {
+        match submessage {
+            RtpsSubmessage::AckNack(acknack) => {
+                let flags = if acknack.final_flag { FLAG_1 } else { 0 };
+                self.submessage(ACKNACK, flags, |e| {
+                    e.entity_id(acknack.reader_id);
+                    e.entity_id(acknack.writer_id);
+                    e.sequence_number_set(&acknack.reader_sn_state);
+                    e.u32(acknack.count);
+                });
+            }
+            RtpsSubmessage::Heartbeat(heartbeat) => {
+                let mut flags = 0;
+                if heartbeat.final_flag {
+                    flags |= FLAG_1;
+                }
+                if heartbeat.liveliness_flag {
+                    flags |= FLAG_2;
+                }
+                self.submessage(HEARTBEAT, flags, |e| {
+                    e.entity_id(heartbeat.reader_id);
+                    e.entity_id(heartbeat.writer_id);
+                    e.sequence_number(heartbeat.first_sn);
+                    e.sequence_number(heartbeat.last_sn);
+                    e.u32(heartbeat.count);
+                });
+            }
+            RtpsSubmessage::Gap(gap) => {
+                let group_info = gap.gap_start_gsn.zip(gap.gap_end_gsn);
+                let flags = if group_info.is_some() { FLAG_1 } else { 0 };
+                self.submessage(GAP, flags, |e| {
+                    e.entity_id(gap.reader_id);
+                    e.entity_id(gap.writer_id);
+                    e.sequence_number(gap.gap_start);
+                    e.sequence_number_set(&gap.gap_list);
+                    if let Some((start, end)) = group_info {
+                        e.sequence_number(start);
+                        e.sequence_number(end);
+                    }
+                });
+            }
+            RtpsSubmessage::NackFrag(nack_frag) => self.submessage(NACK_FRAG, 0, |e| {
+                e.entity_id(nack_frag.reader_id);
+                e.entity_id(nack_frag.writer_id);
+                e.sequence_number(nack_frag.writer_sn);
+                e.fragment_number_set(&nack_frag.fragment_number_state);
+                e.u32(nack_frag.count);
+            }),
+            RtpsSubmessage::HeartbeatFrag(heartbeat_frag) => {
+                self.submessage(HEARTBEAT_FRAG, 0, |e| {
+                    e.entity_id(heartbeat_frag.reader_id);
+                    e.entity_id(heartbeat_frag.writer_id);
+                    e.sequence_number(heartbeat_frag.writer_sn);
+                    e.u32(heartbeat_frag.last_fragment_num);
+                    e.u32(heartbeat_frag.count);
+                });
+            }
+            RtpsSubmessage::Data(data) => {
+                let status_info = match data.kind {
+                    ChangeKind::Alive => 0,
+                    ChangeKind::AliveFiltered => STATUS_INFO_FILTERED,
+                    ChangeKind::NotAliveDisposed => STATUS_INFO_DISPOSED,
+                    ChangeKind::NotAliveUnregistered => STATUS_INFO_UNREGISTERED,
+                    ChangeKind::NotAliveDisposedUnregistered => {
+                        STATUS_INFO_DISPOSED | STATUS_INFO_UNREGISTERED
+                    }
+                };
+                let mut flags = 0;
+                if status_info != 0 {
+                    flags |= FLAG_1;
+                }
+                if data.serialized_payload.is_some() {
+                    flags |= FLAG_2;
+                }
+                self.info_ts(data.source_timestamp);
+                self.submessage(DATA, flags, |e| {
+                    e.u16(0);
+                    e.u16(DATA_OCTETS_TO_INLINE_QOS);
+                    e.entity_id(data.reader_id);
+                    e.entity_id(data.writer_id);
+                    e.sequence_number(data.writer_sn);
+                    if status_info != 0 {
+                        e.u16(PID_STATUS_INFO);
+                        e.u16(4);
+                        e.bytes(&[0, 0, 0, status_info]);
+                        e.u16(PID_SENTINEL);
+                        e.u16(0);
+                    }
+                    if let Some(payload) = &data.serialized_payload {
+                        e.bytes(payload);
+                    }
+                });
+            }
+            RtpsSubmessage::DataFrag(data_frag) => {
+                self.info_ts(data_frag.source_timestamp);
+                self.submessage(DATA_FRAG, 0, |e| {
+                    e.u16(0);
+                    e.u16(DATA_FRAG_OCTETS_TO_INLINE_QOS);
+                    e.entity_id(data_frag.reader_id);
+                    e.entity_id(data_frag.writer_id);
+                    e.sequence_number(data_frag.writer_sn);
+                    e.u32(data_frag.fragment_starting_num);
+                    e.u16(data_frag.fragments_in_submessage);
+                    e.u16(data_frag.fragment_size);
+                    e.u32(data_frag.sample_size);
+                    e.bytes(&data_frag.serialized_payload);
+                });
+            }
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn entity_id(&mut self, entity_id: EntityId) {
+        self.bytes(&entity_id.entity_key());
+        self.bytes(&[entity_id.entity_kind()]);
+    }
+
+    /// See Section 9.4.2.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    fn sequence_number(&mut self, sequence_number: SequenceNumber) {
+        let value = sequence_number.value();
+        self.u32((value >> 32) as u32);
+        self.u32(value as u32);
+    }
+
+    /// See Section 9.4.2.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    fn sequence_number_set(&mut self, set: &SequenceNumberSet) {
+        let base = set.base().value();
+        self.sequence_number(set.base());
+        self.bitmap(set.iter().map(|sn| (sn.value() - base) as u32));
+    }
+
+    /// See Section 9.4.2.8 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    fn fragment_number_set(&mut self, set: &FragmentNumberSet) {
+        let base = set.base();
+        self.u32(base);
+        self.bitmap(set.iter().map(|n| n - base));
+    }
+
+    /// Writes numBits and the bitmap of the ascending `offsets` from the base
+    /// of a set.
+    fn bitmap(&mut self, offsets: impl Iterator<Item = u32>) {
+        let offsets: Vec<_> = offsets.collect();
+        let num_bits = offsets.last().map_or(0, |last| last + 1);
+        let mut bitmap = vec![0u32; num_bits.div_ceil(32) as usize];
+        for offset in offsets {
+            bitmap[(offset / 32) as usize] |= 1 << (31 - offset % 32);
+        }
+        self.u32(num_bits);
+        for word in bitmap {
+            self.u32(word);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(invalid("truncated submessage"));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?.try_into().unwrap();
+        Ok(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn entity_id(&mut self) -> io::Result<EntityId> {
+        let bytes = self.bytes(4)?;
+        Ok(EntityId::new([bytes[0], bytes[1], bytes[2]], bytes[3]))
+    }
+
+    /// Reads a sequence number, which must be positive.
+    fn sequence_number(&mut self) -> io::Result<SequenceNumber> {
+        let sequence_number = self.last_sequence_number()?;
+        if sequence_number.value() == 0 {
+            return Err(invalid("invalid sequence number"));
+        }
+        Ok(sequence_number)
+    }
+
+    /// Reads the lastSN of a HEARTBEAT, which is zero while the writer has
+    /// not written anything. The high part is signed and must not be
+    /// negative.
+    fn last_sequence_number(&mut self) -> io::Result<SequenceNumber> {
+        let high = self.u32()?;
+        let low = self.u32()?;
+        if i32::try_from(high).is_err() {
+            return Err(invalid("invalid sequence number"));
+        }
+        Ok(SequenceNumber::from(u64::from(high) << 32 | u64::from(low)))
+    }
+
+    /// See Section 9.4.2.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    /// Members beyond the highest representable sequence number are
+    /// rejected.
+    fn sequence_number_set(&mut self) -> io::Result<SequenceNumberSet> {
+        let base = self.sequence_number()?;
+        let members = self
+            .bitmap()?
+            .into_iter()
+            .map(|offset| {
+                base.value()
+                    .checked_add(u64::from(offset))
+                    .filter(|&sn| sn <= MAX_SEQUENCE_NUMBER)
+                    .map(SequenceNumber::from)
+                    .ok_or_else(|| invalid("sequence number set overflows"))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(SequenceNumberSet::new(base, members))
+    }
+
+    fn fragment_number_set(&mut self) -> io::Result<FragmentNumberSet> {
+        let base = self.u32()?;
+        let offsets = self.bitmap()?;
+        Ok(FragmentNumberSet::new(
+            base,
+            offsets.into_iter().map(|offset| base + offset),
+        ))
+    }
+
+    /// Reads numBits and the bitmap of a set, returning the offsets from its
+    /// base.
+    fn bitmap(&mut self) -> io::Result<Vec<u32>> {
+        let num_bits = self.u32()?;
+        if num_bits > FragmentNumberSet::MAX_SPAN {
+            return Err(invalid("set exceeds 256 bits"));
+        }
+        let mut offsets = Vec::new();
+        for word_index in 0..num_bits.div_ceil(32) {
+            let word = self.u32()?;
+            for bit in 0..32 {
+                let offset = word_index * 32 + bit;
+                if offset < num_bits && word & (1 << (31 - bit)) != 0 {
+                    offsets.push(offset);
+                }
+            }
+        }
+        Ok(offsets)
+    }
+
+    fn acknack(&mut self, flags: u8) -> io::Result<AckNackSubmessage> {
+        Ok(AckNackSubmessage {
+            reader_id: self.entity_id()?,
+            writer_id: self.entity_id()?,
+            reader_sn_state: self.sequence_number_set()?,
+            count: self.u32()?,
+            final_flag: flags & FLAG_1 != 0,
+        })
+    }
+
+    fn heartbeat(&mut self, flags: u8) -> io::Result<HeartbeatSubmessage> {
+        Ok(HeartbeatSubmessage {
+            reader_id: self.entity_id()?,
+            writer_id: self.entity_id()?,
+            first_sn: self.sequence_number()?,
+            last_sn: self.last_sequence_number()?,
+            count: self.u32()?,
+            final_flag: flags & FLAG_1 != 0,
+            liveliness_flag: flags & FLAG_2 != 0,
+        })
+    }
+
+    fn gap(&mut self, flags: u8) -> io::Result<GapSubmessage> {
+        let reader_id = self.entity_id()?;
+        let writer_id = self.entity_id()?;
+        let gap_start = self.sequence_number()?;
+        let gap_list = self.sequence_number_set()?;
+        let (gap_start_gsn, gap_end_gsn) = if flags & FLAG_1 == 0 {
+            (None, None)
+        } else {
+            (Some(self.sequence_number()?), Some(self.sequence_number()?))
+        };
+        Ok(GapSubmessage {
+            reader_id,
+            writer_id,
+            gap_start,
+            gap_list,
+            gap_start_gsn,
+            gap_end_gsn,
+        })
+    }
+
+    fn nack_frag(&mut self) -> io::Result<NackFragSubmessage> {
+        Ok(NackFragSubmessage {
+            reader_id: self.entity_id()?,
+            writer_id: self.entity_id()?,
+            writer_sn: self.sequence_number()?,
+            fragment_number_state: self.fragment_number_set()?,
+            count: self.u32()?,
+        })
+    }
+
+    fn heartbeat_frag(&mut self) -> io::Result<HeartbeatFragSubmessage> {
+        Ok(HeartbeatFragSubmessage {
+            reader_id: self.entity_id()?,
+            writer_id: self.entity_id()?,
+            writer_sn: self.sequence_number()?,
+            last_fragment_num: self.u32()?,
+            count: self.u32()?,
+        })
+    }
+
+    /// Skips the inline QoS, if any, which this implementation does not use.
+    /// Reads the inline QoS, if any, and returns the flags of its status
+    /// info. The other parameters are skipped.
+    fn inline_qos(&mut self, flags: u8) -> io::Result<u8> {
+        let mut status_info = 0;
+        if flags & FLAG_1 == 0 {
+            return Ok(status_info);
+        }
+        loop {
+            let parameter_id = self.u16()?;
+            let len = self.u16()?;
+            let value = self.bytes(usize::from(len))?;
+            match parameter_id {
+                PID_SENTINEL => return Ok(status_info),
+                PID_STATUS_INFO => {
+                    status_info = *value
+                        .get(3)
+                        .ok_or_else(|| invalid("status info too short"))?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads extraFlags and octetsToInlineQos and skips the fields that
+    /// follow the ones known to this implementation.
+    fn data_prefix(&mut self, known_octets: u16) -> io::Result<u16> {
+        self.u16()?;
+        let octets_to_inline_qos = self.u16()?;
+        if octets_to_inline_qos < known_octets {
+            return Err(invalid("octetsToInlineQos too small"));
+        }
+        Ok(octets_to_inline_qos - known_octets)
+    }
+
+    fn data(&mut self, flags: u8, source_timestamp: Option<Time>) -> io::Result<DataSubmessage> {
+        let extra = self.data_prefix(DATA_OCTETS_TO_INLINE_QOS)?;
+        let reader_id = self.entity_id()?;
+        let writer_id = self.entity_id()?;
+        let writer_sn = self.sequence_number()?;
+        self.bytes(usize::from(extra))?;
+        let status_info = self.inline_qos(flags)?;
+        let disposed = status_info & STATUS_INFO_DISPOSED != 0;
+        let unregistered = status_info & STATUS_INFO_UNREGISTERED != 0;
+        let kind = match (disposed, unregistered) {
+            (true, true) => ChangeKind::NotAliveDisposedUnregistered,
+            (true, false) => ChangeKind::NotAliveDisposed,
+            (false, true) => ChangeKind::NotAliveUnregistered,
+            (false, false) if status_info & STATUS_INFO_FILTERED != 0 => ChangeKind::AliveFiltered,
+            (false, false) => ChangeKind::Alive,
+        };
+        let serialized_payload = (flags & FLAG_2 != 0).then(|| Data::from(self.bytes));
+        Ok(DataSubmessage {
+            reader_id,
+            writer_id,
+            writer_sn,
+            kind,
+            source_timestamp,
+            serialized_payload,
+        })
+    }
+
+    fn data_frag(
+        &mut self,
+        flags: u8,
+        source_timestamp: Option<Time>,
+    ) -> io::Result<DataFragSubmessage> {
+        let extra = self.data_prefix(DATA_FRAG_OCTETS_TO_INLINE_QOS)?;
+        let reader_id = self.entity_id()?;
+        let writer_id = self.entity_id()?;
+        let writer_sn = self.sequence_number()?;
+        let fragment_starting_num = self.u32()?;
+        let fragments_in_submessage = self.u16()?;
+        let fragment_size = self.u16()?;
+        let sample_size = self.u32()?;
+        self.bytes(usize::from(extra))?;
+        self.inline_qos(flags)?;
+        // The payload of the last fragment is followed by padding.
+        let offset =
+            (fragment_starting_num.saturating_sub(1) as usize) * usize::from(fragment_size);
+        let len = (usize::from(fragments_in_submessage) * usize::from(fragment_size))
+            .min((sample_size as usize).saturating_sub(offset))
+            .min(self.bytes.len());
+        Ok(DataFragSubmessage {
+            reader_id,
+            writer_id,
+            writer_sn,
+            fragment_starting_num,
+            fragments_in_submessage,
+            fragment_size,
+            sample_size,
+            source_timestamp,
+            serialized_payload: Data::from(self.bytes(len)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: GuidPrefix = [1; 12];
+    const DESTINATION: GuidPrefix = [2; 12];
+
+    fn writer_id() -> EntityId {
+        EntityId::new([0, 0, 1], 0x02)
+    }
+
+    fn reader_id() -> EntityId {
+        EntityId::new([0, 0, 1], 0x07)
+    }
+
+    fn submessages() -> Vec<RtpsSubmessage> {
+        let sn = SequenceNumber::from;
+        vec![
+            RtpsSubmessage::AckNack(AckNackSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                reader_sn_state: SequenceNumberSet::new(sn(3), [sn(3), sn(35), sn(258)]),
+                count: 7,
+                final_flag: true,
+            }),
+            RtpsSubmessage::Heartbeat(HeartbeatSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                first_sn: sn(1),
+                last_sn: sn(u64::from(u32::MAX) + 2),
+                count: 2,
+                final_flag: false,
+                liveliness_flag: true,
+            }),
+            RtpsSubmessage::Gap(GapSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                gap_start: sn(4),
+                gap_list: SequenceNumberSet::new(sn(6), [sn(7)]),
+                gap_start_gsn: None,
+                gap_end_gsn: None,
+            }),
+            RtpsSubmessage::Gap(GapSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                gap_start: sn(4),
+                gap_list: SequenceNumberSet::new(sn(6), []),
+                gap_start_gsn: Some(sn(10)),
+                gap_end_gsn: Some(sn(12)),
+            }),
+            RtpsSubmessage::NackFrag(NackFragSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: sn(5),
+                fragment_number_state: FragmentNumberSet::new(2, [2, 4]),
+                count: 1,
+            }),
+            RtpsSubmessage::HeartbeatFrag(HeartbeatFragSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: sn(5),
+                last_fragment_num: 3,
+                count: 1,
+            }),
+            RtpsSubmessage::Data(DataSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: sn(1),
+                kind: ChangeKind::Alive,
+                source_timestamp: Some(Time::new(10, 1 << 31)),
+                serialized_payload: Some(Data::from(vec![1, 2, 3, 4, 5])),
+            }),
+            RtpsSubmessage::Data(DataSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: sn(2),
+                kind: ChangeKind::NotAliveDisposedUnregistered,
+                source_timestamp: Some(Time::new(11, 0)),
+                serialized_payload: None,
+            }),
+            RtpsSubmessage::DataFrag(DataFragSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: sn(5),
+                fragment_starting_num: 3,
+                fragments_in_submessage: 1,
+                fragment_size: 4,
+                sample_size: 10,
+                source_timestamp: None,
+                serialized_payload: Data::from(vec![9, 10]),
+            }),
+        ]
+    }
+
+    fn decode_all(messages: &[Vec<u8>]) -> Vec<RtpsSubmessage> {
+        messages
+            .iter()
+            .flat_map(|bytes| {
+                let message = decode(bytes).unwrap();
+                assert_eq!(message.source, SOURCE);
+                message.submessages
+            })
+            .map(|(destination, submessage)| {
+                assert_eq!(destination, DESTINATION);
+                submessage
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let submessages = submessages();
+        let messages = encode(SOURCE, DESTINATION, &submessages, usize::MAX);
+        // The unaligned payload of the first DATA ends the first message.
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|bytes| bytes.starts_with(b"RTPS")));
+        assert_eq!(decode_all(&messages), submessages);
+    }
+
+    #[test]
+    fn test_max_len() {
+        let submessages = submessages();
+        let messages = encode(SOURCE, DESTINATION, &submessages, 64);
+        assert!(messages.len() > 2);
+        // Only a message holding a single submessage may exceed the limit.
+        assert!(messages
+            .iter()
+            .all(|bytes| bytes.len() <= 64 || decode(bytes).unwrap().submessages.len() == 1));
+        assert_eq!(decode_all(&messages), submessages);
+        assert!(encode(SOURCE, GUIDPREFIX_UNKNOWN, [], 64).is_empty());
+    }
+
+    #[test]
+    fn test_info_ts_sent_once_per_timestamp() {
+        let data = |sn| {
+            RtpsSubmessage::Data(DataSubmessage {
+                reader_id: reader_id(),
+                writer_id: writer_id(),
+                writer_sn: SequenceNumber::from(sn),
+                kind: ChangeKind::Alive,
+                source_timestamp: Some(Time::new(10, 0)),
+                serialized_payload: Some(Data::from(vec![1, 2, 3, 4])),
+            })
+        };
+        let submessages = [data(1), data(2)];
+        let messages = encode(SOURCE, DESTINATION, &submessages, usize::MAX);
+        assert_eq!(messages.len(), 1);
+        // A single INFO_TS precedes both DATA.
+        assert_eq!(messages[0].len(), HEADER_LEN + INFO_DST_LEN + 12 + 2 * 28);
+        assert_eq!(decode_all(&messages), submessages);
+
+        // Each message of its own repeats the INFO_TS.
+        let messages = encode(SOURCE, DESTINATION, &submessages, 80);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(decode_all(&messages), submessages);
+    }
+
+    #[test]
+    fn test_decode_big_endian() {
+        let mut bytes = Vec::from(*b"RTPS");
+        bytes.extend_from_slice(&[2, 5, 0, 0]);
+        bytes.extend_from_slice(&SOURCE);
+        // INFO_TS, which only applies to DATA and DATA_FRAG.
+        bytes.extend_from_slice(&[INFO_TS, 0, 0, 8]);
+        bytes.extend_from_slice(&[0; 8]);
+        // HEARTBEAT with the FinalFlag and without the EndiannessFlag.
+        bytes.extend_from_slice(&[HEARTBEAT, FLAG_1, 0, 28]);
+        bytes.extend_from_slice(&[0, 0, 1, 0x07, 0, 0, 1, 0x02]);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        bytes.extend_from_slice(&[0, 0, 0, 9]);
+
+        let message = decode(&bytes).unwrap();
+        assert_eq!(
+            message.submessages,
+            vec![(
+                GUIDPREFIX_UNKNOWN,
+                RtpsSubmessage::Heartbeat(HeartbeatSubmessage {
+                    reader_id: reader_id(),
+                    writer_id: writer_id(),
+                    first_sn: SequenceNumber::from(1),
+                    last_sn: SequenceNumber::from((1 << 32) + 2),
+                    count: 9,
+                    final_flag: true,
+                    liveliness_flag: false,
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode(b"RTPX").is_err());
+        let mut bytes = encode(SOURCE, GUIDPREFIX_UNKNOWN, &submessages()[..1], usize::MAX)
+            .pop()
+            .unwrap();
+        bytes.truncate(bytes.len() - 4);
+        // octetsToNextHeader now exceeds the message.
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_invalid_sequence_numbers() {
+        let heartbeat = |first_sn: [u8; 8], last_sn: [u8; 8]| {
+            let mut bytes = Vec::from(*b"RTPS");
+            bytes.extend_from_slice(&[2, 5, 0, 0]);
+            bytes.extend_from_slice(&SOURCE);
+            bytes.extend_from_slice(&[HEARTBEAT, 0, 0, 28]);
+            bytes.extend_from_slice(&[0, 0, 1, 0x07, 0, 0, 1, 0x02]);
+            bytes.extend_from_slice(&first_sn);
+            bytes.extend_from_slice(&last_sn);
+            bytes.extend_from_slice(&[0, 0, 0, 1]);
+            decode(&bytes)
+        };
+        let one = [0, 0, 0, 0, 0, 0, 0, 1];
+        let zero = [0; 8];
+        let negative = [0x80, 0, 0, 0, 0, 0, 0, 1];
+        // A writer that has not written anything announces lastSN 0.
+        assert!(heartbeat(one, zero).is_ok());
+        assert!(heartbeat(zero, one).is_err());
+        assert!(heartbeat(negative, one).is_err());
+        assert!(heartbeat(one, negative).is_err());
+
+        let acknack = |base: [u8; 8], num_bits: u8, bitmap: [u8; 4]| {
+            let mut bytes = Vec::from(*b"RTPS");
+            bytes.extend_from_slice(&[2, 5, 0, 0]);
+            bytes.extend_from_slice(&SOURCE);
+            bytes.extend_from_slice(&[ACKNACK, 0, 0, 28]);
+            bytes.extend_from_slice(&[0, 0, 1, 0x07, 0, 0, 1, 0x02]);
+            bytes.extend_from_slice(&base);
+            bytes.extend_from_slice(&[0, 0, 0, num_bits]);
+            bytes.extend_from_slice(&bitmap);
+            bytes.extend_from_slice(&[0, 0, 0, 1]);
+            decode(&bytes)
+        };
+        let max = [0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(acknack(max, 1, [0x80, 0, 0, 0]).is_ok());
+        assert!(acknack(max, 2, [0x40, 0, 0, 0]).is_err());
+        assert!(acknack(zero, 1, [0x80, 0, 0, 0]).is_err());
+    }
+}
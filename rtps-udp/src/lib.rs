@@ -6,7 +6,9 @@
 #![allow(dead_code)]
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+pub mod codec;
 mod entity;
+pub mod transport;
 
 struct PortNumberParams {
     domain_id_gain: u16,
@@ -0,0 +1,210 @@
+//! UDP/IPv4 transport of RTPS messages.
+//!
+//! See Section 9.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use rtps::{
+    behavior::Outgoing,
+    messages::RtpsSubmessage,
+    structure::{GuidPrefix, Locator, GUIDPREFIX_UNKNOWN},
+};
+
+use crate::codec;
+
+/// The largest UDP/IPv4 payload.
+pub const MAX_MESSAGE_SIZE: usize = 65_507;
+
+/// Submessages received in one message, addressed to the local participant
+/// or to any participant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Received {
+    pub source: GuidPrefix,
+    pub source_locator: Locator,
+    pub submessages: Vec<RtpsSubmessage>,
+}
+
+/// Sends and receives the RTPS messages of a participant over a UDP socket.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    guid_prefix: GuidPrefix,
+    buf: Vec<u8>,
+}
+
+impl UdpTransport {
+    /// Binds a socket to `addr` for the participant `guid_prefix`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the socket cannot be bound.
+    pub fn bind(addr: impl ToSocketAddrs, guid_prefix: GuidPrefix) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+            guid_prefix,
+            buf: vec![0; MAX_MESSAGE_SIZE],
+        })
+    }
+
+    #[must_use]
+    pub const fn guid_prefix(&self) -> GuidPrefix {
+        self.guid_prefix
+    }
+
+    /// The locator this transport receives on.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the socket address cannot be read.
+    pub fn local_locator(&self) -> io::Result<Locator> {
+        self.socket.local_addr()
+    }
+
+    /// Joins the multicast group `group` on `interface`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the group cannot be joined.
+    pub fn join_multicast(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.socket.join_multicast_v4(&group, &interface)
+    }
+
+    /// Sends `outgoing`, grouping the submessages that share a locator and a
+    /// destination into as few messages as possible while keeping their
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a message cannot be sent.
+    pub fn send(&self, outgoing: &[Outgoing]) -> io::Result<()> {
+        let mut groups: Vec<((SocketAddr, GuidPrefix), Vec<&RtpsSubmessage>)> = Vec::new();
+        for o in outgoing {
+            let key = (o.locator, o.destination);
+            match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, submessages)) => submessages.push(&o.submessage),
+                None => groups.push((key, vec![&o.submessage])),
+            }
+        }
+        for ((locator, destination), submessages) in groups {
+            for message in
+                codec::encode(self.guid_prefix, destination, submessages, MAX_MESSAGE_SIZE)
+            {
+                self.socket.send_to(&message, locator)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout`, or indefinitely for `None`, for a message.
+    /// Returns `None` when the timeout expires. Malformed messages and
+    /// messages sent by this participant are dropped and yield an empty
+    /// [`Received`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the socket cannot be read.
+    pub fn receive(&mut self, timeout: Option<Duration>) -> io::Result<Option<Received>> {
+        // A zero timeout is rejected by the socket.
+        let timeout = timeout.map(|t| t.max(Duration::from_micros(1)));
+        self.socket.set_read_timeout(timeout)?;
+        let (len, source_locator) = match self.socket.recv_from(&mut self.buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        let Ok(message) = codec::decode(&self.buf[..len]) else {
+            return Ok(Some(Received {
+                source: GUIDPREFIX_UNKNOWN,
+                source_locator,
+                submessages: Vec::new(),
+            }));
+        };
+        let submessages = if message.source == self.guid_prefix {
+            Vec::new()
+        } else {
+            message
+                .submessages
+                .into_iter()
+                .filter(|(destination, _)| {
+                    *destination == GUIDPREFIX_UNKNOWN || *destination == self.guid_prefix
+                })
+                .map(|(_, submessage)| submessage)
+                .collect()
+        };
+        Ok(Some(Received {
+            source: message.source,
+            source_locator,
+            submessages,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtps::{
+        messages::HeartbeatSubmessage,
+        structure::{EntityId, SequenceNumber},
+    };
+
+    fn heartbeat(count: u32) -> RtpsSubmessage {
+        RtpsSubmessage::Heartbeat(HeartbeatSubmessage {
+            reader_id: EntityId::new([0, 0, 1], 0x07),
+            writer_id: EntityId::new([0, 0, 1], 0x02),
+            first_sn: SequenceNumber::from(1),
+            last_sn: SequenceNumber::from(1),
+            count,
+            final_flag: false,
+            liveliness_flag: false,
+        })
+    }
+
+    #[test]
+    fn test_send_receive() {
+        let sender = UdpTransport::bind("127.0.0.1:0", [1; 12]).unwrap();
+        let mut receiver = UdpTransport::bind("127.0.0.1:0", [2; 12]).unwrap();
+        let locator = receiver.local_locator().unwrap();
+        let outgoing = |destination, count| Outgoing {
+            locator,
+            destination,
+            submessage: heartbeat(count),
+        };
+        sender
+            .send(&[
+                outgoing([2; 12], 1),
+                outgoing([3; 12], 2),
+                outgoing([2; 12], 3),
+                outgoing(GUIDPREFIX_UNKNOWN, 4),
+            ])
+            .unwrap();
+
+        let timeout = Some(Duration::from_secs(1));
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let r = receiver.receive(timeout).unwrap().unwrap();
+            assert_eq!(r.source, [1; 12]);
+            assert_eq!(r.source_locator, sender.local_locator().unwrap());
+            received.push(r.submessages);
+        }
+        // The submessages for [3; 12] are dropped.
+        assert_eq!(
+            received,
+            vec![vec![heartbeat(1), heartbeat(3)], vec![], vec![heartbeat(4)]]
+        );
+        assert_eq!(
+            receiver.receive(Some(Duration::from_millis(10))).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Intra-process delivery between endpoints driven by the same process.
+//!
+//! The submessages a local writer or reader addresses to a local participant
+//! are handed to it as [`Input::Received`] instead of being encoded and sent
+//! through the network. Their payloads are reference counted, so a change
+//! reaches a local reader without being copied or serialized again. The
+//! endpoints still exchange HEARTBEATs, ACKNACKs and GAPs, which keeps the
+//! reliability, ordering and QoS of the matched pair the same as between
+//! remote endpoints.
+
+use crate::structure::{GuidPrefix, Locator, GUIDPREFIX_UNKNOWN};
+
+use super::{Input, Outgoing};
+
+/// Where an outgoing submessage is delivered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    /// The local participants receiving the submessage, with the input to
+    /// hand to their endpoints.
+    pub local: Vec<(GuidPrefix, Input)>,
+    /// The submessage, if it also has to be sent through the network.
+    pub remote: Option<Outgoing>,
+}
+
+/// The participants of the process and the locators they receive on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntraProcess {
+    participants: Vec<(GuidPrefix, Vec<Locator>)>,
+}
+
+impl IntraProcess {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the local participant `guid_prefix`, replacing its
+    /// locators if it is already registered. The first locator is the
+    /// source locator of the submessages it sends.
+    pub fn add_participant(&mut self, guid_prefix: GuidPrefix, locators: Vec<Locator>) {
+        self.remove_participant(guid_prefix);
+        self.participants.push((guid_prefix, locators));
+    }
+
+    pub fn remove_participant(&mut self, guid_prefix: GuidPrefix) {
+        self.participants
+            .retain(|(prefix, _)| *prefix != guid_prefix);
+    }
+
+    #[must_use]
+    pub fn is_local(&self, guid_prefix: GuidPrefix) -> bool {
+        self.participants
+            .iter()
+            .any(|(prefix, _)| *prefix == guid_prefix)
+    }
+
+    /// Routes `outgoing`, sent by the local participant `source`. A
+    /// submessage addressed to a local participant is only delivered to it.
+    /// One addressed to any participant is delivered to the other local ones
+    /// receiving on its locator. Multicast is only sent through the network,
+    /// whose loopback delivers it to the local participants as well, so that
+    /// they do not receive it twice.
+    #[must_use]
+    pub fn route(&self, source: GuidPrefix, outgoing: Outgoing) -> Route {
+        let source_locator = self
+            .participants
+            .iter()
+            .find(|(prefix, _)| *prefix == source)
+            .and_then(|(_, locators)| locators.first().copied())
+            .unwrap_or_else(|| Locator::from(([0, 0, 0, 0], 0)));
+        let receivers: Vec<_> = if outgoing.locator.ip().is_multicast() {
+            Vec::new()
+        } else if outgoing.destination == GUIDPREFIX_UNKNOWN {
+            self.participants
+                .iter()
+                .filter(|(prefix, locators)| {
+                    *prefix != source && locators.contains(&outgoing.locator)
+                })
+                .map(|(prefix, _)| *prefix)
+                .collect()
+        } else {
+            self.participants
+                .iter()
+                .map(|(prefix, _)| *prefix)
+                .filter(|prefix| *prefix == outgoing.destination)
+                .collect()
+        };
+        let remote = receivers.is_empty();
+        let local = receivers
+            .into_iter()
+            .map(|prefix| {
+                let input = Input::Received {
+                    source,
+                    source_locator,
+                    submessage: outgoing.submessage.clone(),
+                };
+                (prefix, input)
+            })
+            .collect();
+        Route {
+            local,
+            remote: remote.then_some(outgoing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{HeartbeatSubmessage, RtpsSubmessage},
+        structure::{EntityId, SequenceNumber},
+    };
+
+    fn outgoing(locator: &str, destination: GuidPrefix) -> Outgoing {
+        Outgoing {
+            locator: locator.parse().unwrap(),
+            destination,
+            submessage: RtpsSubmessage::Heartbeat(HeartbeatSubmessage {
+                reader_id: EntityId::new([0, 0, 1], 0x07),
+                writer_id: EntityId::new([0, 0, 1], 0x02),
+                first_sn: SequenceNumber::from(1),
+                last_sn: SequenceNumber::from(1),
+                count: 1,
+                final_flag: false,
+                liveliness_flag: false,
+            }),
+        }
+    }
+
+    fn receivers(route: &Route) -> Vec<GuidPrefix> {
+        route.local.iter().map(|(prefix, _)| *prefix).collect()
+    }
+
+    #[test]
+    fn test_route() {
+        let multicast = "239.255.0.1:7400";
+        let mut intra_process = IntraProcess::new();
+        intra_process.add_participant(
+            [1; 12],
+            vec![
+                "127.0.0.1:7410".parse().unwrap(),
+                multicast.parse().unwrap(),
+            ],
+        );
+        intra_process.add_participant(
+            [2; 12],
+            vec![
+                "127.0.0.1:7411".parse().unwrap(),
+                multicast.parse().unwrap(),
+            ],
+        );
+
+        // Addressed to a local participant.
+        let route = intra_process.route([1; 12], outgoing("127.0.0.1:7411", [2; 12]));
+        assert_eq!(receivers(&route), vec![[2; 12]]);
+        assert_eq!(route.remote, None);
+        let Input::Received {
+            source,
+            source_locator,
+            ..
+        } = &route.local[0].1
+        else {
+            panic!("not a received submessage");
+        };
+        assert_eq!(*source, [1; 12]);
+        assert_eq!(*source_locator, "127.0.0.1:7410".parse().unwrap());
+
+        // Addressed to a remote participant.
+        let route = intra_process.route([1; 12], outgoing("10.0.0.2:7400", [3; 12]));
+        assert!(route.local.is_empty());
+        assert!(route.remote.is_some());
+
+        // Multicast only goes through the network, which loops it back to
+        // the local participants.
+        let route = intra_process.route([1; 12], outgoing(multicast, GUIDPREFIX_UNKNOWN));
+        assert!(route.local.is_empty());
+        assert!(route.remote.is_some());
+
+        // Addressed to any participant on a local unicast locator, but not
+        // back to the sender.
+        let route = intra_process.route([1; 12], outgoing("127.0.0.1:7411", GUIDPREFIX_UNKNOWN));
+        assert_eq!(receivers(&route), vec![[2; 12]]);
+        assert_eq!(route.remote, None);
+        let route = intra_process.route([1; 12], outgoing("127.0.0.1:7410", GUIDPREFIX_UNKNOWN));
+        assert!(route.local.is_empty());
+        assert!(route.remote.is_some());
+
+        intra_process.remove_participant([2; 12]);
+        assert!(!intra_process.is_local([2; 12]));
+        let route = intra_process.route([1; 12], outgoing("127.0.0.1:7411", [2; 12]));
+        assert!(route.local.is_empty());
+        assert!(route.remote.is_some());
+    }
+}
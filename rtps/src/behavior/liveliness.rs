@@ -0,0 +1,621 @@
+//! The Writer Liveliness Protocol (WLP) asserts the liveliness of writers to
+//! the readers of remote participants. Participants exchange
+//! [`ParticipantMessageData`] on the builtin participant message topic: a
+//! [`LivelinessWriter`] asserts the liveliness of the local writers and a
+//! [`LivelinessReader`] tracks the remote ones against their lease durations.
+//!
+//! See Section 8.4.13 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+
+use std::{collections::HashMap, io};
+
+use crate::{
+    behavior::{
+        reader::stateful::StatefulReader, rearm, timer::TimerQueue,
+        writer::stateful::StatefulWriter, Behavior, Duration, Input, Output,
+        ParticipantMessageData, ParticipantMessageKind,
+    },
+    messages::{Time, TIME_INFINITE},
+    structure::{historycache::CacheChange, ChangeKind, Guid, InstanceHandle, ParameterList},
+};
+
+/// How many automatic assertions are sent within the shortest lease
+/// duration of the automatic writers, so that losing one does not expire
+/// them.
+const ASSERTIONS_PER_LEASE: u32 = 3;
+
+/// How the liveliness of a writer is asserted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LivelinessKind {
+    /// By the participant, as long as it is running.
+    Automatic,
+    /// By the application, for all the writers of the participant at once.
+    ManualByParticipant,
+    /// By the application, for each writer on its own.
+    ManualByTopic,
+}
+
+/// The BuiltinParticipantMessageWriter. It sends an automatic liveliness
+/// update periodically while the participant has automatic writers, and a
+/// manual one whenever [`LivelinessWriter::assert_liveliness`] is called.
+/// Only the latest update of each kind is kept in the writer's cache.
+#[derive(Debug)]
+pub struct LivelinessWriter {
+    writer: StatefulWriter,
+    automatic_leases: HashMap<Guid, Duration>,
+    next_automatic: Option<Time>,
+    last_updates: HashMap<ParticipantMessageKind, CacheChange>,
+}
+
+impl LivelinessWriter {
+    /// `writer` is expected to be reliable and to use
+    /// [`ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER`](crate::structure::ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER).
+    #[must_use]
+    pub fn new(writer: StatefulWriter) -> Self {
+        Self {
+            writer,
+            automatic_leases: HashMap::new(),
+            next_automatic: None,
+            last_updates: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn writer(&self) -> &StatefulWriter {
+        &self.writer
+    }
+
+    pub fn writer_mut(&mut self) -> &mut StatefulWriter {
+        &mut self.writer
+    }
+
+    /// The period of the automatic liveliness updates.
+    #[must_use]
+    pub fn automatic_period(&self) -> Option<Duration> {
+        self.automatic_leases
+            .values()
+            .min()
+            .map(|lease_duration| *lease_duration / ASSERTIONS_PER_LEASE)
+    }
+
+    /// Asserts the liveliness of a local writer with the AUTOMATIC kind and
+    /// `lease_duration` from now on. The first one is asserted right away.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer's HistoryCache cannot store the update.
+    pub fn add_automatic_writer(
+        &mut self,
+        writer_guid: Guid,
+        lease_duration: Duration,
+        now: Time,
+    ) -> io::Result<Vec<Output>> {
+        self.automatic_leases.insert(writer_guid, lease_duration);
+        let next = self.automatic_period().map_or(now, |period| now + period);
+        self.next_automatic = Some(self.next_automatic.map_or(now, |t| t.min(next)));
+        self.handle(Input::TimerFired, now)
+    }
+
+    /// Stops asserting the liveliness of a local writer.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer's HistoryCache cannot store an update.
+    pub fn remove_automatic_writer(
+        &mut self,
+        writer_guid: &Guid,
+        now: Time,
+    ) -> io::Result<Vec<Output>> {
+        self.automatic_leases.remove(writer_guid);
+        if self.automatic_leases.is_empty() {
+            self.next_automatic = None;
+        }
+        self.handle(Input::TimerFired, now)
+    }
+
+    /// Asserts the liveliness of every MANUAL_BY_PARTICIPANT writer of the
+    /// participant, which the application does by calling
+    /// `assert_liveliness` or writing with any of them.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer's HistoryCache cannot store the update.
+    pub fn assert_liveliness(&mut self, now: Time) -> io::Result<Vec<Output>> {
+        self.update(ParticipantMessageKind::MANUAL_LIVELINESS_UPDATE, now)?;
+        self.handle(Input::TimerFired, now)
+    }
+
+    /// Replaces the last update of `kind` in the writer's cache with one
+    /// written at `now`.
+    fn update(&mut self, kind: ParticipantMessageKind, now: Time) -> io::Result<()> {
+        let message = ParticipantMessageData {
+            participant_guid_prefix: self.writer.writer().guid().guid_prefix(),
+            kind,
+            data: Vec::new(),
+        };
+        let change = self
+            .writer
+            .writer_mut()
+            .new_change(
+                ChangeKind::Alive,
+                Some(message.to_data()),
+                ParameterList,
+                InstanceHandle,
+            )
+            .with_source_timestamp(now);
+        if let Some(previous) = self.last_updates.insert(kind, change.clone()) {
+            self.writer.remove_change(&previous)?;
+        }
+        self.writer.add_change(change)
+    }
+}
+
+impl Behavior for LivelinessWriter {
+    fn guid(&self) -> Guid {
+        self.writer.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        if self.next_automatic.is_some_and(|t| t <= now) {
+            self.update(ParticipantMessageKind::AUTOMATIC_LIVELINESS_UPDATE, now)?;
+            self.next_automatic = self
+                .automatic_period()
+                .map(|period| now + period)
+                .filter(|t| *t != TIME_INFINITE);
+        }
+        let mut outputs = self.writer.handle(input, now)?;
+        rearm(&mut outputs, self.next_automatic);
+        Ok(outputs)
+    }
+}
+
+/// The liveliness of a remote writer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RemoteWriter {
+    kind: LivelinessKind,
+    lease_duration: Duration,
+    alive: bool,
+}
+
+/// The BuiltinParticipantMessageReader. It tracks the liveliness of the
+/// remote writers matched with local readers and reports with
+/// [`Output::LivelinessChanged`] when one becomes alive or its lease
+/// expires.
+///
+/// An automatic liveliness update renews the AUTOMATIC writers of the
+/// participant that sent it. A manual one renews its MANUAL_BY_PARTICIPANT
+/// writers, and its AUTOMATIC writers as well since the participant is
+/// evidently running. MANUAL_BY_TOPIC writers are only renewed by
+/// [`LivelinessReader::assert_writer`].
+#[derive(Debug)]
+pub struct LivelinessReader {
+    reader: StatefulReader,
+    remote_writers: HashMap<Guid, RemoteWriter>,
+    leases: TimerQueue<Guid>,
+}
+
+impl LivelinessReader {
+    /// `reader` is expected to be reliable and to use
+    /// [`ENTITYID_SEDP_BUILTIN_MESSAGE_READER`](crate::structure::ENTITYID_SEDP_BUILTIN_MESSAGE_READER).
+    #[must_use]
+    pub fn new(reader: StatefulReader) -> Self {
+        Self {
+            reader,
+            remote_writers: HashMap::new(),
+            leases: TimerQueue::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn reader(&self) -> &StatefulReader {
+        &self.reader
+    }
+
+    pub fn reader_mut(&mut self) -> &mut StatefulReader {
+        &mut self.reader
+    }
+
+    /// Whether the remote writer is alive, or `None` if it is not tracked.
+    #[must_use]
+    pub fn is_alive(&self, writer_guid: &Guid) -> Option<bool> {
+        self.remote_writers
+            .get(writer_guid)
+            .map(|remote_writer| remote_writer.alive)
+    }
+
+    /// Tracks a remote writer, which is alive until its lease expires.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the reader's HistoryCache cannot store a change.
+    pub fn add_writer(
+        &mut self,
+        writer_guid: Guid,
+        kind: LivelinessKind,
+        lease_duration: Duration,
+        now: Time,
+    ) -> io::Result<Vec<Output>> {
+        // Matching the writer is not a change of its liveliness.
+        self.remote_writers.insert(
+            writer_guid,
+            RemoteWriter {
+                kind,
+                lease_duration,
+                alive: true,
+            },
+        );
+        self.renew(writer_guid, now, &mut Vec::new());
+        self.handle(Input::TimerFired, now)
+    }
+
+    /// Stops tracking a remote writer.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the reader's HistoryCache cannot store a change.
+    pub fn remove_writer(&mut self, writer_guid: &Guid, now: Time) -> io::Result<Vec<Output>> {
+        self.remote_writers.remove(writer_guid);
+        self.leases.cancel(writer_guid);
+        self.handle(Input::TimerFired, now)
+    }
+
+    /// Renews the lease of a remote writer, e.g. when a local reader
+    /// receives data from it or a HEARTBEAT with the liveliness flag.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the reader's HistoryCache cannot store a change.
+    pub fn assert_writer(&mut self, writer_guid: Guid, now: Time) -> io::Result<Vec<Output>> {
+        let mut outputs = Vec::new();
+        self.renew(writer_guid, now, &mut outputs);
+        outputs.extend(self.handle(Input::TimerFired, now)?);
+        Ok(outputs)
+    }
+
+    fn renew(&mut self, writer_guid: Guid, now: Time, outputs: &mut Vec<Output>) {
+        let Some(remote_writer) = self.remote_writers.get_mut(&writer_guid) else {
+            return;
+        };
+        let expiry = now + remote_writer.lease_duration;
+        if expiry == TIME_INFINITE {
+            self.leases.cancel(&writer_guid);
+        } else {
+            self.leases.schedule(writer_guid, expiry);
+        }
+        if !remote_writer.alive {
+            remote_writer.alive = true;
+            outputs.push(Output::LivelinessChanged {
+                writer: writer_guid,
+                alive: true,
+            });
+        }
+    }
+
+    /// Renews the writers asserted by a liveliness update.
+    fn on_update(
+        &mut self,
+        message: &ParticipantMessageData,
+        now: Time,
+        outputs: &mut Vec<Output>,
+    ) {
+        let renewed = |kind| match message.kind {
+            ParticipantMessageKind::AUTOMATIC_LIVELINESS_UPDATE => {
+                kind == LivelinessKind::Automatic
+            }
+            ParticipantMessageKind::MANUAL_LIVELINESS_UPDATE => {
+                kind != LivelinessKind::ManualByTopic
+            }
+            _ => false,
+        };
+        let writers: Vec<_> = self
+            .remote_writers
+            .iter()
+            .filter(|(guid, remote_writer)| {
+                guid.guid_prefix() == message.participant_guid_prefix && renewed(remote_writer.kind)
+            })
+            .map(|(guid, _)| *guid)
+            .collect();
+        for writer_guid in writers {
+            self.renew(writer_guid, now, outputs);
+        }
+    }
+}
+
+impl Behavior for LivelinessReader {
+    fn guid(&self) -> Guid {
+        self.reader.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        if let Input::ParticipantRemoved(guid_prefix) = input {
+            let leases = &mut self.leases;
+            self.remote_writers.retain(|writer_guid, _| {
+                let removed = writer_guid.guid_prefix() == guid_prefix;
+                if removed {
+                    leases.cancel(writer_guid);
+                }
+                !removed
+            });
+        }
+        let mut outputs = Vec::new();
+        for output in self.reader.handle(input, now)? {
+            let Output::Deliver(change) = output else {
+                outputs.push(output);
+                continue;
+            };
+            // The updates are consumed here rather than delivered.
+            self.reader
+                .reader_mut()
+                .reader_cache_mut()
+                .remove_change(&change)?;
+            if let Some(message) = change
+                .data_value()
+                .and_then(|data| ParticipantMessageData::from_data(data).ok())
+            {
+                self.on_update(&message, now, &mut outputs);
+            }
+        }
+        for writer_guid in self.leases.expired(now) {
+            if let Some(remote_writer) = self.remote_writers.get_mut(&writer_guid) {
+                remote_writer.alive = false;
+                outputs.push(Output::LivelinessChanged {
+                    writer: writer_guid,
+                    alive: false,
+                });
+            }
+        }
+        rearm(&mut outputs, self.leases.next_deadline());
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{
+            reader::{stateful::WriterProxy, Reader},
+            writer::{stateful::ReaderProxy, Writer},
+        },
+        messages::RtpsSubmessage,
+        structure::{
+            data::Data, participant::Endpoint, EntityId, GuidPrefix, Locator, ReliabilityKind,
+            SequenceNumber, ENTITYID_SEDP_BUILTIN_MESSAGE_READER,
+            ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER, ENTITYID_UNKNOWN,
+        },
+    };
+
+    const WRITER_PREFIX: GuidPrefix = [1; 12];
+    const READER_PREFIX: GuidPrefix = [2; 12];
+
+    fn at(millis: u64) -> Time {
+        Time::from(Duration::from_millis(millis))
+    }
+
+    fn locator(port: u16) -> Locator {
+        format!("127.0.0.1:{port}").parse().unwrap()
+    }
+
+    fn remote_writer(prefix: GuidPrefix, key: u8) -> Guid {
+        Guid::new(prefix, EntityId::new([0, 0, key], 0x02))
+    }
+
+    fn liveliness_writer() -> LivelinessWriter {
+        let guid = Guid::new(WRITER_PREFIX, ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER);
+        let mut writer = StatefulWriter::new(Writer::new(
+            Endpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            true,
+            Duration::from_secs(1),
+            Duration::ZERO,
+            Duration::ZERO,
+        ));
+        writer.matched_reader_add(ReaderProxy::new(
+            Guid::new(READER_PREFIX, ENTITYID_SEDP_BUILTIN_MESSAGE_READER),
+            ENTITYID_UNKNOWN,
+            vec![locator(7411)],
+            vec![],
+            false,
+            true,
+        ));
+        LivelinessWriter::new(writer)
+    }
+
+    fn liveliness_reader() -> LivelinessReader {
+        let guid = Guid::new(READER_PREFIX, ENTITYID_SEDP_BUILTIN_MESSAGE_READER);
+        let mut reader = StatefulReader::new(Reader::new(
+            Endpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::ZERO,
+        ));
+        reader.matched_writer_add(WriterProxy::new(
+            Guid::new(WRITER_PREFIX, ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER),
+            ENTITYID_UNKNOWN,
+            vec![locator(7410)],
+            vec![],
+            i32::MAX,
+        ));
+        LivelinessReader::new(reader)
+    }
+
+    /// Hands the DATA sent by the liveliness writer to the reader and
+    /// returns the reported liveliness changes.
+    fn deliver(outputs: Vec<Output>, reader: &mut LivelinessReader, now: Time) -> Vec<(u8, bool)> {
+        let mut changes = Vec::new();
+        for output in outputs {
+            let Output::Send(outgoing) = output else {
+                continue;
+            };
+            if !matches!(outgoing.submessage, RtpsSubmessage::Data(_)) {
+                continue;
+            }
+            let input = Input::Received {
+                source: WRITER_PREFIX,
+                source_locator: locator(7410),
+                submessage: outgoing.submessage,
+            };
+            changes.extend(liveliness_changes(reader.handle(input, now).unwrap()));
+        }
+        changes
+    }
+
+    fn liveliness_changes(outputs: Vec<Output>) -> Vec<(u8, bool)> {
+        outputs
+            .into_iter()
+            .filter_map(|output| match output {
+                Output::LivelinessChanged { writer, alive } => {
+                    Some((writer.entity_id().entity_key()[2], alive))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn deadline(outputs: &[Output]) -> Option<Time> {
+        outputs.iter().find_map(|output| match output {
+            Output::ArmTimer(deadline) => Some(*deadline),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_participant_message_data() {
+        let message = ParticipantMessageData {
+            participant_guid_prefix: [7; 12],
+            kind: ParticipantMessageKind::MANUAL_LIVELINESS_UPDATE,
+            data: vec![1, 2, 3],
+        };
+        let data = message.to_data();
+        assert_eq!(data.as_bytes().len(), 27);
+        assert_eq!(ParticipantMessageData::from_data(&data).unwrap(), message);
+
+        let mut big_endian = vec![0, 0, 0, 0];
+        big_endian.extend_from_slice(&[7; 12]);
+        big_endian.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 9]);
+        let parsed = ParticipantMessageData::from_data(&Data::from(big_endian)).unwrap();
+        assert_eq!(
+            parsed.kind,
+            ParticipantMessageKind::AUTOMATIC_LIVELINESS_UPDATE
+        );
+        assert_eq!(parsed.data, vec![9]);
+
+        assert!(ParticipantMessageData::from_data(&Data::from(vec![0, 1, 0, 0])).is_err());
+        let mut truncated = data.as_bytes().to_vec();
+        truncated.pop();
+        assert!(ParticipantMessageData::from_data(&Data::from(truncated)).is_err());
+    }
+
+    #[test]
+    fn test_automatic_liveliness() {
+        let mut writer = liveliness_writer();
+        let mut reader = liveliness_reader();
+        let automatic = remote_writer(WRITER_PREFIX, 1);
+        let manual = remote_writer(WRITER_PREFIX, 2);
+        reader
+            .add_writer(
+                automatic,
+                LivelinessKind::Automatic,
+                Duration::from_millis(300),
+                at(0),
+            )
+            .unwrap();
+        let outputs = reader
+            .add_writer(
+                manual,
+                LivelinessKind::ManualByParticipant,
+                Duration::from_millis(300),
+                at(0),
+            )
+            .unwrap();
+        assert_eq!(deadline(&outputs), Some(at(300)));
+
+        // Asserted right away, then three times per lease.
+        let outputs = writer
+            .add_automatic_writer(
+                remote_writer(WRITER_PREFIX, 1),
+                Duration::from_millis(300),
+                at(0),
+            )
+            .unwrap();
+        assert_eq!(writer.automatic_period(), Some(Duration::from_millis(100)));
+        assert_eq!(deliver(outputs, &mut reader, at(0)), vec![]);
+
+        let mut changes = Vec::new();
+        for millis in (10..=500).step_by(10) {
+            let now = at(millis);
+            let outputs = writer.handle(Input::TimerFired, now).unwrap();
+            changes.extend(deliver(outputs, &mut reader, now));
+            changes.extend(liveliness_changes(
+                reader.handle(Input::TimerFired, now).unwrap(),
+            ));
+        }
+        // Only the latest update is kept.
+        assert_eq!(writer.writer().writer().writer_cache().len(), 1);
+        let last = writer.writer().writer().last_change_sequence_number();
+        let reader_proxy = &writer.writer().matched_readers()[0];
+        assert!((1..last.value()).all(|sn| reader_proxy
+            .change_for_reader(SequenceNumber::from(sn))
+            .is_none()));
+        assert!(reader_proxy.change_for_reader(last).is_some());
+        assert_eq!(changes, vec![(2, false)]);
+        assert_eq!(reader.is_alive(&automatic), Some(true));
+        assert_eq!(reader.is_alive(&manual), Some(false));
+
+        // Once the participant stops asserting, its writers expire.
+        let outputs = reader.handle(Input::TimerFired, at(500)).unwrap();
+        let expiry = deadline(&outputs).unwrap();
+        assert!(expiry <= at(500) + Duration::from_millis(300));
+        let outputs = reader.handle(Input::TimerFired, expiry).unwrap();
+        assert_eq!(liveliness_changes(outputs), vec![(1, false)]);
+    }
+
+    #[test]
+    fn test_manual_liveliness() {
+        let mut writer = liveliness_writer();
+        let mut reader = liveliness_reader();
+        let automatic = remote_writer(WRITER_PREFIX, 1);
+        let manual = remote_writer(WRITER_PREFIX, 2);
+        let by_topic = remote_writer(WRITER_PREFIX, 3);
+        let other = remote_writer([3; 12], 2);
+        let lease_duration = Duration::from_millis(100);
+        for (guid, kind) in [
+            (automatic, LivelinessKind::Automatic),
+            (manual, LivelinessKind::ManualByParticipant),
+            (by_topic, LivelinessKind::ManualByTopic),
+            (other, LivelinessKind::ManualByParticipant),
+        ] {
+            reader
+                .add_writer(guid, kind, lease_duration, at(0))
+                .unwrap();
+        }
+        let outputs = reader.handle(Input::TimerFired, at(100)).unwrap();
+        let mut changes = liveliness_changes(outputs);
+        changes.sort_unstable();
+        assert_eq!(
+            changes,
+            vec![(1, false), (2, false), (2, false), (3, false)]
+        );
+
+        // A manual update renews the participant's automatic and
+        // MANUAL_BY_PARTICIPANT writers only.
+        let outputs = writer.assert_liveliness(at(150)).unwrap();
+        let mut changes = deliver(outputs, &mut reader, at(150));
+        changes.sort_unstable();
+        assert_eq!(changes, vec![(1, true), (2, true)]);
+        assert_eq!(reader.is_alive(&other), Some(false));
+        assert_eq!(reader.is_alive(&by_topic), Some(false));
+
+        let outputs = reader.assert_writer(by_topic, at(160)).unwrap();
+        assert_eq!(liveliness_changes(outputs), vec![(3, true)]);
+        assert_eq!(reader.is_alive(&by_topic), Some(true));
+
+        reader.remove_writer(&by_topic, at(170)).unwrap();
+        assert_eq!(reader.is_alive(&by_topic), None);
+        let outputs = reader.handle(Input::TimerFired, at(250)).unwrap();
+        let mut changes = liveliness_changes(outputs);
+        changes.sort_unstable();
+        assert_eq!(changes, vec![(1, false), (2, false)]);
+    }
+}
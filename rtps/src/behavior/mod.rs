@@ -4,14 +4,140 @@
 //! messages.
 //!
 //! See Secion 8.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=72)
+//!
+//! Writers and readers are pure state machines. They never touch the network
+//! or read the clock: each [`Input`] is passed to [`Behavior::handle`] along
+//! with the current time, and the resulting [`Output`]s tell the caller what
+//! to send, which changes were delivered and when to call back. The transport
+//! and the scheduler driving the endpoints live outside of this crate, which
+//! lets the protocol be exercised deterministically with a virtual clock.
+
+use std::io;
+
+use crate::{
+    messages::{RtpsSubmessage, Time},
+    structure::{data::Data, historycache::CacheChange, Guid, GuidPrefix, Locator},
+};
 
+pub mod intra_process;
+pub mod liveliness;
 pub mod reader;
+pub mod timer;
 pub mod writer;
 
 /// Type used to hold time differences.
 /// Should have at least nano-second resolution.
 pub type Duration = std::time::Duration;
 
+/// A submessage produced by a writer or reader and the locator it has to be
+/// sent to. The behavior module never touches the network itself; the
+/// transport drains these and sends them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outgoing {
+    pub locator: Locator,
+    /// The participant the submessage is addressed to, or
+    /// [`GUIDPREFIX_UNKNOWN`](crate::structure::GUIDPREFIX_UNKNOWN) when it is
+    /// meant for any participant listening on `locator`.
+    pub destination: GuidPrefix,
+    pub submessage: RtpsSubmessage,
+}
+
+/// An event handled by a writer or reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// A submessage received from the participant `source` through
+    /// `source_locator`.
+    Received {
+        source: GuidPrefix,
+        source_locator: Locator,
+        submessage: RtpsSubmessage,
+    },
+    /// A change created with
+    /// [`Writer::new_change`](writer::Writer::new_change) to be added to the
+    /// writer's cache. Readers ignore it.
+    NewChange(CacheChange),
+    /// Sends the changes a writer holds back for
+    /// [batching](writer::batching) right away. Readers ignore it.
+    Flush,
+    /// A remote participant left the domain or its lease expired. Endpoints
+    /// forget what they know about its endpoints.
+    ParticipantRemoved(GuidPrefix),
+    /// The deadline last requested with [`Output::ArmTimer`] has been
+    /// reached.
+    TimerFired,
+}
+
+/// An effect requested by a writer or reader in response to an [`Input`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Output {
+    Send(Outgoing),
+    /// A change a reader added to its HistoryCache. Changes from the same
+    /// writer are delivered in order.
+    Deliver(CacheChange),
+    /// The endpoint must be handled with [`Input::TimerFired`] at this time.
+    /// It replaces any timer armed before; an endpoint that requests no
+    /// timer has nothing to do until its next input.
+    ArmTimer(Time),
+    /// A remote writer tracked by a
+    /// [`LivelinessReader`](liveliness::LivelinessReader) became alive or
+    /// lost its liveliness.
+    LivelinessChanged {
+        writer: Guid,
+        alive: bool,
+    },
+    /// `count` changes of a matched writer will never be delivered: the
+    /// writer removed them before they were received, or a best-effort
+    /// reader received a later change first.
+    SamplesLost {
+        writer: Guid,
+        count: u64,
+    },
+    /// A remote participant discovered by an
+    /// [`SPDPbuiltinParticipantReader`](crate::discovery::spdp::SPDPbuiltinParticipantReader)
+    /// left the domain or its lease expired. Every local endpoint has to be
+    /// handed [`Input::ParticipantRemoved`].
+    ParticipantRemoved(GuidPrefix),
+}
+
+/// A writer or reader driven by explicit events.
+pub trait Behavior {
+    fn guid(&self) -> Guid;
+
+    /// Handles `input` at time `now`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the endpoint's HistoryCache cannot store a change.
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>>;
+}
+
+/// Collects the outputs of a handled input.
+fn outputs(
+    delivered: Vec<CacheChange>,
+    outgoing: Vec<Outgoing>,
+    deadline: Option<Time>,
+) -> Vec<Output> {
+    delivered
+        .into_iter()
+        .map(Output::Deliver)
+        .chain(outgoing.into_iter().map(Output::Send))
+        .chain(deadline.map(Output::ArmTimer))
+        .collect()
+}
+
+/// Arms the earlier of the timer in `outputs` and `deadline`.
+pub(crate) fn rearm(outputs: &mut Vec<Output>, deadline: Option<Time>) {
+    let mut earliest = deadline;
+    outputs.retain(|output| match output {
+        Output::ArmTimer(t) => {
+            earliest = Some(earliest.map_or(*t, |earliest| earliest.min(*t)));
+            false
+        }
+        _ => true,
+    });
+    outputs.extend(earliest.map(Output::ArmTimer));
+}
+
 /// Enumeration used to indicate the status of a
 /// ChangeForReader. It can take the values:
 /// - UNSENT
@@ -19,6 +145,7 @@ pub type Duration = std::time::Duration;
 /// - REQUESTED
 /// - ACKNOWLEDGED
 /// - UNDERWAY
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ChangeForReaderStatusKind {
     Unsent,
     Unacknowledged,
@@ -38,6 +165,7 @@ pub enum ChangeForReaderStatusKind {
 /// - NA_FILTERED
 /// - NA_REMOVED
 /// - NA_UNSPECIFIED
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ChangeFromWriterStatusKind {
     NotAvailableFiltered,
     NotAvailableRemoved,
@@ -53,4 +181,270 @@ pub struct InstanceHandle;
 
 /// Type used to hold data exchanged between Participants. The most
 /// notable use of this type is for the Writer Liveliness Protocol.
-pub struct ParticipantMessageData;
+///
+/// See Sections 8.4.13.3 and 9.6.2.1 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParticipantMessageData {
+    pub participant_guid_prefix: GuidPrefix,
+    pub kind: ParticipantMessageKind,
+    pub data: Vec<u8>,
+}
+
+/// The CDR little-endian encapsulation identifier and options.
+const CDR_LE: [u8; 4] = [0x00, 0x01, 0x00, 0x00];
+/// The CDR big-endian encapsulation identifier and options.
+const CDR_BE: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+
+impl ParticipantMessageData {
+    /// The serialized payload of the sample, in little-endian CDR.
+    #[must_use]
+    pub fn to_data(&self) -> Data {
+        let length = u32::try_from(self.data.len()).expect("data exceeds the length of a sequence");
+        let mut bytes = Vec::with_capacity(24 + self.data.len());
+        bytes.extend_from_slice(&CDR_LE);
+        bytes.extend_from_slice(&self.participant_guid_prefix);
+        bytes.extend_from_slice(&self.kind.0);
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        Data::from(bytes)
+    }
+
+    /// Parses a serialized payload in either CDR byte order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the payload is truncated or not CDR encapsulated.
+    pub fn from_data(data: &Data) -> io::Result<Self> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let bytes = data.as_bytes();
+        if bytes.len() < 24 {
+            return Err(invalid("truncated ParticipantMessageData"));
+        }
+        let length = <[u8; 4]>::try_from(&bytes[20..24]).unwrap();
+        let length = match <[u8; 4]>::try_from(&bytes[..4]).unwrap() {
+            CDR_LE => u32::from_le_bytes(length),
+            CDR_BE => u32::from_be_bytes(length),
+            _ => return Err(invalid("unsupported encapsulation")),
+        };
+        let data = usize::try_from(length)
+            .ok()
+            .and_then(|length| bytes[24..].get(..length))
+            .ok_or_else(|| invalid("truncated ParticipantMessageData"))?;
+        Ok(Self {
+            participant_guid_prefix: bytes[4..16].try_into().unwrap(),
+            kind: ParticipantMessageKind(bytes[16..20].try_into().unwrap()),
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Identifies the purpose of a [`ParticipantMessageData`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ParticipantMessageKind(pub [u8; 4]);
+
+impl ParticipantMessageKind {
+    pub const UNKNOWN: Self = Self([0x00, 0x00, 0x00, 0x00]);
+    pub const AUTOMATIC_LIVELINESS_UPDATE: Self = Self([0x00, 0x00, 0x00, 0x01]);
+    pub const MANUAL_LIVELINESS_UPDATE: Self = Self([0x00, 0x00, 0x00, 0x02]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::{
+        data::Data, participant::Endpoint, ChangeKind, EntityId, InstanceHandle, ParameterList,
+        ReliabilityKind, ENTITYID_UNKNOWN,
+    };
+    use reader::{
+        stateful::{StatefulReader, WriterProxy},
+        Reader,
+    };
+    use writer::{
+        stateful::{ReaderProxy, StatefulWriter},
+        Writer,
+    };
+
+    const LATENCY: Duration = Duration::from_millis(10);
+
+    /// A virtual network and clock. Every submessage takes [`LATENCY`] to
+    /// arrive and those for which `lose` returns `true` are dropped.
+    struct Sim<F> {
+        now: Time,
+        endpoints: Vec<(Box<dyn Behavior>, Locator)>,
+        timers: Vec<Option<Time>>,
+        in_flight: Vec<(Time, usize, Input)>,
+        delivered: Vec<(Time, u64)>,
+        lose: F,
+    }
+
+    impl<F: FnMut(&RtpsSubmessage) -> bool> Sim<F> {
+        fn new(endpoints: Vec<(Box<dyn Behavior>, Locator)>, lose: F) -> Self {
+            let timers = vec![None; endpoints.len()];
+            Self {
+                now: Time::new(0, 0),
+                endpoints,
+                timers,
+                in_flight: Vec::new(),
+                delivered: Vec::new(),
+                lose,
+            }
+        }
+
+        fn handle(&mut self, index: usize, input: Input) {
+            let (endpoint, source_locator) = &mut self.endpoints[index];
+            let source = endpoint.guid().guid_prefix();
+            let source_locator = *source_locator;
+            self.timers[index] = None;
+            for output in endpoint.handle(input, self.now).unwrap() {
+                match output {
+                    Output::Send(outgoing) => {
+                        if (self.lose)(&outgoing.submessage) {
+                            continue;
+                        }
+                        let destination = self
+                            .endpoints
+                            .iter()
+                            .position(|(_, locator)| *locator == outgoing.locator)
+                            .unwrap();
+                        let input = Input::Received {
+                            source,
+                            source_locator,
+                            submessage: outgoing.submessage,
+                        };
+                        self.in_flight
+                            .push((self.now + LATENCY, destination, input));
+                    }
+                    Output::Deliver(change) => self
+                        .delivered
+                        .push((self.now, change.sequence_number().value())),
+                    Output::ArmTimer(deadline) => self.timers[index] = Some(deadline),
+                    Output::LivelinessChanged { .. }
+                    | Output::SamplesLost { .. }
+                    | Output::ParticipantRemoved(_) => {}
+                }
+            }
+        }
+
+        /// Processes events in time order until none is left.
+        fn run(&mut self) {
+            loop {
+                let arrival = self
+                    .in_flight
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, (t, _, _))| *t)
+                    .map(|(i, (t, _, _))| (*t, Some(i), 0));
+                let timer = self
+                    .timers
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, t)| t.map(|t| (t, None, index)))
+                    .min_by_key(|(t, _, _)| *t);
+                let Some((time, arrival, index)) =
+                    arrival.into_iter().chain(timer).min_by_key(|e| e.0)
+                else {
+                    return;
+                };
+                self.now = time;
+                match arrival {
+                    Some(i) => {
+                        let (_, destination, input) = self.in_flight.remove(i);
+                        self.handle(destination, input);
+                    }
+                    None => self.handle(index, Input::TimerFired),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reliable_delivery_over_lossy_network() {
+        let writer_locator: Locator = "127.0.0.1:7410".parse().unwrap();
+        let reader_locator: Locator = "127.0.0.1:7400".parse().unwrap();
+        let writer_guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let reader_guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+
+        let mut writer = StatefulWriter::new(Writer::new(
+            Endpoint::builder(writer_guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            true,
+            Duration::from_secs(1),
+            Duration::from_millis(200),
+            Duration::ZERO,
+        ));
+        writer.matched_reader_add(ReaderProxy::new(
+            reader_guid,
+            ENTITYID_UNKNOWN,
+            vec![reader_locator],
+            vec![],
+            false,
+            true,
+        ));
+        let mut reader = StatefulReader::new(Reader::new(
+            Endpoint::builder(reader_guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::from_millis(100),
+        ));
+        reader.matched_writer_add(WriterProxy::new(
+            writer_guid,
+            ENTITYID_UNKNOWN,
+            vec![writer_locator],
+            vec![],
+            i32::MAX,
+        ));
+        let changes: Vec<_> = (0..5)
+            .map(|_| {
+                writer.writer_mut().new_change(
+                    ChangeKind::Alive,
+                    Some(Data::from(vec![0; 8])),
+                    ParameterList,
+                    InstanceHandle,
+                )
+            })
+            .collect();
+
+        // The first transmission of changes 2 and 4 and the first ACKNACK
+        // are lost.
+        let mut lost = vec![2, 4];
+        let mut acknack_lost = false;
+        let mut sim = Sim::new(
+            vec![
+                (Box::new(writer), writer_locator),
+                (Box::new(reader), reader_locator),
+            ],
+            move |submessage: &RtpsSubmessage| match submessage {
+                RtpsSubmessage::Data(data) => {
+                    let sn = data.writer_sn.value();
+                    lost.iter()
+                        .position(|l| *l == sn)
+                        .map(|i| lost.remove(i))
+                        .is_some()
+                }
+                RtpsSubmessage::AckNack(_) => !std::mem::replace(&mut acknack_lost, true),
+                _ => false,
+            },
+        );
+        for change in changes {
+            sim.handle(0, Input::NewChange(change));
+        }
+        sim.run();
+
+        let sequence_numbers: Vec<_> = sim.delivered.iter().map(|(_, sn)| *sn).collect();
+        assert_eq!(sequence_numbers, vec![1, 2, 3, 4, 5]);
+        // Change 1 arrives right away. The ACKNACK answering the HEARTBEAT
+        // sent along with the changes is lost, so the rest waits for the
+        // periodic HEARTBEAT at 1s, the ACKNACK 100ms after it arrives and the
+        // repair 200ms after that.
+        let repaired = Time::from(Duration::from_secs(1))
+            + LATENCY
+            + Duration::from_millis(100)
+            + LATENCY
+            + Duration::from_millis(200)
+            + LATENCY;
+        assert!(sim.delivered[1..].iter().all(|(t, _)| *t == repaired));
+        assert!(sim.timers.iter().all(Option::is_none));
+    }
+}
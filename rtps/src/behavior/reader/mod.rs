@@ -1,7 +1,13 @@
-use crate::structure::{
-    historycache::HistoryCache,
-    participant::{self, Endpoint},
-    Guid,
+use std::{collections::BTreeSet, io};
+
+use crate::{
+    messages::{DataFragSubmessage, FragmentNumber, Time},
+    structure::{
+        data::Data,
+        historycache::{CacheChange, HistoryCache},
+        participant::Endpoint,
+        Guid,
+    },
 };
 
 use super::Duration;
@@ -9,13 +15,248 @@ use super::Duration;
 pub mod stateful;
 pub mod stateless;
 
+/// See Section 8.4.10 of the [specification](https://www.omg.org/spec/DDSI-RTPS/)
+#[derive(Debug)]
 pub struct Reader {
-    guid: Guid,
-
-    endpoint: Endpoint<participant::Reader>,
+    endpoint: Endpoint,
 
     expects_inline_qos: bool,
     heartbeat_response_delay: Duration,
+    lifespan: Option<Duration>,
+
+    reader_cache: HistoryCache,
+}
+
+impl Reader {
+    /// See 8.4.10.1.2 of the specification. The GUID, locators, reliability
+    /// and topic kind are taken from `endpoint`.
+    #[must_use]
+    pub fn new(
+        endpoint: Endpoint,
+        expects_inline_qos: bool,
+        heartbeat_response_delay: Duration,
+    ) -> Self {
+        Self {
+            endpoint,
+            expects_inline_qos,
+            heartbeat_response_delay,
+            lifespan: None,
+            reader_cache: HistoryCache::new(),
+        }
+    }
+
+    /// Replaces the reader's HistoryCache, e.g. with one using a different
+    /// storage backend.
+    #[must_use]
+    pub fn with_reader_cache(mut self, reader_cache: HistoryCache) -> Self {
+        self.reader_cache = reader_cache;
+        self
+    }
+
+    /// Removes changes from the reader's cache once `lifespan` has elapsed
+    /// since they were written, or received if the writer did not send a
+    /// source timestamp.
+    #[must_use]
+    pub fn with_lifespan(mut self, lifespan: Duration) -> Self {
+        self.lifespan = Some(lifespan);
+        self
+    }
+
+    #[must_use]
+    pub const fn lifespan(&self) -> Option<Duration> {
+        self.lifespan
+    }
+
+    /// Removes the changes whose Lifespan has elapsed at `now` from the
+    /// reader's cache and returns them.
+    pub(crate) fn remove_expired(&mut self, now: Time) -> io::Result<Vec<CacheChange>> {
+        match self.lifespan {
+            Some(lifespan) => self.reader_cache.remove_expired(now, lifespan),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// When the next change in the reader's cache expires.
+    pub(crate) fn expiry_deadline(&self) -> Option<Time> {
+        self.reader_cache.next_expiry(self.lifespan?)
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.endpoint.guid()
+    }
+
+    #[must_use]
+    pub const fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    #[must_use]
+    pub const fn expects_inline_qos(&self) -> bool {
+        self.expects_inline_qos
+    }
+
+    #[must_use]
+    pub const fn heartbeat_response_delay(&self) -> Duration {
+        self.heartbeat_response_delay
+    }
+
+    #[must_use]
+    pub const fn reader_cache(&self) -> &HistoryCache {
+        &self.reader_cache
+    }
+
+    pub fn reader_cache_mut(&mut self) -> &mut HistoryCache {
+        &mut self.reader_cache
+    }
+}
+
+/// The number of samples reassembled at once per matched writer of a
+/// StatefulReader, and across all writers of a StatelessReader.
+pub(crate) const MAX_REASSEMBLIES: usize = 16;
+
+/// Reassembles a sample received as DATA_FRAG submessages.
+#[derive(Debug)]
+pub(crate) struct Reassembly {
+    fragment_size: usize,
+    bytes: Vec<u8>,
+    received: BTreeSet<FragmentNumber>,
+    /// Every fragment up to this one is known to be available from the
+    /// writer.
+    available: FragmentNumber,
+}
+
+impl Reassembly {
+    /// Starts reassembling the sample `frag` belongs to, or returns `None`
+    /// if its sizes are inconsistent or the sample is larger than
+    /// `max_sample_size` bytes.
+    pub(crate) fn new(frag: &DataFragSubmessage, max_sample_size: usize) -> Option<Self> {
+        let fragment_size = usize::from(frag.fragment_size);
+        let sample_size = usize::try_from(frag.sample_size).ok()?;
+        if fragment_size == 0 || sample_size == 0 || sample_size > max_sample_size {
+            return None;
+        }
+        Some(Self {
+            fragment_size,
+            bytes: vec![0; sample_size],
+            received: BTreeSet::new(),
+            available: 0,
+        })
+    }
+
+    pub(crate) fn fragment_count(&self) -> FragmentNumber {
+        FragmentNumber::try_from(self.bytes.len().div_ceil(self.fragment_size))
+            .unwrap_or(FragmentNumber::MAX)
+    }
+
+    /// Copies the fragments carried by `frag` into the sample. Fragments
+    /// that do not fit the sample are ignored.
+    pub(crate) fn insert(&mut self, frag: &DataFragSubmessage) {
+        if usize::from(frag.fragment_size) != self.fragment_size
+            || usize::try_from(frag.sample_size).ok() != Some(self.bytes.len())
+        {
+            return;
+        }
+        let mut payload = frag.serialized_payload.as_bytes();
+        for offset in 0..FragmentNumber::from(frag.fragments_in_submessage) {
+            let Some(fragment_number) = frag.fragment_starting_num.checked_add(offset) else {
+                return;
+            };
+            let Some(start) = fragment_number
+                .checked_sub(1)
+                .and_then(|n| usize::try_from(n).ok())
+                .and_then(|n| n.checked_mul(self.fragment_size))
+                .filter(|start| *start < self.bytes.len())
+            else {
+                return;
+            };
+            let len = self.fragment_size.min(self.bytes.len() - start);
+            let Some((fragment, rest)) = payload.split_at_checked(len) else {
+                return;
+            };
+            self.bytes[start..start + len].copy_from_slice(fragment);
+            self.received.insert(fragment_number);
+            self.available = self.available.max(fragment_number);
+            payload = rest;
+        }
+    }
+
+    /// Records that the writer has every fragment up to `last_fragment_num`.
+    pub(crate) fn set_available(&mut self, last_fragment_num: FragmentNumber) {
+        self.available = self
+            .available
+            .max(last_fragment_num.min(self.fragment_count()));
+    }
+
+    /// Fragments known to be available that were not received.
+    pub(crate) fn missing(&self) -> impl Iterator<Item = FragmentNumber> + '_ {
+        (1..=self.available).filter(|n| !self.received.contains(n))
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.received.len() == self.bytes.len().div_ceil(self.fragment_size)
+    }
+
+    pub(crate) fn into_data(self) -> Data {
+        Data::from(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::{EntityId, ReliabilityKind, SequenceNumber, ENTITYID_UNKNOWN};
+
+    fn frag(start: FragmentNumber, count: u16, payload: &[u8]) -> DataFragSubmessage {
+        DataFragSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: ENTITYID_UNKNOWN,
+            writer_sn: SequenceNumber::from(1),
+            fragment_starting_num: start,
+            fragments_in_submessage: count,
+            fragment_size: 4,
+            sample_size: 10,
+            source_timestamp: None,
+            serialized_payload: Data::from(payload),
+        }
+    }
+
+    #[test]
+    fn test_reassembly() {
+        assert!(Reassembly::new(&frag(3, 1, &[8, 9]), 9).is_none());
+        let mut reassembly = Reassembly::new(&frag(3, 1, &[8, 9]), 10).unwrap();
+        assert_eq!(reassembly.fragment_count(), 3);
+        reassembly.insert(&frag(3, 1, &[8, 9]));
+        assert_eq!(reassembly.missing().collect::<Vec<_>>(), vec![1, 2]);
+        // A fragment that does not fit the sample is ignored.
+        reassembly.insert(&frag(4, 1, &[0]));
+        reassembly.insert(&frag(FragmentNumber::MAX, 2, &[0; 8]));
+        reassembly.insert(&frag(1, 2, &[0, 1, 2, 3, 4, 5, 6]));
+        assert!(!reassembly.is_complete());
+        assert_eq!(reassembly.missing().collect::<Vec<_>>(), vec![2]);
+        reassembly.insert(&frag(2, 1, &[4, 5, 6, 7]));
+        assert!(reassembly.is_complete());
+        assert_eq!(
+            reassembly.into_data(),
+            Data::from((0..10).collect::<Vec<u8>>())
+        );
+    }
 
-    reader_cache: Box<HistoryCache>,
+    #[test]
+    fn test_new_reader() {
+        let guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x07));
+        let reader = Reader::new(
+            Endpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::from_millis(100),
+        );
+        assert_eq!(reader.guid(), guid);
+        assert_eq!(
+            reader.endpoint().reliability_level(),
+            ReliabilityKind::Reliable
+        );
+        assert!(reader.reader_cache().is_empty());
+    }
 }
@@ -1,7 +1,29 @@
-use crate::structure::{EntityId, Guid, Locator};
+//! The RTPS StatefulReader keeps a [`WriterProxy`] for every matched writer
+//! and tracks the status of each change coming from that writer.
+//!
+//! See Sections 8.4.10.4, 8.4.10.5 and 8.4.12 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
 
-use super::Reader;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    io,
+};
 
+use crate::{
+    behavior::{outputs, Behavior, ChangeFromWriterStatusKind, Input, Outgoing, Output},
+    messages::{
+        AckNackSubmessage, Count, DataFragSubmessage, DataSubmessage, FragmentNumberSet,
+        GapSubmessage, HeartbeatFragSubmessage, HeartbeatSubmessage, NackFragSubmessage,
+        RtpsSubmessage, SequenceNumberSet, Time,
+    },
+    structure::{
+        data::Data, historycache::CacheChange, ChangeKind, EntityId, Guid, GuidPrefix,
+        InstanceHandle, Locator, ParameterList, ReliabilityKind, SequenceNumber,
+    },
+};
+
+use super::{Reader, Reassembly, MAX_REASSEMBLIES};
+
+#[derive(Debug)]
 pub struct StatefulReader {
     reader: Reader,
 
@@ -9,49 +31,1215 @@ pub struct StatefulReader {
 }
 
 impl StatefulReader {
-    pub fn matched_writer_add(&self) {
-        todo!()
+    #[must_use]
+    pub fn new(reader: Reader) -> Self {
+        Self {
+            reader,
+            matched_writers: Vec::new(),
+        }
     }
 
-    pub fn matched_writer_remove(&self) {
-        todo!()
+    #[must_use]
+    pub const fn reader(&self) -> &Reader {
+        &self.reader
     }
 
-    pub fn matched_writer_lookup(&self) {
-        todo!();
+    pub fn reader_mut(&mut self) -> &mut Reader {
+        &mut self.reader
+    }
+
+    #[must_use]
+    pub fn matched_writers(&self) -> &[WriterProxy] {
+        &self.matched_writers
+    }
+
+    /// Matches a writer. A proxy whose GUID is already matched replaces the
+    /// existing one.
+    pub fn matched_writer_add(&mut self, writer_proxy: WriterProxy) {
+        self.matched_writer_remove(&writer_proxy.remote_writer_guid);
+        self.matched_writers.push(writer_proxy);
+    }
+
+    pub fn matched_writer_remove(&mut self, writer_guid: &Guid) -> Option<WriterProxy> {
+        self.matched_writers
+            .iter()
+            .position(|w| w.remote_writer_guid == *writer_guid)
+            .map(|index| self.matched_writers.remove(index))
+    }
+
+    /// Unmatches the writers of the participant `guid_prefix` and returns
+    /// them.
+    pub fn remove_participant(&mut self, guid_prefix: GuidPrefix) -> Vec<WriterProxy> {
+        let (removed, kept) = std::mem::take(&mut self.matched_writers)
+            .into_iter()
+            .partition(|w| w.remote_writer_guid.guid_prefix() == guid_prefix);
+        self.matched_writers = kept;
+        removed
+    }
+
+    #[must_use]
+    pub fn matched_writer_lookup(&self, writer_guid: &Guid) -> Option<&WriterProxy> {
+        self.matched_writers
+            .iter()
+            .find(|w| w.remote_writer_guid == *writer_guid)
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.reader.endpoint.reliability_level() == ReliabilityKind::Reliable
+    }
+
+    fn split_mut(&mut self, writer_guid: Guid) -> Option<(&mut Reader, &mut WriterProxy)> {
+        let writer_proxy = self
+            .matched_writers
+            .iter_mut()
+            .find(|w| w.remote_writer_guid == writer_guid)?;
+        Some((&mut self.reader, writer_proxy))
+    }
+
+    /// Handles a DATA sent by a writer of the participant `source`.
+    ///
+    /// A reliable reader adds changes to its cache in sequence order: a
+    /// change received ahead of a missing one is held back until the gap is
+    /// filled or the missing change is known to be irrelevant or lost. A
+    /// best-effort reader adds every change newer than the last one received
+    /// right away and treats the skipped ones as lost.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store a released change.
+    pub fn on_data(
+        &mut self,
+        source: GuidPrefix,
+        data: &DataSubmessage,
+        now: Time,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, data.writer_id);
+        let reliable = self.is_reliable();
+        let Some((reader, writer_proxy)) = self.split_mut(writer_guid) else {
+            return Ok(Vec::new());
+        };
+        // No change can follow the last sequence number, so the proxy could
+        // never settle it.
+        if successor(data.writer_sn).is_none() {
+            return Ok(Vec::new());
+        }
+        if !reliable {
+            writer_proxy.lost_changes_update(data.writer_sn);
+        }
+        writer_proxy.received_change_set(change_from_data(writer_guid, data, now));
+        release(reader, writer_proxy)
+    }
+
+    /// Handles a DATA_FRAG sent by a writer of the participant `source`.
+    /// Once every fragment of a sample has arrived, the sample is handled
+    /// like a DATA carrying it.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store a released change.
+    pub fn on_data_frag(
+        &mut self,
+        source: GuidPrefix,
+        data_frag: &DataFragSubmessage,
+        now: Time,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, data_frag.writer_id);
+        let reliable = self.is_reliable();
+        let Some((reader, writer_proxy)) = self.split_mut(writer_guid) else {
+            return Ok(Vec::new());
+        };
+        if successor(data_frag.writer_sn).is_none() {
+            return Ok(Vec::new());
+        }
+        let Some(serialized_payload) = writer_proxy.received_fragment(data_frag) else {
+            return Ok(Vec::new());
+        };
+        let data = DataSubmessage {
+            reader_id: data_frag.reader_id,
+            writer_id: data_frag.writer_id,
+            writer_sn: data_frag.writer_sn,
+            kind: ChangeKind::Alive,
+            source_timestamp: data_frag.source_timestamp,
+            serialized_payload: Some(serialized_payload),
+        };
+        if !reliable {
+            writer_proxy.lost_changes_update(data.writer_sn);
+        }
+        writer_proxy.received_change_set(change_from_data(writer_guid, &data, now));
+        release(reader, writer_proxy)
+    }
+
+    /// Handles a HEARTBEAT sent by a writer of the participant `source`.
+    /// Unless the writer set the final flag and nothing is missing, an
+    /// ACKNACK is sent by the first [`StatefulReader::poll`] after
+    /// `heartbeat_response_delay`. Best-effort readers ignore HEARTBEATs.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store a released change.
+    pub fn on_heartbeat(
+        &mut self,
+        source: GuidPrefix,
+        heartbeat: &HeartbeatSubmessage,
+        now: Time,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, heartbeat.writer_id);
+        let heartbeat_response_delay = self.reader.heartbeat_response_delay;
+        if !self.is_reliable() {
+            return Ok(Vec::new());
+        }
+        let Some((reader, writer_proxy)) = self.split_mut(writer_guid) else {
+            return Ok(Vec::new());
+        };
+        if writer_proxy
+            .last_heartbeat_count
+            .is_some_and(|count| heartbeat.count <= count)
+        {
+            return Ok(Vec::new());
+        }
+        writer_proxy.last_heartbeat_count = Some(heartbeat.count);
+        if heartbeat.liveliness_flag {
+            writer_proxy.last_liveliness_assertion = Some(now);
+        }
+        writer_proxy.missing_changes_update(heartbeat.last_sn);
+        writer_proxy.lost_changes_update(heartbeat.first_sn);
+        let released = release(reader, writer_proxy)?;
+        // Every fragment of the changes in the writer's cache is available.
+        for reassembly in writer_proxy
+            .fragmented
+            .range_mut(..=heartbeat.last_sn)
+            .map(|(_, r)| r)
+        {
+            reassembly.set_available(reassembly.fragment_count());
+        }
+
+        let must_respond = !heartbeat.final_flag
+            || writer_proxy.missing_changes().next().is_some()
+            || writer_proxy.has_missing_fragments();
+        if must_respond && writer_proxy.acknack_at.is_none() {
+            writer_proxy.acknack_at = Some(now + heartbeat_response_delay);
+        }
+        Ok(released)
+    }
+
+    /// Handles a HEARTBEAT_FRAG sent by a writer of the participant
+    /// `source`. If fragments of the announced sample are missing, a
+    /// NACK_FRAG requesting them is sent by the first
+    /// [`StatefulReader::poll`] after `heartbeat_response_delay`.
+    pub fn on_heartbeat_frag(
+        &mut self,
+        source: GuidPrefix,
+        heartbeat_frag: &HeartbeatFragSubmessage,
+        now: Time,
+    ) {
+        let writer_guid = Guid::new(source, heartbeat_frag.writer_id);
+        let heartbeat_response_delay = self.reader.heartbeat_response_delay;
+        if !self.is_reliable() {
+            return;
+        }
+        let Some((_, writer_proxy)) = self.split_mut(writer_guid) else {
+            return;
+        };
+        if writer_proxy
+            .last_heartbeat_frag_count
+            .is_some_and(|count| heartbeat_frag.count <= count)
+        {
+            return;
+        }
+        writer_proxy.last_heartbeat_frag_count = Some(heartbeat_frag.count);
+        let Some(reassembly) = writer_proxy.fragmented.get_mut(&heartbeat_frag.writer_sn) else {
+            return;
+        };
+        reassembly.set_available(heartbeat_frag.last_fragment_num);
+        if reassembly.missing().next().is_some() && writer_proxy.acknack_at.is_none() {
+            writer_proxy.acknack_at = Some(now + heartbeat_response_delay);
+        }
+    }
+
+    /// Handles a GAP sent by a writer of the participant `source`, marking
+    /// the listed changes as irrelevant.
+    ///
+    /// Only the irrelevant changes an ACKNACK could request are tracked
+    /// individually, so that a GAP far ahead of the settled changes costs no
+    /// more than its bitmap; the writer declares the others again once they
+    /// are requested.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store a released change.
+    pub fn on_gap(
+        &mut self,
+        source: GuidPrefix,
+        gap: &GapSubmessage,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, gap.writer_id);
+        let Some((reader, writer_proxy)) = self.split_mut(writer_guid) else {
+            return Ok(Vec::new());
+        };
+        let contiguous =
+            successor(writer_proxy.available_changes_max).is_some_and(|next| gap.gap_start <= next);
+        if contiguous {
+            // The range continues the settled changes, so it can be settled
+            // at once however long it is.
+            writer_proxy.settle_before(gap.gap_list.base());
+        }
+        let horizon = SequenceNumber::from(
+            writer_proxy
+                .available_changes_max
+                .value()
+                .saturating_add(SequenceNumberSet::MAX_SPAN),
+        );
+        if !contiguous {
+            let mut sequence_number = gap.gap_start;
+            while sequence_number < gap.gap_list.base() && sequence_number <= horizon {
+                writer_proxy.irrelevant_change_set(sequence_number);
+                sequence_number.increment();
+            }
+        }
+        for sequence_number in gap.gap_list.iter().filter(|sn| *sn <= horizon) {
+            writer_proxy.irrelevant_change_set(sequence_number);
+        }
+        release(reader, writer_proxy)
+    }
+
+    /// The next time at which [`StatefulReader::poll`] has an ACKNACK to
+    /// send.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Time> {
+        self.matched_writers
+            .iter()
+            .filter_map(|w| w.acknack_at)
+            .chain(self.reader.expiry_deadline())
+            .min()
+    }
+
+    /// Sends the ACKNACKs that are due, acknowledging everything up to
+    /// `available_changes_max` and requesting the missing changes. Changes
+    /// of which some fragments were received are not requested whole;
+    /// a NACK_FRAG requests their missing fragments instead.
+    pub fn poll(&mut self, now: Time) -> Vec<Outgoing> {
+        let reader_id = self.reader.guid().entity_id();
+        let mut outgoing = Vec::new();
+        for writer_proxy in &mut self.matched_writers {
+            if writer_proxy.acknack_at.is_none_or(|t| t > now) {
+                continue;
+            }
+            writer_proxy.acknack_at = None;
+            let Some(locator) = writer_proxy.locator() else {
+                continue;
+            };
+            writer_proxy.acknack_count = writer_proxy.acknack_count.wrapping_add(1);
+            let reader_sn_state = writer_proxy.reader_sn_state();
+            outgoing.push(Outgoing {
+                locator,
+                destination: writer_proxy.remote_writer_guid.guid_prefix(),
+                submessage: RtpsSubmessage::AckNack(AckNackSubmessage {
+                    reader_id,
+                    writer_id: writer_proxy.remote_writer_guid.entity_id(),
+                    final_flag: reader_sn_state.is_empty(),
+                    reader_sn_state,
+                    count: writer_proxy.acknack_count,
+                }),
+            });
+            let nack_frags: Vec<_> = writer_proxy
+                .fragmented
+                .iter()
+                .filter_map(|(sn, reassembly)| {
+                    let base = reassembly.missing().next()?;
+                    Some((*sn, FragmentNumberSet::new(base, reassembly.missing())))
+                })
+                .collect();
+            for (writer_sn, fragment_number_state) in nack_frags {
+                writer_proxy.nack_frag_count = writer_proxy.nack_frag_count.wrapping_add(1);
+                outgoing.push(Outgoing {
+                    locator,
+                    destination: writer_proxy.remote_writer_guid.guid_prefix(),
+                    submessage: RtpsSubmessage::NackFrag(NackFragSubmessage {
+                        reader_id,
+                        writer_id: writer_proxy.remote_writer_guid.entity_id(),
+                        writer_sn,
+                        fragment_number_state,
+                        count: writer_proxy.nack_frag_count,
+                    }),
+                });
+            }
+        }
+        outgoing
     }
 }
 
+impl Behavior for StatefulReader {
+    fn guid(&self) -> Guid {
+        self.reader.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        let delivered = match input {
+            Input::Received {
+                source, submessage, ..
+            } => match submessage {
+                RtpsSubmessage::Data(data) => self.on_data(source, &data, now)?,
+                RtpsSubmessage::DataFrag(data_frag) => {
+                    self.on_data_frag(source, &data_frag, now)?
+                }
+                RtpsSubmessage::Gap(gap) => self.on_gap(source, &gap)?,
+                RtpsSubmessage::Heartbeat(heartbeat) => {
+                    self.on_heartbeat(source, &heartbeat, now)?
+                }
+                RtpsSubmessage::HeartbeatFrag(heartbeat_frag) => {
+                    self.on_heartbeat_frag(source, &heartbeat_frag, now);
+                    Vec::new()
+                }
+                RtpsSubmessage::AckNack(_) | RtpsSubmessage::NackFrag(_) => Vec::new(),
+            },
+            Input::ParticipantRemoved(guid_prefix) => {
+                self.remove_participant(guid_prefix);
+                Vec::new()
+            }
+            Input::NewChange(_) | Input::Flush | Input::TimerFired => Vec::new(),
+        };
+        self.reader.remove_expired(now)?;
+        let outgoing = self.poll(now);
+        let mut outputs = outputs(delivered, outgoing, self.next_deadline());
+        outputs.extend(self.matched_writers.iter_mut().filter_map(|w| {
+            let count = std::mem::take(&mut w.unreported_lost);
+            (count > 0).then_some(Output::SamplesLost {
+                writer: w.remote_writer_guid,
+                count,
+            })
+        }));
+        Ok(outputs)
+    }
+}
+
+/// Builds the CacheChange carried by a DATA from `writer_guid`, received at
+/// `now`.
+pub(super) fn change_from_data(writer_guid: Guid, data: &DataSubmessage, now: Time) -> CacheChange {
+    let change = CacheChange::new(
+        data.kind,
+        writer_guid,
+        InstanceHandle,
+        data.writer_sn,
+        data.serialized_payload.clone(),
+        ParameterList,
+    )
+    .with_reception_timestamp(now);
+    match data.source_timestamp {
+        Some(timestamp) => change.with_source_timestamp(timestamp),
+        None => change,
+    }
+}
+
+/// The sequence number following `sequence_number`, or `None` past the
+/// last one.
+fn successor(sequence_number: SequenceNumber) -> Option<SequenceNumber> {
+    sequence_number
+        .value()
+        .checked_add(1)
+        .map(SequenceNumber::from)
+}
+
+/// Moves the changes the proxy can release in order into the reader's cache
+/// and returns them.
+fn release(reader: &mut Reader, writer_proxy: &mut WriterProxy) -> io::Result<Vec<CacheChange>> {
+    let released = writer_proxy.take_available();
+    for change in &released {
+        reader.reader_cache.add_change(change.clone())?;
+    }
+    Ok(released)
+}
+
+/// See Section 8.4.10.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+///
+/// Changes up to `available_changes_max` have all been received or are known
+/// to be unavailable and are no longer tracked individually. Above it, only
+/// changes that were received or declared unavailable are stored; any other
+/// sequence number is MISSING up to the highest one the writer announced, and
+/// UNKNOWN beyond.
+///
+/// Received changes are queued until every change before them has been
+/// received or declared irrelevant or lost, so that they are delivered in
+/// order. The changes declared lost are counted.
+#[derive(Debug)]
 pub struct WriterProxy {
     remote_writer_guid: Guid,
     remote_group_entity_id: EntityId,
     unicast_locator_list: Vec<Locator>,
     multicast_locator_list: Vec<Locator>,
     data_max_size_serialized: i32,
+
+    available_changes_max: SequenceNumber,
+    highest_announced: SequenceNumber,
+    changes_from_writer: BTreeMap<SequenceNumber, ChangeFromWriterStatusKind>,
+    /// Received changes waiting for the ones before them.
+    pending: BTreeMap<SequenceNumber, CacheChange>,
+    /// Samples of which only some fragments were received.
+    fragmented: BTreeMap<SequenceNumber, Reassembly>,
+    samples_lost: u64,
+    /// Lost changes not reported with [`Output::SamplesLost`] yet.
+    unreported_lost: u64,
+
+    last_heartbeat_count: Option<Count>,
+    last_heartbeat_frag_count: Option<Count>,
+    last_liveliness_assertion: Option<Time>,
+    acknack_count: Count,
+    nack_frag_count: Count,
+    acknack_at: Option<Time>,
 }
 
 impl WriterProxy {
-    pub fn available_changes_max(&self) {
-        todo!()
+    #[must_use]
+    pub fn new(
+        remote_writer_guid: Guid,
+        remote_group_entity_id: EntityId,
+        unicast_locator_list: Vec<Locator>,
+        multicast_locator_list: Vec<Locator>,
+        data_max_size_serialized: i32,
+    ) -> Self {
+        Self {
+            remote_writer_guid,
+            remote_group_entity_id,
+            unicast_locator_list,
+            multicast_locator_list,
+            data_max_size_serialized,
+            available_changes_max: SequenceNumber::default(),
+            highest_announced: SequenceNumber::default(),
+            changes_from_writer: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            fragmented: BTreeMap::new(),
+            samples_lost: 0,
+            unreported_lost: 0,
+            last_heartbeat_count: None,
+            last_heartbeat_frag_count: None,
+            last_liveliness_assertion: None,
+            acknack_count: 0,
+            nack_frag_count: 0,
+            acknack_at: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn remote_writer_guid(&self) -> Guid {
+        self.remote_writer_guid
+    }
+
+    #[must_use]
+    pub const fn remote_group_entity_id(&self) -> EntityId {
+        self.remote_group_entity_id
+    }
+
+    #[must_use]
+    pub fn unicast_locator_list(&self) -> &[Locator] {
+        &self.unicast_locator_list
+    }
+
+    #[must_use]
+    pub fn multicast_locator_list(&self) -> &[Locator] {
+        &self.multicast_locator_list
+    }
+
+    #[must_use]
+    pub const fn data_max_size_serialized(&self) -> i32 {
+        self.data_max_size_serialized
+    }
+
+    /// When the writer last asserted its liveliness with a HEARTBEAT.
+    #[must_use]
+    pub const fn last_liveliness_assertion(&self) -> Option<Time> {
+        self.last_liveliness_assertion
+    }
+
+    /// The locator ACKNACKs are sent to: the first unicast locator, or the
+    /// first multicast locator for writers without a unicast one.
+    fn locator(&self) -> Option<Locator> {
+        self.unicast_locator_list
+            .first()
+            .or_else(|| self.multicast_locator_list.first())
+            .copied()
+    }
+
+    /// The highest sequence number such that every change up to it has been
+    /// received or is not available.
+    #[must_use]
+    pub const fn available_changes_max(&self) -> SequenceNumber {
+        self.available_changes_max
+    }
+
+    /// The number of changes of the writer that were lost.
+    #[must_use]
+    pub const fn samples_lost(&self) -> u64 {
+        self.samples_lost
+    }
+
+    /// The status of the change with `sequence_number`, or `None` for
+    /// changes at or below `available_changes_max`, which are settled and no
+    /// longer tracked.
+    #[must_use]
+    pub fn change_from_writer(
+        &self,
+        sequence_number: SequenceNumber,
+    ) -> Option<ChangeFromWriterStatusKind> {
+        if sequence_number <= self.available_changes_max {
+            return None;
+        }
+        Some(match self.changes_from_writer.get(&sequence_number) {
+            Some(status) => *status,
+            None if sequence_number <= self.highest_announced => {
+                ChangeFromWriterStatusKind::Missing
+            }
+            None => ChangeFromWriterStatusKind::Unknown,
+        })
+    }
+
+    /// Marks the change with `sequence_number` as not relevant to the reader.
+    /// The last sequence number is ignored, as no change can follow it.
+    pub fn irrelevant_change_set(&mut self, sequence_number: SequenceNumber) {
+        if sequence_number > self.available_changes_max && successor(sequence_number).is_some() {
+            self.fragmented.remove(&sequence_number);
+            self.changes_from_writer.insert(
+                sequence_number,
+                ChangeFromWriterStatusKind::NotAvailableFiltered,
+            );
+            self.advance();
+        }
+    }
+
+    /// Marks every change below `first_available_seq_num` that was not
+    /// received as removed by the writer, and counts them as lost.
+    pub fn lost_changes_update(&mut self, first_available_seq_num: SequenceNumber) {
+        let lost = self.settle_before(first_available_seq_num);
+        self.samples_lost += lost;
+        self.unreported_lost += lost;
+    }
+
+    /// Settles every change below `first_sn` and returns the number of them
+    /// that were neither received nor declared irrelevant.
+    fn settle_before(&mut self, first_sn: SequenceNumber) -> u64 {
+        if successor(self.available_changes_max).is_none_or(|next| first_sn <= next) {
+            return 0;
+        }
+        // Received changes below the first available one stay pending and
+        // are released along with the watermark.
+        let above = self.changes_from_writer.split_off(&first_sn);
+        let settled = std::mem::replace(&mut self.changes_from_writer, above);
+        let unsettled = first_sn.value() - self.available_changes_max.value() - 1;
+        self.available_changes_max = SequenceNumber::from(first_sn.value() - 1);
+        self.advance();
+        unsettled - settled.len() as u64
+    }
+
+    /// Marks every unknown change up to `last_available_seq_num` as missing.
+    pub fn missing_changes_update(&mut self, last_available_seq_num: SequenceNumber) {
+        self.highest_announced = self.highest_announced.max(last_available_seq_num);
+    }
+
+    /// Records `change` as received. Duplicates, and a change with the last
+    /// sequence number, are ignored.
+    pub fn received_change_set(&mut self, change: CacheChange) {
+        let sequence_number = change.sequence_number();
+        if sequence_number <= self.available_changes_max
+            || successor(sequence_number).is_none()
+            || self.changes_from_writer.contains_key(&sequence_number)
+        {
+            return;
+        }
+        self.changes_from_writer
+            .insert(sequence_number, ChangeFromWriterStatusKind::Received);
+        self.pending.insert(sequence_number, change);
+        self.advance();
+    }
+
+    /// Adds the fragments carried by `data_frag` to the sample being
+    /// reassembled, and returns the sample once it is complete.
+    ///
+    /// Samples larger than `data_max_size_serialized` are dropped. At most
+    /// [`MAX_REASSEMBLIES`] samples are reassembled at once; the one with the
+    /// highest sequence number gives way to an earlier sample, which is
+    /// delivered first, and is requested again later.
+    fn received_fragment(&mut self, data_frag: &DataFragSubmessage) -> Option<Data> {
+        let sequence_number = data_frag.writer_sn;
+        if sequence_number <= self.available_changes_max
+            || self.changes_from_writer.contains_key(&sequence_number)
+        {
+            return None;
+        }
+        if !self.fragmented.contains_key(&sequence_number)
+            && self.fragmented.len() >= MAX_REASSEMBLIES
+        {
+            let highest = self.fragmented.last_entry()?;
+            if *highest.key() < sequence_number {
+                return None;
+            }
+            highest.remove();
+        }
+        let max_sample_size = usize::try_from(self.data_max_size_serialized).unwrap_or(0);
+        let reassembly = match self.fragmented.entry(sequence_number) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Reassembly::new(data_frag, max_sample_size)?),
+        };
+        reassembly.insert(data_frag);
+        if !reassembly.is_complete() {
+            return None;
+        }
+        self.fragmented
+            .remove(&sequence_number)
+            .map(Reassembly::into_data)
+    }
+
+    fn has_missing_fragments(&self) -> bool {
+        self.fragmented
+            .values()
+            .any(|reassembly| reassembly.missing().next().is_some())
+    }
+
+    /// Sequence numbers announced by the writer that were neither received
+    /// nor declared unavailable. The last sequence number is never missing,
+    /// as a change carrying it is dropped.
+    pub fn missing_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        let last = self.highest_announced.value().min(u64::MAX - 1);
+        successor(self.available_changes_max)
+            .into_iter()
+            .flat_map(move |next| next.value()..=last)
+            .map(SequenceNumber::from)
+            .filter(|sn| !self.changes_from_writer.contains_key(sn))
     }
 
-    pub fn irrelevant_change_set(&self) {
-        todo!()
+    /// The state an ACKNACK reports: everything up to
+    /// `available_changes_max` is acknowledged and the first missing
+    /// changes are requested.
+    fn reader_sn_state(&self) -> SequenceNumberSet {
+        let base = successor(self.available_changes_max).unwrap_or(self.available_changes_max);
+        SequenceNumberSet::new(
+            base,
+            self.missing_changes()
+                .take_while(|sn| sn.value() - base.value() < SequenceNumberSet::MAX_SPAN)
+                .filter(|sn| !self.fragmented.contains_key(sn)),
+        )
     }
 
-    pub fn lost_changes_update(&self) {
-        todo!()
+    /// Moves `available_changes_max` past every contiguous change that was
+    /// received or is not available.
+    fn advance(&mut self) {
+        while let Some(next) = successor(self.available_changes_max) {
+            if self.changes_from_writer.remove(&next).is_none() {
+                break;
+            }
+            self.available_changes_max = next;
+        }
+        self.fragmented = match successor(self.available_changes_max) {
+            Some(above) => self.fragmented.split_off(&above),
+            None => BTreeMap::new(),
+        };
+        self.highest_announced = self.highest_announced.max(self.available_changes_max);
     }
 
-    pub fn missing_changes_update(&self) {
-        todo!()
+    /// Takes the received changes up to `available_changes_max`, in order.
+    fn take_available(&mut self) -> Vec<CacheChange> {
+        let held = match successor(self.available_changes_max) {
+            Some(above) => self.pending.split_off(&above),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut self.pending, held)
+            .into_values()
+            .collect()
     }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::{
+        behavior::Duration,
+        messages::FragmentNumber,
+        structure::{data::Data, participant::Endpoint, ReliabilityKind, ENTITYID_UNKNOWN},
+    };
+
+    pub(crate) const WRITER_PREFIX: GuidPrefix = [1; 12];
+    pub(crate) const WRITER_ID: EntityId = EntityId::new([0, 0, 1], 0x02);
+
+    pub(crate) fn reader(reliability_level: ReliabilityKind) -> StatefulReader {
+        let guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let mut reader = StatefulReader::new(Reader::new(
+            Endpoint::builder(guid)
+                .reliability_level(reliability_level)
+                .build(),
+            false,
+            Duration::from_millis(100),
+        ));
+        reader.matched_writer_add(WriterProxy::new(
+            Guid::new(WRITER_PREFIX, WRITER_ID),
+            ENTITYID_UNKNOWN,
+            vec!["127.0.0.1:7410".parse().unwrap()],
+            vec![],
+            i32::MAX,
+        ));
+        reader
+    }
+
+    pub(crate) fn data(sn: u64) -> DataSubmessage {
+        DataSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: WRITER_ID,
+            writer_sn: SequenceNumber::from(sn),
+            kind: ChangeKind::Alive,
+            source_timestamp: None,
+            serialized_payload: Some(Data::from(vec![sn as u8])),
+        }
+    }
+
+    pub(crate) fn heartbeat(
+        first: u64,
+        last: u64,
+        count: Count,
+        final_flag: bool,
+    ) -> HeartbeatSubmessage {
+        HeartbeatSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: WRITER_ID,
+            first_sn: SequenceNumber::from(first),
+            last_sn: SequenceNumber::from(last),
+            count,
+            final_flag,
+            liveliness_flag: false,
+        }
+    }
+
+    pub(crate) fn cached(reader: &StatefulReader) -> Vec<u64> {
+        reader
+            .reader()
+            .reader_cache()
+            .changes()
+            .map(|c| c.sequence_number().value())
+            .collect()
+    }
+
+    fn acknacks(outgoing: &[Outgoing]) -> Vec<(u64, Vec<u64>, bool)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::AckNack(acknack) => Some((
+                    acknack.reader_sn_state.base().value(),
+                    acknack
+                        .reader_sn_state
+                        .iter()
+                        .map(|sn| sn.value())
+                        .collect(),
+                    acknack.final_flag,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn writer_proxy(reader: &StatefulReader) -> &WriterProxy {
+        reader
+            .matched_writer_lookup(&Guid::new(WRITER_PREFIX, WRITER_ID))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_in_order_release() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let now = Time::new(0, 0);
+        reader.on_data(WRITER_PREFIX, &data(2), now).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(3), now).unwrap();
+        assert!(cached(&reader).is_empty());
+        assert_eq!(
+            writer_proxy(&reader).change_from_writer(SequenceNumber::from(1)),
+            Some(ChangeFromWriterStatusKind::Unknown)
+        );
+
+        reader.on_data(WRITER_PREFIX, &data(1), now).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(2), now).unwrap();
+        assert_eq!(cached(&reader), vec![1, 2, 3]);
+        assert_eq!(
+            writer_proxy(&reader).available_changes_max(),
+            SequenceNumber::from(3)
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_acknack() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let now = Time::new(0, 0);
+        reader.on_data(WRITER_PREFIX, &data(1), now).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(3), now).unwrap();
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 4, 1, false), now)
+            .unwrap();
+        assert_eq!(
+            writer_proxy(&reader).change_from_writer(SequenceNumber::from(2)),
+            Some(ChangeFromWriterStatusKind::Missing)
+        );
+
+        // The response waits for heartbeat_response_delay.
+        let respond_at = now + Duration::from_millis(100);
+        assert_eq!(reader.next_deadline(), Some(respond_at));
+        assert!(reader.poll(now).is_empty());
+        let out = reader.poll(respond_at);
+        assert_eq!(acknacks(&out), vec![(2, vec![2, 4], false)]);
+        assert_eq!(out[0].destination, WRITER_PREFIX);
+
+        // A stale heartbeat is ignored.
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 4, 1, false), respond_at)
+            .unwrap();
+        assert_eq!(reader.next_deadline(), None);
+
+        reader.on_data(WRITER_PREFIX, &data(2), respond_at).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(4), respond_at).unwrap();
+        assert_eq!(cached(&reader), vec![1, 2, 3, 4]);
+
+        // A final heartbeat with nothing missing needs no response.
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 4, 2, true), respond_at)
+            .unwrap();
+        assert_eq!(reader.next_deadline(), None);
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 4, 3, false), respond_at)
+            .unwrap();
+        let out = reader.poll(respond_at + Duration::from_millis(100));
+        assert_eq!(acknacks(&out), vec![(5, vec![], true)]);
+    }
+
+    #[test]
+    fn test_best_effort() {
+        let mut reader = reader(ReliabilityKind::BestEffort);
+        let now = Time::new(0, 0);
+        for sn in [2, 1, 4, 4, 3, 5] {
+            reader.on_data(WRITER_PREFIX, &data(sn), now).unwrap();
+        }
+        assert_eq!(cached(&reader), vec![2, 4, 5]);
+
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 7, 1, false), now)
+            .unwrap();
+        assert_eq!(reader.next_deadline(), None);
+
+        // Changes from unmatched writers are dropped.
+        reader.on_data([9; 12], &data(6), now).unwrap();
+        assert_eq!(cached(&reader), vec![2, 4, 5]);
+    }
+
+    pub(crate) fn data_frag(sn: u64, fragment: FragmentNumber) -> DataFragSubmessage {
+        let sample: Vec<u8> = (0..10).collect();
+        let start = (fragment as usize - 1) * 4;
+        DataFragSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: WRITER_ID,
+            writer_sn: SequenceNumber::from(sn),
+            fragment_starting_num: fragment,
+            fragments_in_submessage: 1,
+            fragment_size: 4,
+            sample_size: 10,
+            source_timestamp: None,
+            serialized_payload: Data::from(&sample[start..sample.len().min(start + 4)]),
+        }
+    }
+
+    #[test]
+    fn test_fragment_repair() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let now = Time::new(0, 0);
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 1), now)
+            .unwrap();
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 3), now)
+            .unwrap();
+        assert!(cached(&reader).is_empty());
+
+        // The HEARTBEAT makes every fragment of 1 available and announces 2.
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(1, 2, 1, true), now)
+            .unwrap();
+        let out = reader.poll(now + Duration::from_millis(100));
+        assert_eq!(acknacks(&out), vec![(1, vec![2], false)]);
+        let nack_frags: Vec<_> = out
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::NackFrag(nack_frag) => Some((
+                    nack_frag.writer_sn.value(),
+                    nack_frag.fragment_number_state.iter().collect::<Vec<_>>(),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nack_frags, vec![(1, vec![2])]);
+
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 2), now)
+            .unwrap();
+        assert_eq!(cached(&reader), vec![1]);
+        assert_eq!(
+            reader
+                .reader()
+                .reader_cache()
+                .changes()
+                .next()
+                .unwrap()
+                .data_value(),
+            Some(&Data::from((0..10).collect::<Vec<u8>>()))
+        );
+        // Late fragments of a released change are ignored.
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 2), now)
+            .unwrap();
+        assert!(writer_proxy(&reader).fragmented.is_empty());
+    }
+
+    #[test]
+    fn test_fragment_limits() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let now = Time::new(0, 0);
+        let writer_guid = Guid::new(WRITER_PREFIX, WRITER_ID);
+        let locators = vec!["127.0.0.1:7410".parse().unwrap()];
+        // Samples larger than the writer's maximum are dropped.
+        reader.matched_writer_add(WriterProxy::new(
+            writer_guid,
+            ENTITYID_UNKNOWN,
+            locators.clone(),
+            vec![],
+            8,
+        ));
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 1), now)
+            .unwrap();
+        assert!(writer_proxy(&reader).fragmented.is_empty());
+
+        // Earlier samples take precedence once too many are reassembled.
+        reader.matched_writer_add(WriterProxy::new(
+            writer_guid,
+            ENTITYID_UNKNOWN,
+            locators,
+            vec![],
+            i32::MAX,
+        ));
+        let last = 2 + MAX_REASSEMBLIES as u64;
+        for sn in (2..=last).chain([1]) {
+            reader
+                .on_data_frag(WRITER_PREFIX, &data_frag(sn, 1), now)
+                .unwrap();
+        }
+        let fragmented: Vec<_> = writer_proxy(&reader)
+            .fragmented
+            .keys()
+            .map(|sn| sn.value())
+            .collect();
+        assert_eq!(fragmented, (1..last - 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_gap_and_lost_changes() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let now = Time::new(0, 0);
+        reader.on_data(WRITER_PREFIX, &data(3), now).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(6), now).unwrap();
+        reader
+            .on_gap(
+                WRITER_PREFIX,
+                &GapSubmessage {
+                    reader_id: ENTITYID_UNKNOWN,
+                    writer_id: WRITER_ID,
+                    gap_start: SequenceNumber::from(4),
+                    gap_list: SequenceNumberSet::new(
+                        SequenceNumber::from(5),
+                        [SequenceNumber::from(5)],
+                    ),
+                    gap_start_gsn: None,
+                    gap_end_gsn: None,
+                },
+            )
+            .unwrap();
+        assert!(cached(&reader).is_empty());
+
+        // The writer no longer has 1 and 2.
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(3, 6, 1, true), now)
+            .unwrap();
+        assert_eq!(cached(&reader), vec![3, 6]);
+        assert_eq!(reader.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_gap_far_ahead() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let sn = SequenceNumber::from;
+        let gap = GapSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: WRITER_ID,
+            gap_start: sn(3),
+            gap_list: SequenceNumberSet::new(sn(u64::MAX / 2), [sn(u64::MAX / 2 + 1)]),
+            gap_start_gsn: None,
+            gap_end_gsn: None,
+        };
+        reader.on_gap(WRITER_PREFIX, &gap).unwrap();
+        let writer_proxy = reader
+            .matched_writer_lookup(&Guid::new(WRITER_PREFIX, WRITER_ID))
+            .unwrap();
+        assert_eq!(
+            writer_proxy.change_from_writer(sn(3)),
+            Some(ChangeFromWriterStatusKind::NotAvailableFiltered)
+        );
+        assert_eq!(
+            writer_proxy.change_from_writer(sn(256)),
+            Some(ChangeFromWriterStatusKind::NotAvailableFiltered)
+        );
+        assert_eq!(
+            writer_proxy.change_from_writer(sn(257)),
+            Some(ChangeFromWriterStatusKind::Unknown)
+        );
+
+        // Once it continues the settled changes, the range is settled at once.
+        let now = Time::new(0, 0);
+        reader.on_data(WRITER_PREFIX, &data(1), now).unwrap();
+        reader.on_data(WRITER_PREFIX, &data(2), now).unwrap();
+        reader.on_gap(WRITER_PREFIX, &gap).unwrap();
+        let writer_proxy = reader
+            .matched_writer_lookup(&Guid::new(WRITER_PREFIX, WRITER_ID))
+            .unwrap();
+        assert_eq!(writer_proxy.available_changes_max(), sn(u64::MAX / 2 - 1));
+        assert_eq!(
+            writer_proxy.change_from_writer(sn(u64::MAX / 2 + 1)),
+            Some(ChangeFromWriterStatusKind::NotAvailableFiltered)
+        );
+        assert_eq!(cached(&reader), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_last_sequence_number() {
+        let now = Time::new(0, 0);
+        for reliability_level in [ReliabilityKind::Reliable, ReliabilityKind::BestEffort] {
+            let mut reader = reader(reliability_level);
+            assert!(reader
+                .on_data(WRITER_PREFIX, &data(u64::MAX), now)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                writer_proxy(&reader).available_changes_max(),
+                SequenceNumber::from(0)
+            );
+            reader.on_data(WRITER_PREFIX, &data(1), now).unwrap();
+            assert_eq!(cached(&reader), vec![1]);
+        }
+
+        // Neither a GAP nor a HEARTBEAT reaching it settles the last
+        // sequence number.
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let gap = GapSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: WRITER_ID,
+            gap_start: SequenceNumber::from(1),
+            gap_list: SequenceNumberSet::new(SequenceNumber::from(u64::MAX), []),
+            gap_start_gsn: None,
+            gap_end_gsn: None,
+        };
+        reader.on_gap(WRITER_PREFIX, &gap).unwrap();
+        reader
+            .on_heartbeat(WRITER_PREFIX, &heartbeat(u64::MAX, u64::MAX, 1, false), now)
+            .unwrap();
+        assert_eq!(
+            writer_proxy(&reader).available_changes_max(),
+            SequenceNumber::from(u64::MAX - 1)
+        );
+        let out = reader.poll(now + Duration::from_millis(100));
+        assert_eq!(acknacks(&out), vec![(u64::MAX, vec![], true)]);
+    }
+
+    #[test]
+    fn test_samples_lost() {
+        let now = Time::new(0, 0);
+        // A best-effort reader loses the changes it skips.
+        let mut best_effort = reader(ReliabilityKind::BestEffort);
+        for sn in [2, 1, 5] {
+            best_effort.on_data(WRITER_PREFIX, &data(sn), now).unwrap();
+        }
+        assert_eq!(writer_proxy(&best_effort).samples_lost(), 3);
+
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let received = |submessage| Input::Received {
+            source: WRITER_PREFIX,
+            source_locator: "127.0.0.1:7410".parse().unwrap(),
+            submessage,
+        };
+        reader
+            .handle(received(RtpsSubmessage::Data(data(3))), now)
+            .unwrap();
+        reader
+            .handle(received(RtpsSubmessage::Data(data(5))), now)
+            .unwrap();
 
-    pub fn received_change_set(&self) {
-        todo!()
+        // The writer removed 1 and 2 before they were received, and 4 is
+        // not relevant, which is not a loss.
+        let outputs = reader
+            .handle(
+                received(RtpsSubmessage::Gap(GapSubmessage {
+                    reader_id: ENTITYID_UNKNOWN,
+                    writer_id: WRITER_ID,
+                    gap_start: SequenceNumber::from(4),
+                    gap_list: SequenceNumberSet::new(SequenceNumber::from(5), []),
+                    gap_start_gsn: None,
+                    gap_end_gsn: None,
+                })),
+                now,
+            )
+            .unwrap();
+        assert!(outputs.is_empty());
+        let outputs = reader
+            .handle(
+                received(RtpsSubmessage::Heartbeat(heartbeat(3, 5, 1, true))),
+                now,
+            )
+            .unwrap();
+        assert_eq!(
+            outputs,
+            vec![
+                Output::Deliver(change_from_data(
+                    Guid::new(WRITER_PREFIX, WRITER_ID),
+                    &data(3),
+                    now
+                )),
+                Output::Deliver(change_from_data(
+                    Guid::new(WRITER_PREFIX, WRITER_ID),
+                    &data(5),
+                    now
+                )),
+                Output::SamplesLost {
+                    writer: Guid::new(WRITER_PREFIX, WRITER_ID),
+                    count: 2,
+                },
+            ]
+        );
+        assert_eq!(writer_proxy(&reader).samples_lost(), 2);
     }
 
-    pub fn missing_changes(&self) {
-        todo!()
+    #[test]
+    fn test_participant_removed() {
+        let mut reader = reader(ReliabilityKind::Reliable);
+        let other = Guid::new([3; 12], WRITER_ID);
+        reader.matched_writer_add(WriterProxy::new(
+            other,
+            ENTITYID_UNKNOWN,
+            vec!["127.0.0.1:7411".parse().unwrap()],
+            vec![],
+            i32::MAX,
+        ));
+        reader
+            .handle(Input::ParticipantRemoved(WRITER_PREFIX), Time::new(0, 0))
+            .unwrap();
+        let matched: Vec<_> = reader
+            .matched_writers()
+            .iter()
+            .map(|w| w.remote_writer_guid)
+            .collect();
+        assert_eq!(matched, vec![other]);
     }
 }
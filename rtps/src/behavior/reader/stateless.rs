@@ -1,7 +1,381 @@
-use super::Reader;
+//! The RTPS StatelessReader keeps no state about the writers it receives
+//! from and accepts DATA from any of them.
+//!
+//! See Sections 8.4.10.3 and 8.4.11 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
 
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+};
+
+use crate::{
+    behavior::{outputs, Behavior, Input, Output},
+    messages::{DataFragSubmessage, DataSubmessage, RtpsSubmessage, Time},
+    structure::{historycache::CacheChange, ChangeKind, Guid, GuidPrefix, SequenceNumber},
+};
+
+use super::{stateful::change_from_data, Reader, Reassembly, MAX_REASSEMBLIES};
+
+/// The largest sample a StatelessReader reassembles from DATA_FRAG
+/// submessages unless configured otherwise.
+pub const DEFAULT_DATA_MAX_SIZE_SERIALIZED: i32 = 1 << 20;
+
+/// A reader that accepts DATA and DATA_FRAG from any writer.
+///
+/// Each writer has at most one sample reassembled from DATA_FRAG at a time,
+/// for at most [`MAX_REASSEMBLIES`] writers at once. A later sample from the
+/// same writer replaces the incomplete one, which is lost as no fragment is
+/// ever repaired.
+#[derive(Debug)]
 pub struct StatelessReader {
     reader: Reader,
+    data_max_size_serialized: i32,
+
+    /// Highest sequence number received from each writer, used to drop
+    /// duplicates and changes older than ones already received.
+    highest_received: HashMap<Guid, SequenceNumber>,
+    /// The sample being reassembled for each writer.
+    fragmented: HashMap<Guid, (SequenceNumber, Reassembly)>,
+}
+
+impl StatelessReader {
+    #[must_use]
+    pub fn new(reader: Reader) -> Self {
+        Self {
+            reader,
+            data_max_size_serialized: DEFAULT_DATA_MAX_SIZE_SERIALIZED,
+            highest_received: HashMap::new(),
+            fragmented: HashMap::new(),
+        }
+    }
+
+    /// Drops samples sent as DATA_FRAG that are larger than
+    /// `data_max_size_serialized` bytes.
+    #[must_use]
+    pub const fn with_data_max_size_serialized(mut self, data_max_size_serialized: i32) -> Self {
+        self.data_max_size_serialized = data_max_size_serialized;
+        self
+    }
+
+    #[must_use]
+    pub const fn data_max_size_serialized(&self) -> i32 {
+        self.data_max_size_serialized
+    }
+
+    #[must_use]
+    pub const fn reader(&self) -> &Reader {
+        &self.reader
+    }
+
+    pub fn reader_mut(&mut self) -> &mut Reader {
+        &mut self.reader
+    }
+
+    /// The highest sequence number received from `writer_guid`.
+    #[must_use]
+    pub fn highest_received(&self, writer_guid: &Guid) -> Option<SequenceNumber> {
+        self.highest_received.get(writer_guid).copied()
+    }
+
+    /// Forgets the writers of the participant `guid_prefix`, so that its
+    /// changes are received again should it come back with the same prefix.
+    pub fn remove_participant(&mut self, guid_prefix: GuidPrefix) {
+        self.highest_received
+            .retain(|writer_guid, _| writer_guid.guid_prefix() != guid_prefix);
+        self.fragmented
+            .retain(|writer_guid, _| writer_guid.guid_prefix() != guid_prefix);
+    }
+
+    /// Handles a DATA sent by a writer of the participant `source`. Only the
+    /// best-effort behavior of Section 8.4.11.1 is defined: the change is
+    /// added to the reader's cache unless a change with the same or a higher
+    /// sequence number was already received from that writer.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store the change.
+    pub fn on_data(
+        &mut self,
+        source: GuidPrefix,
+        data: &DataSubmessage,
+        now: Time,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, data.writer_id);
+        if self
+            .highest_received
+            .get(&writer_guid)
+            .is_some_and(|highest| data.writer_sn <= *highest)
+        {
+            return Ok(Vec::new());
+        }
+        let change = change_from_data(writer_guid, data, now);
+        self.reader.reader_cache.add_change(change.clone())?;
+        self.highest_received.insert(writer_guid, data.writer_sn);
+        if let Entry::Occupied(entry) = self.fragmented.entry(writer_guid) {
+            if entry.get().0 <= data.writer_sn {
+                entry.remove();
+            }
+        }
+        Ok(vec![change])
+    }
+
+    /// Handles a DATA_FRAG sent by a writer of the participant `source`.
+    /// Once every fragment of a sample has arrived, the sample is handled
+    /// like a DATA carrying it.
+    ///
+    /// Returns the changes added to the reader's cache, in order.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store the change.
+    pub fn on_data_frag(
+        &mut self,
+        source: GuidPrefix,
+        data_frag: &DataFragSubmessage,
+        now: Time,
+    ) -> io::Result<Vec<CacheChange>> {
+        let writer_guid = Guid::new(source, data_frag.writer_id);
+        let sequence_number = data_frag.writer_sn;
+        if self
+            .highest_received
+            .get(&writer_guid)
+            .is_some_and(|highest| sequence_number <= *highest)
+        {
+            return Ok(Vec::new());
+        }
+        let max_sample_size = usize::try_from(self.data_max_size_serialized).unwrap_or(0);
+        let full = self.fragmented.len() >= MAX_REASSEMBLIES;
+        let (in_progress, reassembly) = match self.fragmented.entry(writer_guid) {
+            Entry::Occupied(entry) if entry.get().0 >= sequence_number => entry.into_mut(),
+            Entry::Occupied(mut entry) => {
+                let Some(reassembly) = Reassembly::new(data_frag, max_sample_size) else {
+                    return Ok(Vec::new());
+                };
+                entry.insert((sequence_number, reassembly));
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) if !full => {
+                let Some(reassembly) = Reassembly::new(data_frag, max_sample_size) else {
+                    return Ok(Vec::new());
+                };
+                entry.insert((sequence_number, reassembly))
+            }
+            Entry::Vacant(_) => return Ok(Vec::new()),
+        };
+        if *in_progress != sequence_number {
+            return Ok(Vec::new());
+        }
+        reassembly.insert(data_frag);
+        if !reassembly.is_complete() {
+            return Ok(Vec::new());
+        }
+        let Some((_, reassembly)) = self.fragmented.remove(&writer_guid) else {
+            return Ok(Vec::new());
+        };
+        let data = DataSubmessage {
+            reader_id: data_frag.reader_id,
+            writer_id: data_frag.writer_id,
+            writer_sn: sequence_number,
+            kind: ChangeKind::Alive,
+            source_timestamp: data_frag.source_timestamp,
+            serialized_payload: Some(reassembly.into_data()),
+        };
+        self.on_data(source, &data, now)
+    }
 }
 
-impl StatelessReader {}
+impl Behavior for StatelessReader {
+    fn guid(&self) -> Guid {
+        self.reader.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        let delivered = match input {
+            Input::Received {
+                source,
+                submessage: RtpsSubmessage::Data(data),
+                ..
+            } => self.on_data(source, &data, now)?,
+            Input::Received {
+                source,
+                submessage: RtpsSubmessage::DataFrag(data_frag),
+                ..
+            } => self.on_data_frag(source, &data_frag, now)?,
+            Input::ParticipantRemoved(guid_prefix) => {
+                self.remove_participant(guid_prefix);
+                Vec::new()
+            }
+            Input::Received { .. } | Input::NewChange(_) | Input::Flush | Input::TimerFired => {
+                Vec::new()
+            }
+        };
+        self.reader.remove_expired(now)?;
+        Ok(outputs(
+            delivered,
+            Vec::new(),
+            self.reader.expiry_deadline(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{
+            reader::stateful::tests::{data, data_frag, WRITER_ID, WRITER_PREFIX},
+            Duration,
+        },
+        structure::{data::Data, participant::Endpoint, EntityId},
+    };
+
+    #[test]
+    fn test_drop_duplicates_per_writer() {
+        let guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let mut reader = StatelessReader::new(Reader::new(
+            Endpoint::builder(guid).build(),
+            false,
+            Duration::ZERO,
+        ));
+        let now = Time::new(0, 0);
+        for (source, sn) in [
+            (WRITER_PREFIX, 2),
+            (WRITER_PREFIX, 1),
+            ([3; 12], 1),
+            (WRITER_PREFIX, 2),
+            (WRITER_PREFIX, 5),
+        ] {
+            reader.on_data(source, &data(sn), now).unwrap();
+        }
+        let cached: Vec<_> = reader
+            .reader()
+            .reader_cache()
+            .changes()
+            .map(|c| (c.writer_guid().guid_prefix(), c.sequence_number().value()))
+            .collect();
+        assert_eq!(
+            cached,
+            vec![(WRITER_PREFIX, 2), (WRITER_PREFIX, 5), ([3; 12], 1)]
+        );
+        assert_eq!(
+            reader.highest_received(&Guid::new(WRITER_PREFIX, WRITER_ID)),
+            Some(SequenceNumber::from(5))
+        );
+    }
+
+    #[test]
+    fn test_data_frag() {
+        let guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let mut reader = StatelessReader::new(Reader::new(
+            Endpoint::builder(guid).build(),
+            false,
+            Duration::ZERO,
+        ));
+        let now = Time::new(0, 0);
+        let cached = |reader: &StatelessReader| -> Vec<_> {
+            reader
+                .reader()
+                .reader_cache()
+                .changes()
+                .map(|c| (c.sequence_number().value(), c.data_value().cloned()))
+                .collect()
+        };
+        let sample = Some(Data::from((0..10).collect::<Vec<u8>>()));
+        for fragment in [3, 1, 1] {
+            reader
+                .on_data_frag(WRITER_PREFIX, &data_frag(1, fragment), now)
+                .unwrap();
+        }
+        assert!(cached(&reader).is_empty());
+        let delivered = reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 2), now)
+            .unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(cached(&reader), vec![(1, sample.clone())]);
+
+        // A later sample replaces the incomplete one, which is lost.
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(2, 1), now)
+            .unwrap();
+        for fragment in 1..=3 {
+            reader
+                .on_data_frag(WRITER_PREFIX, &data_frag(3, fragment), now)
+                .unwrap();
+        }
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(2, 2), now)
+            .unwrap();
+        assert_eq!(
+            cached(&reader),
+            vec![(1, sample.clone()), (3, sample.clone())]
+        );
+
+        // Samples larger than the limit, or beyond the number reassembled at
+        // once, are dropped.
+        let mut reader = StatelessReader::new(Reader::new(
+            Endpoint::builder(guid).build(),
+            false,
+            Duration::ZERO,
+        ))
+        .with_data_max_size_serialized(9);
+        reader
+            .on_data_frag(WRITER_PREFIX, &data_frag(1, 1), now)
+            .unwrap();
+        assert!(reader.fragmented.is_empty());
+        let mut reader = StatelessReader::new(Reader::new(
+            Endpoint::builder(guid).build(),
+            false,
+            Duration::ZERO,
+        ));
+        for key in 0..=MAX_REASSEMBLIES {
+            reader
+                .on_data_frag([key as u8; 12], &data_frag(1, 1), now)
+                .unwrap();
+        }
+        assert_eq!(reader.fragmented.len(), MAX_REASSEMBLIES);
+    }
+
+    #[test]
+    fn test_lifespan() {
+        let guid = Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07));
+        let lifespan = Duration::from_secs(1);
+        let mut reader = StatelessReader::new(
+            Reader::new(Endpoint::builder(guid).build(), false, Duration::ZERO)
+                .with_lifespan(lifespan),
+        );
+        let received = |data| Input::Received {
+            source: WRITER_PREFIX,
+            source_locator: "127.0.0.1:7410".parse().unwrap(),
+            submessage: RtpsSubmessage::Data(data),
+        };
+        let now = Time::new(10, 0);
+        // Lifespan is measured from the source timestamp if there is one.
+        let stamped = DataSubmessage {
+            source_timestamp: Some(Time::new(9, 1 << 31)),
+            ..data(1)
+        };
+        reader.handle(received(stamped), now).unwrap();
+        let outputs = reader.handle(received(data(2)), now).unwrap();
+        assert_eq!(
+            outputs.last(),
+            Some(&Output::ArmTimer(Time::new(10, 1 << 31)))
+        );
+
+        let outputs = reader
+            .handle(Input::TimerFired, Time::new(10, 1 << 31))
+            .unwrap();
+        assert_eq!(outputs, vec![Output::ArmTimer(now + lifespan)]);
+        let cached: Vec<_> = reader
+            .reader()
+            .reader_cache()
+            .changes()
+            .map(|c| c.sequence_number().value())
+            .collect();
+        assert_eq!(cached, vec![2]);
+
+        reader.handle(Input::TimerFired, now + lifespan).unwrap();
+        assert!(reader.reader().reader_cache().is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+//! Timekeeping for the scheduler driving the writers and readers.
+//!
+//! Endpoints only ever report their next deadline (see
+//! [`Output::ArmTimer`](super::Output::ArmTimer)). A [`TimerQueue`] keeps one
+//! deadline per endpoint, or per any other key such as a participant lease,
+//! and yields them in order as a [`Clock`] advances.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use crate::messages::Time;
+
+use super::Duration;
+
+/// A source of the current time.
+pub trait Clock {
+    fn now(&self) -> Time;
+}
+
+/// The system's wall clock.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Time {
+        Time::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one while the scheduler under test owns another.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Time>>,
+}
+
+impl ManualClock {
+    #[must_use]
+    pub fn new(now: Time) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: Time) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(Time::new(0, 0))
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Time {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// At most one deadline per key, ordered by time. Scheduling, rescheduling
+/// and cancelling are `O(log n)`.
+pub struct TimerQueue<K> {
+    /// Deadlines in firing order. The sequence number keeps deadlines
+    /// distinct and fires equal ones in the order they were scheduled.
+    deadlines: BTreeMap<(Time, u64), K>,
+    keys: HashMap<K, (Time, u64)>,
+    next_sequence: u64,
+}
+
+impl<K: Clone + Eq + Hash> TimerQueue<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            deadlines: BTreeMap::new(),
+            keys: HashMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Arms the timer of `key` for `deadline`, replacing its previous
+    /// deadline, which is returned.
+    pub fn schedule(&mut self, key: K, deadline: Time) -> Option<Time> {
+        let previous = self.cancel(&key);
+        let entry = (deadline, self.next_sequence);
+        self.next_sequence += 1;
+        self.deadlines.insert(entry, key.clone());
+        self.keys.insert(key, entry);
+        previous
+    }
+
+    /// Disarms the timer of `key` and returns its deadline.
+    pub fn cancel(&mut self, key: &K) -> Option<Time> {
+        let entry = self.keys.remove(key)?;
+        self.deadlines.remove(&entry);
+        Some(entry.0)
+    }
+
+    #[must_use]
+    pub fn deadline(&self, key: &K) -> Option<Time> {
+        self.keys.get(key).map(|(deadline, _)| *deadline)
+    }
+
+    /// The earliest deadline.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Time> {
+        self.deadlines.keys().next().map(|(deadline, _)| *deadline)
+    }
+
+    /// Disarms and returns the earliest timer if it is due at `now`.
+    pub fn pop_expired(&mut self, now: Time) -> Option<(K, Time)> {
+        let entry = self.deadlines.first_entry()?;
+        if entry.key().0 > now {
+            return None;
+        }
+        let ((deadline, _), key) = entry.remove_entry();
+        self.keys.remove(&key);
+        Some((key, deadline))
+    }
+
+    /// Disarms and returns the keys of every timer due at `now`, earliest
+    /// first.
+    pub fn expired(&mut self, now: Time) -> Vec<K> {
+        std::iter::from_fn(|| self.pop_expired(now))
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+impl<K: Clone + Eq + Hash> Default for TimerQueue<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for TimerQueue<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.deadlines
+                    .iter()
+                    .map(|((deadline, _), key)| (key, deadline)),
+            )
+            .finish()
+    }
+}
+
+/// Randomly shortens a period by up to a maximum, so that endpoints created
+/// together do not send their periodic messages in lockstep. The sequence is
+/// pseudo-random and reproducible from its seed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Jitter {
+    max: Duration,
+    state: u64,
+}
+
+impl Jitter {
+    #[must_use]
+    pub const fn new(max: Duration, seed: u64) -> Self {
+        Self {
+            max,
+            // xorshift gets stuck on zero.
+            state: if seed == 0 {
+                0x9e37_79b9_7f4a_7c15
+            } else {
+                seed
+            },
+        }
+    }
+
+    #[must_use]
+    pub const fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// `period` shortened by a random duration in `[0, max]`, never below
+    /// zero.
+    pub fn apply(&mut self, period: Duration) -> Duration {
+        let max = self.max.min(period);
+        let nanos = u64::try_from(max.as_nanos()).unwrap_or(u64::MAX);
+        if nanos == 0 {
+            return period;
+        }
+        let offset = self.next() % (nanos + 1);
+        period - Duration::from_nanos(offset)
+    }
+
+    /// Next value of the xorshift64* generator.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> Time {
+        Time::from(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_timer_queue() {
+        let mut timers = TimerQueue::new();
+        assert_eq!(timers.schedule("heartbeat", at(30)), None);
+        timers.schedule("nack", at(10));
+        timers.schedule("lease", at(20));
+        assert_eq!(timers.next_deadline(), Some(at(10)));
+
+        // Rescheduling replaces the deadline, cancelling removes it.
+        assert_eq!(timers.schedule("nack", at(40)), Some(at(10)));
+        assert_eq!(timers.cancel(&"lease"), Some(at(20)));
+        assert_eq!(timers.cancel(&"lease"), None);
+        assert_eq!(timers.len(), 2);
+
+        assert_eq!(timers.pop_expired(at(29)), None);
+        assert_eq!(timers.expired(at(40)), vec!["heartbeat", "nack"]);
+        assert!(timers.is_empty());
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_timer_queue_many_keys() {
+        let mut timers = TimerQueue::new();
+        for key in 0..10_000u64 {
+            timers.schedule(key, at(key % 100));
+        }
+        for key in (0..10_000).step_by(2) {
+            timers.cancel(&key);
+        }
+        let expired = timers.expired(at(50));
+        assert_eq!(expired.len(), 2_500);
+        // Equal deadlines fire in scheduling order.
+        assert_eq!(&expired[..3], &[1, 101, 201]);
+        assert!(expired.windows(2).all(|w| w[0] % 100 <= w[1] % 100));
+        assert_eq!(timers.next_deadline(), Some(at(51)));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let period = Duration::from_secs(1);
+        let max = Duration::from_millis(100);
+        let mut jitter = Jitter::new(max, 7);
+        let periods: Vec<_> = (0..100).map(|_| jitter.apply(period)).collect();
+        assert!(periods.iter().all(|p| *p <= period && *p >= period - max));
+        assert!(periods.windows(2).any(|w| w[0] != w[1]));
+
+        let mut same_seed = Jitter::new(max, 7);
+        assert!(periods.iter().all(|p| *p == same_seed.apply(period)));
+        assert_eq!(
+            Jitter::new(Duration::ZERO, 7).apply(period),
+            Duration::from_secs(1)
+        );
+        assert!(Jitter::new(Duration::from_secs(5), 0).apply(period) <= period);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::default();
+        let shared = clock.clone();
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(shared.now(), Time::new(1, 1 << 31));
+        shared.set(Time::new(5, 0));
+        assert_eq!(clock.now(), Time::new(5, 0));
+    }
+}
@@ -0,0 +1,130 @@
+//! Batching holds back the DATA submessages of a writer for a short while so
+//! that the transport packs those sent together into as few RTPS messages as
+//! possible. At high sample rates this saves most of the per-message
+//! overhead, at the cost of some latency.
+//!
+//! While a batch is open every submessage of the writer joins it, so that
+//! HEARTBEATs and GAPs are never sent ahead of the DATA they announce.
+
+use std::collections::HashMap;
+
+use crate::{
+    behavior::{Duration, Outgoing},
+    messages::{RtpsSubmessage, Time},
+    structure::{GuidPrefix, Locator},
+};
+
+use super::flow_control::paced_len;
+
+/// When a batch is sent: as soon as the DATA submessages for one destination
+/// amount to `max_bytes` or `max_samples`, `max_delay` after the first of
+/// them, or when the writer is flushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Batching {
+    max_bytes: usize,
+    max_samples: usize,
+    max_delay: Duration,
+}
+
+impl Batching {
+    #[must_use]
+    pub const fn new(max_bytes: usize, max_samples: usize, max_delay: Duration) -> Self {
+        Self {
+            max_bytes,
+            max_samples,
+            max_delay,
+        }
+    }
+
+    #[must_use]
+    pub const fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    #[must_use]
+    pub const fn max_samples(&self) -> usize {
+        self.max_samples
+    }
+
+    #[must_use]
+    pub const fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+}
+
+/// The submessages of an open batch.
+#[derive(Debug)]
+pub(crate) struct Batch {
+    batching: Batching,
+    pending: Vec<Outgoing>,
+    opened_at: Option<Time>,
+    flush: bool,
+}
+
+impl Batch {
+    pub(crate) const fn new(batching: Batching) -> Self {
+        Self {
+            batching,
+            pending: Vec::new(),
+            opened_at: None,
+            flush: false,
+        }
+    }
+
+    pub(crate) const fn batching(&self) -> Batching {
+        self.batching
+    }
+
+    /// Sends the batch the next time [`Batch::add`] is called.
+    pub(crate) fn flush(&mut self) {
+        self.flush = true;
+    }
+
+    /// Adds `outgoing` to the batch. Returns the submessages that must be
+    /// sent at `now`, in order: those sent while no batch was open, followed
+    /// by the whole batch if it is due.
+    pub(crate) fn add(&mut self, outgoing: Vec<Outgoing>, now: Time) -> Vec<Outgoing> {
+        let mut released = Vec::new();
+        for o in outgoing {
+            if self.pending.is_empty() && !is_batched(&o.submessage) {
+                released.push(o);
+            } else {
+                self.opened_at.get_or_insert(now);
+                self.pending.push(o);
+            }
+        }
+        let due = self.deadline().is_some_and(|deadline| deadline <= now);
+        if std::mem::take(&mut self.flush) || due || self.is_full() {
+            self.opened_at = None;
+            released.append(&mut self.pending);
+        }
+        released
+    }
+
+    /// When the open batch, if any, is sent.
+    pub(crate) fn deadline(&self) -> Option<Time> {
+        self.opened_at.map(|t| t + self.batching.max_delay)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_full(&self) -> bool {
+        let mut sizes: HashMap<(Locator, GuidPrefix), (usize, usize)> = HashMap::new();
+        for o in self.pending.iter().filter(|o| is_batched(&o.submessage)) {
+            let (bytes, samples) = sizes.entry((o.locator, o.destination)).or_default();
+            *bytes += paced_len(&o.submessage);
+            *samples += 1;
+        }
+        sizes.values().any(|(bytes, samples)| {
+            *bytes >= self.batching.max_bytes || *samples >= self.batching.max_samples
+        })
+    }
+}
+
+/// Whether `submessage` opens a batch. The DATA_FRAG submessages of large
+/// samples fill messages on their own.
+fn is_batched(submessage: &RtpsSubmessage) -> bool {
+    matches!(submessage, RtpsSubmessage::Data(_))
+}
@@ -0,0 +1,341 @@
+//! Flow controllers pace the DATA and DATA_FRAG submessages a writer sends,
+//! including repairs, so that bursts do not saturate slow links. HEARTBEAT,
+//! GAP and HEARTBEAT_FRAG submessages are small and never held back.
+//!
+//! A controller can be attached to several writers through a
+//! [`SharedFlowController`], in which case they share its budget.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    behavior::Duration,
+    messages::{RtpsSubmessage, Time},
+    structure::Guid,
+};
+
+/// Decides when the submessages of the writers it is attached to may be
+/// sent.
+pub trait FlowController: Send {
+    /// Asks to send `len` bytes for `writer`, which has the given
+    /// `priority`, at `now`. Returns `true` and consumes the budget if they
+    /// may be sent.
+    fn request(&mut self, writer: Guid, priority: i32, len: usize, now: Time) -> bool;
+
+    /// Tells the controller that `writer` has nothing left waiting.
+    fn release(&mut self, _writer: Guid) {}
+
+    /// When a request refused earlier may succeed.
+    fn next_available(&self) -> Option<Time>;
+}
+
+/// Limits the throughput to `bytes_per_second`, allowing bursts of up to
+/// `max_burst` bytes. A submessage larger than the burst is sent once the
+/// bucket is full.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenBucket {
+    bytes_per_second: u64,
+    max_burst: u64,
+    tokens: u64,
+    refilled_at: Option<Time>,
+    available_at: Option<Time>,
+}
+
+impl TokenBucket {
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_second` is zero.
+    #[must_use]
+    pub fn new(bytes_per_second: u64, max_burst: u64) -> Self {
+        assert!(bytes_per_second > 0, "rate must not be zero");
+        Self {
+            bytes_per_second,
+            max_burst,
+            tokens: max_burst,
+            refilled_at: None,
+            available_at: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    #[must_use]
+    pub const fn max_burst(&self) -> u64 {
+        self.max_burst
+    }
+
+    fn refill(&mut self, now: Time) {
+        let Some(refilled_at) = self.refilled_at else {
+            self.refilled_at = Some(now);
+            return;
+        };
+        let elapsed = now.as_duration().saturating_sub(refilled_at.as_duration());
+        let rate = u128::from(self.bytes_per_second);
+        // Converting a Time to a Duration truncates its fraction, which the
+        // extra nanosecond makes up for.
+        let added = (elapsed.as_nanos() + 1) * rate / 1_000_000_000;
+        if added == 0 {
+            return;
+        }
+        self.tokens = u64::try_from(u128::from(self.tokens) + added)
+            .unwrap_or(u64::MAX)
+            .min(self.max_burst);
+        // Only the time the added tokens took is consumed, so that the
+        // remainder counts towards the next ones.
+        self.refilled_at = Some(if self.tokens == self.max_burst {
+            now
+        } else {
+            let nanos = u64::try_from(added * 1_000_000_000 / rate).unwrap_or(u64::MAX);
+            refilled_at + Duration::from_nanos(nanos)
+        });
+    }
+}
+
+impl FlowController for TokenBucket {
+    fn request(&mut self, _writer: Guid, _priority: i32, len: usize, now: Time) -> bool {
+        self.refill(now);
+        let needed = u64::try_from(len).unwrap_or(u64::MAX).min(self.max_burst);
+        if self.tokens >= needed {
+            self.tokens -= needed;
+            self.available_at = None;
+            return true;
+        }
+        let missing = u128::from(needed - self.tokens);
+        let nanos = (missing * 1_000_000_000).div_ceil(u128::from(self.bytes_per_second));
+        let refilled_at = self.refilled_at.unwrap_or(now);
+        self.available_at =
+            Some(refilled_at + Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX)));
+        false
+    }
+
+    fn next_available(&self) -> Option<Time> {
+        self.available_at
+    }
+}
+
+/// Sends at most `max_per_period` submessages every `period`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedRate {
+    max_per_period: usize,
+    period: Duration,
+    window_start: Option<Time>,
+    sent: usize,
+    available_at: Option<Time>,
+}
+
+impl FixedRate {
+    #[must_use]
+    pub const fn new(max_per_period: usize, period: Duration) -> Self {
+        Self {
+            max_per_period,
+            period,
+            window_start: None,
+            sent: 0,
+            available_at: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn max_per_period(&self) -> usize {
+        self.max_per_period
+    }
+
+    #[must_use]
+    pub const fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl FlowController for FixedRate {
+    fn request(&mut self, _writer: Guid, _priority: i32, _len: usize, now: Time) -> bool {
+        let window_start = match self.window_start {
+            Some(start) if now < start + self.period => start,
+            _ => {
+                self.sent = 0;
+                *self.window_start.insert(now)
+            }
+        };
+        if self.sent < self.max_per_period {
+            self.sent += 1;
+            self.available_at = None;
+            true
+        } else {
+            self.available_at = Some(window_start + self.period);
+            false
+        }
+    }
+
+    fn next_available(&self) -> Option<Time> {
+        self.available_at
+    }
+}
+
+/// Shares the budget of another controller between writers in priority
+/// order: as long as a writer has submessages waiting, writers of a lower
+/// priority are held back and retry every `retry_delay`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PriorityScheduler<C> {
+    inner: C,
+    retry_delay: Duration,
+    waiting: HashMap<Guid, i32>,
+    retry_at: Option<Time>,
+}
+
+impl<C: FlowController> PriorityScheduler<C> {
+    #[must_use]
+    pub fn new(inner: C, retry_delay: Duration) -> Self {
+        Self {
+            inner,
+            retry_delay,
+            waiting: HashMap::new(),
+            retry_at: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: FlowController> FlowController for PriorityScheduler<C> {
+    fn request(&mut self, writer: Guid, priority: i32, len: usize, now: Time) -> bool {
+        self.waiting.insert(writer, priority);
+        if self
+            .waiting
+            .iter()
+            .any(|(other, p)| *other != writer && *p > priority)
+        {
+            self.retry_at = Some(now + self.retry_delay);
+            return false;
+        }
+        self.inner.request(writer, priority, len, now)
+    }
+
+    fn release(&mut self, writer: Guid) {
+        self.waiting.remove(&writer);
+        if self.waiting.is_empty() {
+            self.retry_at = None;
+        }
+        self.inner.release(writer);
+    }
+
+    fn next_available(&self) -> Option<Time> {
+        self.inner
+            .next_available()
+            .into_iter()
+            .chain(self.retry_at)
+            .min()
+    }
+}
+
+/// A flow controller that can be attached to several writers.
+#[derive(Clone)]
+pub struct SharedFlowController(Arc<Mutex<dyn FlowController>>);
+
+impl SharedFlowController {
+    pub fn new(controller: impl FlowController + 'static) -> Self {
+        Self(Arc::new(Mutex::new(controller)))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, dyn FlowController + 'static> {
+        self.0.lock().unwrap()
+    }
+
+    #[must_use]
+    pub fn next_available(&self) -> Option<Time> {
+        self.lock().next_available()
+    }
+}
+
+impl fmt::Debug for SharedFlowController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedFlowController").finish()
+    }
+}
+
+/// Whether `submessage` is paced by the flow controller.
+pub(crate) fn is_paced(submessage: &RtpsSubmessage) -> bool {
+    matches!(
+        submessage,
+        RtpsSubmessage::Data(_) | RtpsSubmessage::DataFrag(_)
+    )
+}
+
+/// The number of bytes charged for `submessage`: its payload and the fixed
+/// part of the submessage.
+pub(crate) fn paced_len(submessage: &RtpsSubmessage) -> usize {
+    match submessage {
+        RtpsSubmessage::Data(data) => 24 + data.serialized_payload.as_ref().map_or(0, |d| d.len()),
+        RtpsSubmessage::DataFrag(data_frag) => 36 + data_frag.serialized_payload.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::EntityId;
+
+    fn guid(key: u8) -> Guid {
+        Guid::new([1; 12], EntityId::new([0, 0, key], 0x02))
+    }
+
+    fn at(millis: u64) -> Time {
+        Time::from(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        // 1000 bytes per second with bursts of 500 bytes.
+        let mut bucket = TokenBucket::new(1000, 500);
+        assert!(bucket.request(guid(1), 0, 300, at(0)));
+        assert!(bucket.request(guid(1), 0, 200, at(0)));
+        assert!(!bucket.request(guid(1), 0, 100, at(0)));
+        assert_eq!(bucket.next_available(), Some(at(100)));
+        assert!(!bucket.request(guid(1), 0, 100, at(99)));
+        assert!(bucket.request(guid(1), 0, 100, at(100)));
+        assert_eq!(bucket.next_available(), None);
+
+        // The bucket never holds more than a burst, which a larger
+        // submessage waits for.
+        assert!(bucket.request(guid(1), 0, 2000, at(5000)));
+        assert_eq!(bucket.tokens, 0);
+        assert!(!bucket.request(guid(1), 0, 1, at(5000)));
+        assert_eq!(bucket.next_available(), Some(at(5001)));
+    }
+
+    #[test]
+    fn test_fixed_rate() {
+        let period = Duration::from_millis(100);
+        let mut rate = FixedRate::new(2, period);
+        assert!(rate.request(guid(1), 0, 10_000, at(50)));
+        assert!(rate.request(guid(1), 0, 10_000, at(60)));
+        assert!(!rate.request(guid(1), 0, 1, at(149)));
+        assert_eq!(rate.next_available(), Some(at(50) + period));
+        assert!(rate.request(guid(1), 0, 1, at(50) + period));
+    }
+
+    #[test]
+    fn test_priority_scheduler() {
+        let retry_delay = Duration::from_millis(10);
+        let mut scheduler = PriorityScheduler::new(TokenBucket::new(1000, 100), retry_delay);
+        let (low, high) = (guid(1), guid(2));
+        assert!(scheduler.request(high, 5, 100, at(0)));
+        // The high priority writer still has submessages waiting.
+        assert!(!scheduler.request(high, 5, 100, at(0)));
+        assert!(!scheduler.request(low, 1, 10, at(0)));
+        assert_eq!(scheduler.next_available(), Some(at(10)));
+
+        assert!(scheduler.request(high, 5, 100, at(100)));
+        scheduler.release(high);
+        assert!(!scheduler.request(low, 1, 10, at(100)));
+        assert!(scheduler.request(low, 1, 10, at(110)));
+    }
+}
@@ -1,57 +1,654 @@
-use crate::structure::{
-    historycache::{CacheChange, HistoryCache},
-    participant::{self, Endpoint},
-    Guid, Locator, ReliabilityKind, SequenceNumber, TopicKind,
+use std::{collections::VecDeque, io};
+
+use crate::{
+    messages::{
+        Count, DataFragSubmessage, DataSubmessage, FragmentNumber, GapSubmessage,
+        HeartbeatFragSubmessage, HeartbeatSubmessage, RtpsSubmessage, SequenceNumberSet, Time,
+    },
+    structure::{
+        data::Data,
+        historycache::{CacheChange, HistoryCache},
+        participant::Endpoint,
+        ChangeKind, EntityId, Guid, InstanceHandle, ParameterList, SequenceNumber,
+    },
+};
+
+use self::{
+    batching::{Batch, Batching},
+    flow_control::{is_paced, paced_len, SharedFlowController},
 };
 
-use super::Duration;
+use super::{timer::Jitter, Duration, Outgoing};
 
+pub mod batching;
+pub mod flow_control;
 pub mod stateful;
 pub mod stateless;
 
+/// See Section 8.4.7.1 of the [specification](https://www.omg.org/spec/DDSI-RTPS/)
+#[derive(Debug)]
 pub struct Writer {
-    guid: Guid,
-
-    endpoint: Endpoint<participant::Writer>,
+    endpoint: Endpoint,
 
     push_mode: bool,
     heartbeat_period: Duration,
+    heartbeat_jitter: Option<Jitter>,
     nack_response_delay: Duration,
     nack_suppression_delay: Duration,
     last_change_sequence_number: SequenceNumber,
     data_max_size_serialized: i32,
+    fragment_size: Option<u16>,
+    heartbeat_count: Count,
+    heartbeat_frag_count: Count,
+    lifespan: Option<Duration>,
+    history_depth: Option<usize>,
+    flow_controller: Option<SharedFlowController>,
+    priority: i32,
+    /// DATA and DATA_FRAG submessages held back by the flow controller, in
+    /// sending order.
+    paced: VecDeque<Outgoing>,
+    batch: Option<Batch>,
 
     writer_cache: HistoryCache,
 }
 
 impl Writer {
-    /// See 8.4.7.1.2 of the specification.
+    /// See 8.4.7.1.2 of the specification. The GUID, locators, reliability
+    /// and topic kind are taken from `endpoint`.
+    #[must_use]
     pub fn new(
-        guid: Guid,
-        unicast_locator_list: Vec<Locator>,
-        multicast_locator_list: Vec<Locator>,
-        reliability_kind: ReliabilityKind,
-        topic_kind: TopicKind,
+        endpoint: Endpoint,
         push_mode: bool,
         heartbeat_period: Duration,
         nack_response_delay: Duration,
-        nack_suppression_duration: Duration,
+        nack_suppression_delay: Duration,
     ) -> Self {
-        let endpoint = Endpoint::new();
         Self {
-            guid,
-            endpoint: (),
-            push_mode: (),
-            heartbeat_period: (),
-            nack_response_delay: (),
-            nack_suppression_delay: (),
-            last_change_sequence_number: (),
-            data_max_size_serialized: (),
-            writer_cache: (),
+            endpoint,
+            push_mode,
+            heartbeat_period,
+            heartbeat_jitter: None,
+            nack_response_delay,
+            nack_suppression_delay,
+            last_change_sequence_number: SequenceNumber::default(),
+            data_max_size_serialized: i32::MAX,
+            fragment_size: None,
+            heartbeat_count: 0,
+            heartbeat_frag_count: 0,
+            lifespan: None,
+            history_depth: None,
+            flow_controller: None,
+            priority: 0,
+            paced: VecDeque::new(),
+            batch: None,
+            writer_cache: HistoryCache::new(),
+        }
+    }
+
+    /// Replaces the writer's HistoryCache, e.g. with one recovered from
+    /// persistent storage. Sequence numbering resumes after the highest
+    /// sequence number the cache has ever held.
+    #[must_use]
+    pub fn with_writer_cache(mut self, writer_cache: HistoryCache) -> Self {
+        self.last_change_sequence_number = writer_cache
+            .last_sequence_number()
+            .unwrap_or_default()
+            .max(self.last_change_sequence_number);
+        self.writer_cache = writer_cache;
+        self
+    }
+
+    /// Sends payloads larger than `fragment_size` bytes as DATA_FRAG
+    /// submessages of that size. Without it, every payload is sent whole.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fragment_size` is zero.
+    #[must_use]
+    pub fn with_fragment_size(mut self, fragment_size: u16) -> Self {
+        assert!(fragment_size > 0, "fragment size must not be zero");
+        self.fragment_size = Some(fragment_size);
+        self
+    }
+
+    /// Sends each periodic HEARTBEAT up to `jitter.max()` earlier than
+    /// `heartbeat_period`, so that writers created together do not send
+    /// them in lockstep.
+    #[must_use]
+    pub fn with_heartbeat_jitter(mut self, jitter: Jitter) -> Self {
+        self.heartbeat_jitter = Some(jitter);
+        self
+    }
+
+    /// Paces the DATA and DATA_FRAG submessages, including repairs, with
+    /// `flow_controller`. Writers sharing a
+    /// [`PriorityScheduler`](flow_control::PriorityScheduler) are served in
+    /// decreasing `priority`.
+    #[must_use]
+    pub fn with_flow_controller(
+        mut self,
+        flow_controller: SharedFlowController,
+        priority: i32,
+    ) -> Self {
+        self.flow_controller = Some(flow_controller);
+        self.priority = priority;
+        self
+    }
+
+    /// Gathers the DATA submessages sent within `batching`'s limits into
+    /// batches, which the transport sends in as few messages as possible.
+    #[must_use]
+    pub fn with_batching(mut self, batching: Batching) -> Self {
+        self.batch = Some(Batch::new(batching));
+        self
+    }
+
+    #[must_use]
+    pub fn batching(&self) -> Option<Batching> {
+        self.batch.as_ref().map(Batch::batching)
+    }
+
+    /// Sends the open batch, if any, the next time the writer is polled.
+    pub fn flush(&mut self) {
+        if let Some(batch) = &mut self.batch {
+            batch.flush();
+        }
+    }
+
+    /// Adds `outgoing` to the open batch, if batching is enabled. Returns the
+    /// submessages that may be sent at `now`.
+    pub(crate) fn batch(&mut self, outgoing: Vec<Outgoing>, now: Time) -> Vec<Outgoing> {
+        match &mut self.batch {
+            Some(batch) => batch.add(outgoing, now),
+            None => outgoing,
+        }
+    }
+
+    /// When the open batch is due.
+    pub(crate) fn batch_deadline(&self) -> Option<Time> {
+        self.batch.as_ref()?.deadline()
+    }
+
+    /// The number of submessages held back in the open batch.
+    #[must_use]
+    pub fn batched_len(&self) -> usize {
+        self.batch.as_ref().map_or(0, Batch::len)
+    }
+
+    /// Hands `outgoing` to the flow controller, if any. Returns the
+    /// submessages that may be sent at `now`: the paced ones the controller
+    /// lets through, oldest first, followed by the others. The rest is kept
+    /// for a later call.
+    pub(crate) fn pace(&mut self, outgoing: Vec<Outgoing>, now: Time) -> Vec<Outgoing> {
+        let Some(flow_controller) = &self.flow_controller else {
+            return outgoing;
+        };
+        let (paced, unpaced): (Vec<_>, Vec<_>) =
+            outgoing.into_iter().partition(|o| is_paced(&o.submessage));
+        self.paced.extend(paced);
+
+        let guid = self.endpoint.guid();
+        let mut controller = flow_controller.lock();
+        let mut released = Vec::new();
+        while let Some(o) = self.paced.front() {
+            if !controller.request(guid, self.priority, paced_len(&o.submessage), now) {
+                break;
+            }
+            released.extend(self.paced.pop_front());
+        }
+        if self.paced.is_empty() {
+            controller.release(guid);
+        }
+        released.extend(unpaced);
+        released
+    }
+
+    /// When the flow controller may let held back submessages through.
+    pub(crate) fn pace_deadline(&self) -> Option<Time> {
+        if self.paced.is_empty() {
+            return None;
+        }
+        self.flow_controller.as_ref()?.next_available()
+    }
+
+    /// The number of submessages held back by the flow controller.
+    #[must_use]
+    pub fn paced_len(&self) -> usize {
+        self.paced.len()
+    }
+
+    /// When the periodic HEARTBEAT sent at `now` is next due.
+    pub(crate) fn next_heartbeat(&mut self, now: Time) -> Time {
+        let period = match &mut self.heartbeat_jitter {
+            Some(jitter) => jitter.apply(self.heartbeat_period),
+            None => self.heartbeat_period,
+        };
+        now + period
+    }
+
+    /// Removes changes from the writer's cache once `lifespan` has elapsed
+    /// since they were written. Readers that request them are sent a GAP.
+    #[must_use]
+    pub fn with_lifespan(mut self, lifespan: Duration) -> Self {
+        self.lifespan = Some(lifespan);
+        self
+    }
+
+    #[must_use]
+    pub const fn lifespan(&self) -> Option<Duration> {
+        self.lifespan
+    }
+
+    /// Removes the changes whose Lifespan has elapsed at `now` from the
+    /// writer's cache and returns them.
+    pub(crate) fn remove_expired(&mut self, now: Time) -> io::Result<Vec<CacheChange>> {
+        match self.lifespan {
+            Some(lifespan) => self.writer_cache.remove_expired(now, lifespan),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Keeps only the `depth` most recent changes in the writer's cache, as
+    /// a KEEP_LAST History does, evicting older ones as changes are added.
+    /// Readers that have not been sent an evicted change are sent a GAP. A
+    /// depth of zero keeps one change.
+    #[must_use]
+    pub fn with_history_depth(mut self, depth: usize) -> Self {
+        self.history_depth = Some(depth.max(1));
+        self
+    }
+
+    #[must_use]
+    pub const fn history_depth(&self) -> Option<usize> {
+        self.history_depth
+    }
+
+    /// Removes the oldest changes beyond the history depth from the
+    /// writer's cache and returns them.
+    pub(crate) fn remove_evicted(&mut self) -> io::Result<Vec<CacheChange>> {
+        let Some(depth) = self.history_depth else {
+            return Ok(Vec::new());
+        };
+        let evicted: Vec<_> = self
+            .writer_cache
+            .changes()
+            .take(self.writer_cache.len().saturating_sub(depth))
+            .cloned()
+            .collect();
+        let mut removed = Vec::with_capacity(evicted.len());
+        for change in &evicted {
+            removed.extend(self.writer_cache.remove_change(change)?);
+        }
+        Ok(removed)
+    }
+
+    /// When the next change in the writer's cache expires.
+    pub(crate) fn expiry_deadline(&self) -> Option<Time> {
+        self.writer_cache.next_expiry(self.lifespan?)
+    }
+
+    /// See 8.4.7.1.3 of the specification. Each change is assigned the next
+    /// sequence number; the change still has to be added to the
+    /// [`HistoryCache`] by the caller.
+    pub fn new_change(
+        &mut self,
+        kind: ChangeKind,
+        data: Option<Data>,
+        inline_qos: ParameterList,
+        handle: InstanceHandle,
+    ) -> CacheChange {
+        self.last_change_sequence_number.increment();
+        CacheChange::new(
+            kind,
+            self.guid(),
+            handle,
+            self.last_change_sequence_number,
+            data,
+            inline_qos,
+        )
+    }
+
+    /// Builds the next HEARTBEAT announcing the range of changes available
+    /// in the writer's cache. An empty cache is announced as
+    /// `first_sn = last_sn + 1`.
+    pub(crate) fn heartbeat(
+        &mut self,
+        reader_id: EntityId,
+        final_flag: bool,
+    ) -> HeartbeatSubmessage {
+        self.heartbeat_count = self.heartbeat_count.wrapping_add(1);
+        let last_sn = self.last_change_sequence_number;
+        let first_sn = self.writer_cache.get_seq_num_min().unwrap_or_else(|| {
+            let mut first_sn = last_sn;
+            first_sn.increment();
+            first_sn
+        });
+        HeartbeatSubmessage {
+            reader_id,
+            writer_id: self.guid().entity_id(),
+            first_sn,
+            last_sn,
+            count: self.heartbeat_count,
+            final_flag,
+            liveliness_flag: false,
+        }
+    }
+
+    /// Builds the next HEARTBEAT_FRAG announcing that every fragment of the
+    /// change with `writer_sn` is available.
+    pub(crate) fn heartbeat_frag(
+        &mut self,
+        reader_id: EntityId,
+        writer_sn: SequenceNumber,
+        last_fragment_num: FragmentNumber,
+    ) -> HeartbeatFragSubmessage {
+        self.heartbeat_frag_count = self.heartbeat_frag_count.wrapping_add(1);
+        HeartbeatFragSubmessage {
+            reader_id,
+            writer_id: self.guid().entity_id(),
+            writer_sn,
+            last_fragment_num,
+            count: self.heartbeat_frag_count,
         }
     }
 
-    pub fn new_change(&mut self) -> CacheChange {
-        todo!()
+    /// The number of fragments `change` is sent in, or `None` if it is sent
+    /// whole.
+    #[must_use]
+    pub fn fragment_count(&self, change: &CacheChange) -> Option<FragmentNumber> {
+        let fragment_size = usize::from(self.fragment_size?);
+        let sample_size = change.data_value()?.len();
+        (sample_size > fragment_size)
+            .then(|| FragmentNumber::try_from(sample_size.div_ceil(fragment_size)).ok())
+            .flatten()
+    }
+
+    #[must_use]
+    pub const fn fragment_size(&self) -> Option<u16> {
+        self.fragment_size
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.endpoint.guid()
+    }
+
+    #[must_use]
+    pub const fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Whether changes are sent as soon as they are written. In pull mode,
+    /// reliable readers are only announced them by HEARTBEATs and sent the
+    /// ones they request, which suits large histories few readers need.
+    #[must_use]
+    pub const fn push_mode(&self) -> bool {
+        self.push_mode
+    }
+
+    #[must_use]
+    pub const fn heartbeat_period(&self) -> Duration {
+        self.heartbeat_period
+    }
+
+    #[must_use]
+    pub const fn nack_response_delay(&self) -> Duration {
+        self.nack_response_delay
+    }
+
+    #[must_use]
+    pub const fn nack_suppression_delay(&self) -> Duration {
+        self.nack_suppression_delay
+    }
+
+    #[must_use]
+    pub const fn last_change_sequence_number(&self) -> SequenceNumber {
+        self.last_change_sequence_number
+    }
+
+    #[must_use]
+    pub const fn data_max_size_serialized(&self) -> i32 {
+        self.data_max_size_serialized
+    }
+
+    #[must_use]
+    pub const fn writer_cache(&self) -> &HistoryCache {
+        &self.writer_cache
+    }
+
+    pub fn writer_cache_mut(&mut self) -> &mut HistoryCache {
+        &mut self.writer_cache
+    }
+}
+
+/// Stamps `change`, handed to the writer at `now`, with the time it was
+/// written, unless the caller already did.
+pub(crate) fn stamp(change: CacheChange, now: Time) -> CacheChange {
+    match change.source_timestamp() {
+        Some(_) => change,
+        None => change.with_source_timestamp(now),
+    }
+}
+
+/// Builds the DATA submessage carrying `change` to `reader_id`.
+pub(crate) fn data_submessage(reader_id: EntityId, change: &CacheChange) -> DataSubmessage {
+    DataSubmessage {
+        reader_id,
+        writer_id: change.writer_guid().entity_id(),
+        writer_sn: change.sequence_number(),
+        kind: change.kind(),
+        source_timestamp: change.source_timestamp(),
+        serialized_payload: change.data_value().cloned(),
+    }
+}
+
+/// Builds the submessages carrying `change` to `reader_id`: a single DATA,
+/// or one DATA_FRAG per fragment if the writer fragments it.
+pub(crate) fn change_submessages(
+    writer: &Writer,
+    reader_id: EntityId,
+    change: &CacheChange,
+) -> Vec<RtpsSubmessage> {
+    match writer.fragment_count(change) {
+        Some(count) => (1..=count)
+            .filter_map(|n| data_frag_submessage(writer, reader_id, change, n))
+            .map(RtpsSubmessage::DataFrag)
+            .collect(),
+        None => vec![RtpsSubmessage::Data(data_submessage(reader_id, change))],
+    }
+}
+
+/// Builds the DATA_FRAG carrying fragment `fragment_number` of `change` to
+/// `reader_id`, or `None` if the writer does not fragment the change or it
+/// has no such fragment.
+pub(crate) fn data_frag_submessage(
+    writer: &Writer,
+    reader_id: EntityId,
+    change: &CacheChange,
+    fragment_number: FragmentNumber,
+) -> Option<DataFragSubmessage> {
+    let fragment_size = writer.fragment_size?;
+    let payload = change.data_value()?;
+    let start =
+        usize::from(fragment_size) * usize::try_from(fragment_number.checked_sub(1)?).ok()?;
+    let end = payload.len().min(start + usize::from(fragment_size));
+    if start >= end {
+        return None;
+    }
+    Some(DataFragSubmessage {
+        reader_id,
+        writer_id: change.writer_guid().entity_id(),
+        writer_sn: change.sequence_number(),
+        fragment_starting_num: fragment_number,
+        fragments_in_submessage: 1,
+        fragment_size,
+        sample_size: u32::try_from(payload.len()).ok()?,
+        source_timestamp: change.source_timestamp(),
+        serialized_payload: Data::from(&payload[start..end]),
+    })
+}
+
+/// Builds the GAP submessages announcing the `irrelevant` sequence numbers,
+/// which must be sorted and unique, to `reader_id`. Each GAP covers a
+/// contiguous run as `[gap_start, gap_list.base)` and folds the sequence
+/// numbers that follow within the bitmap window into `gap_list`.
+///
+/// The group sequence numbers are left out: they only apply to writers of
+/// an ordered (GROUP access scope) Publisher, and writers here belong to no
+/// group and number their changes on their own.
+pub(crate) fn gap_submessages(
+    reader_id: EntityId,
+    writer_id: EntityId,
+    irrelevant: &[SequenceNumber],
+) -> Vec<GapSubmessage> {
+    let mut gaps = Vec::new();
+    let mut rest = irrelevant;
+    while let Some(&gap_start) = rest.first() {
+        let run = rest
+            .iter()
+            .zip(gap_start.value()..)
+            .take_while(|(sn, expected)| sn.value() == *expected)
+            .count();
+        let base = SequenceNumber::from(gap_start.value() + run as u64);
+        let listed = rest[run..]
+            .iter()
+            .take_while(|sn| sn.value() - base.value() < SequenceNumberSet::MAX_SPAN)
+            .count();
+        gaps.push(GapSubmessage {
+            reader_id,
+            writer_id,
+            gap_start,
+            gap_list: SequenceNumberSet::new(base, rest[run..run + listed].iter().copied()),
+            gap_start_gsn: None,
+            gap_end_gsn: None,
+        });
+        rest = &rest[run + listed..];
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::{EntityId, ReliabilityKind, ENTITYID_UNKNOWN};
+
+    pub(crate) fn writer(reliability_level: ReliabilityKind) -> Writer {
+        let guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        Writer::new(
+            Endpoint::builder(guid)
+                .reliability_level(reliability_level)
+                .build(),
+            true,
+            Duration::from_secs(1),
+            Duration::from_millis(200),
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_gap_coalescing() {
+        let irrelevant: Vec<_> = [1, 2, 3, 5, 7, 300, 301]
+            .into_iter()
+            .map(SequenceNumber::from)
+            .collect();
+        let gaps: Vec<_> = gap_submessages(ENTITYID_UNKNOWN, ENTITYID_UNKNOWN, &irrelevant)
+            .into_iter()
+            .map(|gap| {
+                (
+                    gap.gap_start.value(),
+                    gap.gap_list.base().value(),
+                    gap.gap_list.iter().map(|sn| sn.value()).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(gaps, vec![(1, 4, vec![5, 7]), (300, 302, vec![])]);
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let mut writer = writer(ReliabilityKind::Reliable).with_fragment_size(4);
+        let small = writer.new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 4])),
+            ParameterList,
+            InstanceHandle,
+        );
+        assert_eq!(writer.fragment_count(&small), None);
+        assert!(matches!(
+            change_submessages(&writer, ENTITYID_UNKNOWN, &small)[..],
+            [RtpsSubmessage::Data(_)]
+        ));
+
+        let large = writer.new_change(
+            ChangeKind::Alive,
+            Some(Data::from((0..10).collect::<Vec<u8>>())),
+            ParameterList,
+            InstanceHandle,
+        );
+        assert_eq!(writer.fragment_count(&large), Some(3));
+        let fragments: Vec<_> = change_submessages(&writer, ENTITYID_UNKNOWN, &large)
+            .into_iter()
+            .map(|submessage| match submessage {
+                RtpsSubmessage::DataFrag(frag) => {
+                    assert_eq!(frag.sample_size, 10);
+                    (frag.fragment_starting_num, frag.serialized_payload.to_vec())
+                }
+                _ => panic!("expected DATA_FRAG"),
+            })
+            .collect();
+        assert_eq!(
+            fragments,
+            vec![
+                (1, vec![0, 1, 2, 3]),
+                (2, vec![4, 5, 6, 7]),
+                (3, vec![8, 9])
+            ]
+        );
+        assert!(data_frag_submessage(&writer, ENTITYID_UNKNOWN, &large, 4).is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_jitter() {
+        let now = Time::new(10, 0);
+        let mut writer = writer(ReliabilityKind::Reliable);
+        assert_eq!(writer.next_heartbeat(now), Time::new(11, 0));
+
+        let max = Duration::from_millis(100);
+        let mut writer = writer.with_heartbeat_jitter(Jitter::new(max, 1));
+        let next: Vec<_> = (0..10).map(|_| writer.next_heartbeat(now)).collect();
+        assert!(next
+            .iter()
+            .all(|t| *t <= Time::new(11, 0) && *t >= now + (Duration::from_secs(1) - max)));
+        assert!(next.windows(2).any(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn test_new_change_sequence_numbers() {
+        let mut writer = writer(ReliabilityKind::BestEffort);
+        let first = writer.new_change(ChangeKind::Alive, None, ParameterList, InstanceHandle);
+        let second = writer.new_change(ChangeKind::Alive, None, ParameterList, InstanceHandle);
+        assert_eq!(first.sequence_number(), SequenceNumber::from(1));
+        assert_eq!(second.sequence_number(), SequenceNumber::from(2));
+        assert_eq!(second.writer_guid(), writer.guid());
+        assert_eq!(
+            writer.last_change_sequence_number(),
+            SequenceNumber::from(2)
+        );
+    }
+
+    #[test]
+    fn test_sequence_numbers_resume_from_cache() {
+        let mut previous = writer(ReliabilityKind::Reliable);
+        let mut cache = HistoryCache::new();
+        for _ in 0..3 {
+            let change =
+                previous.new_change(ChangeKind::Alive, None, ParameterList, InstanceHandle);
+            cache.add_change(change).unwrap();
+        }
+
+        let mut writer = writer(ReliabilityKind::Reliable).with_writer_cache(cache);
+        let change = writer.new_change(ChangeKind::Alive, None, ParameterList, InstanceHandle);
+        assert_eq!(change.sequence_number(), SequenceNumber::from(4));
+        assert_eq!(writer.writer_cache().len(), 3);
     }
 }
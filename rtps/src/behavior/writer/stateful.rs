@@ -1,63 +1,1649 @@
-use crate::structure::{historycache::CacheChange, Guid, Locator, SequenceNumber};
+//! The RTPS StatefulWriter keeps a [`ReaderProxy`] for every matched reader
+//! and tracks the status of each change with respect to that reader.
+//!
+//! See Sections 8.4.7.2, 8.4.7.4 and 8.4.9 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
 
-use super::Writer;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+    sync::{Arc, Condvar, Mutex},
+};
 
+use crate::{
+    behavior::{outputs, Behavior, ChangeForReaderStatusKind, Duration, Input, Outgoing, Output},
+    messages::{
+        AckNackSubmessage, Count, FragmentNumber, NackFragSubmessage, RtpsSubmessage, Time,
+    },
+    structure::{
+        historycache::CacheChange, EntityId, Guid, GuidPrefix, Locator, ReliabilityKind,
+        SequenceNumber, ENTITYID_UNKNOWN, GUIDPREFIX_UNKNOWN,
+    },
+};
+
+use super::{change_submessages, data_frag_submessage, gap_submessages, stamp, Writer};
+
+#[derive(Debug)]
 pub struct StatefulWriter {
     writer: Writer,
     matched_readers: Vec<ReaderProxy>,
+
+    next_heartbeat: Option<Time>,
+    purge_acknowledged: bool,
+    ack_notifier: AckNotifier,
 }
 
 impl StatefulWriter {
-    pub fn is_acked_by_all() -> bool {
-        todo!()
+    #[must_use]
+    pub fn new(writer: Writer) -> Self {
+        Self {
+            writer,
+            matched_readers: Vec::new(),
+            next_heartbeat: None,
+            purge_acknowledged: false,
+            ack_notifier: AckNotifier::default(),
+        }
+    }
+
+    /// Removes changes from the writer's cache as soon as every matched
+    /// reader has acknowledged them, for writers with KEEP_ALL history and
+    /// VOLATILE durability that never resend to late-joining readers.
+    #[must_use]
+    pub fn with_purge_acknowledged(mut self, purge_acknowledged: bool) -> Self {
+        self.purge_acknowledged = purge_acknowledged;
+        self
+    }
+
+    /// A handle through which other threads wait for the writer's changes
+    /// to be acknowledged or purged.
+    #[must_use]
+    pub fn ack_notifier(&self) -> AckNotifier {
+        self.ack_notifier.clone()
+    }
+
+    #[must_use]
+    pub const fn writer(&self) -> &Writer {
+        &self.writer
+    }
+
+    pub fn writer_mut(&mut self) -> &mut Writer {
+        &mut self.writer
+    }
+
+    #[must_use]
+    pub fn matched_readers(&self) -> &[ReaderProxy] {
+        &self.matched_readers
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.writer.endpoint.reliability_level() == ReliabilityKind::Reliable
+    }
+
+    /// Adds a change created with [`Writer::new_change`] to the writer's
+    /// cache and marks it unsent to every matched reader. Readers whose
+    /// content filter rejects the change are sent a GAP for it instead.
+    /// Changes evicted by the history depth are sent as a GAP if unsent.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store the change.
+    pub fn add_change(&mut self, change: CacheChange) -> io::Result<()> {
+        let sequence_number = change.sequence_number();
+        let relevance: Vec<_> = self
+            .matched_readers
+            .iter()
+            .map(|r| r.filter(&change))
+            .collect();
+        self.writer.writer_cache.add_change(change)?;
+        let push_mode = self.writer.push_mode;
+        for (reader_proxy, is_relevant) in self.matched_readers.iter_mut().zip(relevance) {
+            reader_proxy.add_change(sequence_number, is_relevant, push_mode);
+        }
+        // Evicted changes stay tracked, so that readers they were not yet
+        // sent to get a GAP on the next poll.
+        self.writer.remove_evicted()?;
+        self.notify_acknowledgements();
+        Ok(())
+    }
+
+    /// Removes `change` from the writer's cache and stops tracking it for
+    /// every matched reader. Readers learn that it is gone from the next
+    /// HEARTBEAT, and are sent a GAP if they request it.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot record the removal.
+    pub fn remove_change(&mut self, change: &CacheChange) -> io::Result<Option<CacheChange>> {
+        let removed = self.writer.writer_cache.remove_change(change)?;
+        for reader_proxy in &mut self.matched_readers {
+            reader_proxy.forget(change.sequence_number());
+        }
+        self.notify_acknowledgements();
+        Ok(removed)
+    }
+
+    /// Removes the changes every matched reader has acknowledged from the
+    /// writer's cache, best-effort readers acknowledging a change once it
+    /// was sent to them, and returns how many were removed. Readers that
+    /// later request them are sent a GAP.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot record the removal.
+    pub fn purge_acknowledged(&mut self) -> io::Result<usize> {
+        let acknowledged: Vec<_> = self
+            .writer
+            .writer_cache
+            .changes()
+            .filter(|c| {
+                self.matched_readers
+                    .iter()
+                    .all(|r| r.is_acked(c.sequence_number()))
+            })
+            .cloned()
+            .collect();
+        for change in &acknowledged {
+            self.remove_change(change)?;
+        }
+        self.notify_acknowledgements();
+        Ok(acknowledged.len())
+    }
+
+    /// Publishes the acknowledgement state to the [`AckNotifier`], waking up
+    /// the threads waiting on it.
+    fn notify_acknowledgements(&self) {
+        let acknowledged = self
+            .writer
+            .writer_cache
+            .changes()
+            .map(CacheChange::sequence_number)
+            .filter(|sn| !self.is_acked_by_all(*sn))
+            .min()
+            .map_or(self.writer.last_change_sequence_number, |sn| {
+                SequenceNumber::from(sn.value() - 1)
+            });
+        self.ack_notifier
+            .update(acknowledged, self.writer.writer_cache.len());
+    }
+
+    /// Returns `true` if every matched reliable reader has acknowledged the
+    /// change with `sequence_number`.
+    #[must_use]
+    pub fn is_acked_by_all(&self, sequence_number: SequenceNumber) -> bool {
+        self.matched_readers
+            .iter()
+            .filter(|r| r.reliable)
+            .all(|r| r.is_acked(sequence_number))
+    }
+
+    /// Matches a reader. Every change already in the writer's cache is
+    /// unsent to it. A proxy whose GUID is already matched replaces the
+    /// existing one.
+    pub fn matched_reader_add(&mut self, mut reader_proxy: ReaderProxy) {
+        reader_proxy.reliable &= self.is_reliable();
+        for change in self.writer.writer_cache.changes() {
+            reader_proxy.add_change(
+                change.sequence_number(),
+                reader_proxy.filter(change),
+                self.writer.push_mode,
+            );
+        }
+        self.matched_reader_remove(&reader_proxy.remote_reader_guid);
+        self.matched_readers.push(reader_proxy);
     }
 
-    pub fn matched_reader_add(&mut self, _reader: ReaderProxy) {
-        todo!()
+    pub fn matched_reader_remove(&mut self, reader_guid: &Guid) -> Option<ReaderProxy> {
+        let reader_proxy = self
+            .matched_readers
+            .iter()
+            .position(|r| r.remote_reader_guid == *reader_guid)
+            .map(|index| self.matched_readers.remove(index));
+        if reader_proxy.is_some() {
+            self.notify_acknowledgements();
+        }
+        reader_proxy
     }
 
-    pub fn matched_reader_remove(&mut self, _reader: &ReaderProxy) {
-        todo!()
+    /// Unmatches the readers of the participant `guid_prefix` and returns
+    /// them.
+    pub fn remove_participant(&mut self, guid_prefix: GuidPrefix) -> Vec<ReaderProxy> {
+        let (removed, kept) = std::mem::take(&mut self.matched_readers)
+            .into_iter()
+            .partition(|r| r.remote_reader_guid.guid_prefix() == guid_prefix);
+        self.matched_readers = kept;
+        if !removed.is_empty() {
+            self.notify_acknowledgements();
+        }
+        removed
+    }
+
+    #[must_use]
+    pub fn matched_reader_lookup(&self, reader_guid: &Guid) -> Option<&ReaderProxy> {
+        self.matched_readers
+            .iter()
+            .find(|r| r.remote_reader_guid == *reader_guid)
+    }
+
+    /// Handles an ACKNACK sent by a reader of the participant `source`.
+    /// Acknowledged changes are recorded immediately; requested changes are
+    /// repaired by the first [`StatefulWriter::poll`] after
+    /// `nack_response_delay`. Requested sequence numbers the writer never
+    /// sent to the reader are answered with a GAP.
+    pub fn on_acknack(&mut self, source: GuidPrefix, acknack: &AckNackSubmessage, now: Time) {
+        if !self.is_reliable() {
+            return;
+        }
+        let nack_response_delay = self.writer.nack_response_delay;
+        let nack_suppression_delay = self.writer.nack_suppression_delay;
+        let last_sequence_number = self.writer.last_change_sequence_number;
+        let reader_guid = Guid::new(source, acknack.reader_id);
+        let Some(reader_proxy) = self
+            .matched_readers
+            .iter_mut()
+            .find(|r| r.remote_reader_guid == reader_guid && r.reliable)
+        else {
+            return;
+        };
+        if reader_proxy
+            .last_acknack_count
+            .is_some_and(|count| acknack.count <= count)
+        {
+            return;
+        }
+        reader_proxy.last_acknack_count = Some(acknack.count);
+        reader_proxy.end_suppression(now, nack_suppression_delay);
+        reader_proxy.acked_changes_set(acknack.reader_sn_state.base());
+        reader_proxy.requested_changes_set(
+            acknack
+                .reader_sn_state
+                .iter()
+                .filter(|sn| *sn <= last_sequence_number),
+        );
+        if reader_proxy.repair_at.is_none() && reader_proxy.requested_changes().next().is_some() {
+            reader_proxy.repair_at = Some(now + nack_response_delay);
+        }
+        self.notify_acknowledgements();
+    }
+
+    /// Handles a NACK_FRAG sent by a reader of the participant `source`.
+    /// Only the requested fragments are resent, by the first
+    /// [`StatefulWriter::poll`] after `nack_response_delay`.
+    pub fn on_nack_frag(&mut self, source: GuidPrefix, nack_frag: &NackFragSubmessage, now: Time) {
+        if !self.is_reliable() {
+            return;
+        }
+        let nack_response_delay = self.writer.nack_response_delay;
+        let nack_suppression_delay = self.writer.nack_suppression_delay;
+        let reader_guid = Guid::new(source, nack_frag.reader_id);
+        let Some(reader_proxy) = self
+            .matched_readers
+            .iter_mut()
+            .find(|r| r.remote_reader_guid == reader_guid && r.reliable)
+        else {
+            return;
+        };
+        if reader_proxy
+            .last_nack_frag_count
+            .is_some_and(|count| nack_frag.count <= count)
+        {
+            return;
+        }
+        reader_proxy.last_nack_frag_count = Some(nack_frag.count);
+        reader_proxy.end_suppression(now, nack_suppression_delay);
+        reader_proxy
+            .requested_fragments_set(nack_frag.writer_sn, nack_frag.fragment_number_state.iter());
+        if reader_proxy.repair_at.is_none() && !reader_proxy.requested_fragments.is_empty() {
+            reader_proxy.repair_at = Some(now + nack_response_delay);
+        }
+    }
+
+    /// The next time at which [`StatefulWriter::poll`] has periodic or
+    /// delayed work to do.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Time> {
+        let suppression = self.writer.nack_suppression_delay;
+        self.matched_readers
+            .iter()
+            .flat_map(|r| {
+                r.changes_for_reader
+                    .values()
+                    .filter(|c| c.status == ChangeForReaderStatusKind::Underway)
+                    .filter_map(move |c| c.sent_at.map(|t| t + suppression))
+                    .chain(r.repair_at)
+            })
+            .chain(self.next_heartbeat)
+            .chain(self.writer.batch_deadline())
+            .chain(self.writer.pace_deadline())
+            .chain(self.writer.expiry_deadline())
+            .min()
+    }
+
+    /// Sends the first transmission of changes once to each multicast
+    /// locator shared by several matched readers. A change is only
+    /// multicast if it is unsent and relevant to every reader of the group,
+    /// so that none receives a change filtered out for it or directed to
+    /// others. Everything else, repairs included, is left to be unicast;
+    /// readers without a multicast locator are always sent changes unicast.
+    fn multicast(&mut self, now: Time) -> Vec<Outgoing> {
+        let mut groups: BTreeMap<Locator, Vec<usize>> = BTreeMap::new();
+        for (index, reader_proxy) in self.matched_readers.iter().enumerate() {
+            if let Some(locator) = reader_proxy.multicast_locator_list.first() {
+                groups.entry(*locator).or_default().push(index);
+            }
+        }
+        let mut outgoing = Vec::new();
+        let writer_guid = self.writer.guid();
+        for (locator, members) in groups.into_iter().filter(|(_, m)| m.len() > 1) {
+            let unsent: BTreeSet<_> = members
+                .iter()
+                .flat_map(|index| self.matched_readers[*index].unsent_changes())
+                .collect();
+            for sequence_number in unsent {
+                let shared = members.iter().all(|index| {
+                    let reader_proxy = &self.matched_readers[*index];
+                    reader_proxy.is_relevant(sequence_number)
+                        && reader_proxy
+                            .change_for_reader(sequence_number)
+                            .is_some_and(|c| c.status == ChangeForReaderStatusKind::Unsent)
+                });
+                let Some(change) = self
+                    .writer
+                    .writer_cache
+                    .get_change(writer_guid, sequence_number)
+                    .filter(|_| shared)
+                else {
+                    continue;
+                };
+                outgoing.extend(
+                    change_submessages(&self.writer, ENTITYID_UNKNOWN, change)
+                        .into_iter()
+                        .map(|submessage| Outgoing {
+                            locator,
+                            destination: GUIDPREFIX_UNKNOWN,
+                            submessage,
+                        }),
+                );
+                for index in &members {
+                    self.matched_readers[*index].mark_sent(sequence_number, now);
+                }
+            }
+        }
+        outgoing
+    }
+
+    /// Performs the behavior of Section 8.4.9: unsent changes are pushed to
+    /// every matched reader in sequence order. Readers sharing a multicast
+    /// locator are sent a change once through it when it is relevant to all
+    /// of them.
+    ///
+    /// For reliable readers, requested changes and fragments are repaired
+    /// once `nack_response_delay` has elapsed, and a HEARTBEAT is sent every
+    /// `heartbeat_period` while a reader has unacknowledged changes, along
+    /// with a HEARTBEAT_FRAG for each of them that is fragmented. A change
+    /// stays UNDERWAY for `nack_suppression_delay` after being sent, during
+    /// which NACKs for it are ignored.
+    pub fn poll(&mut self, now: Time) -> Vec<Outgoing> {
+        let suppression = self.writer.nack_suppression_delay;
+        for reader_proxy in &mut self.matched_readers {
+            reader_proxy.end_suppression(now, suppression);
+        }
+        let mut outgoing = self.multicast(now);
+        let writer = &self.writer;
+        let writer_guid = writer.guid();
+        let cache = &writer.writer_cache;
+
+        for reader_proxy in &mut self.matched_readers {
+            let Some(locator) = reader_proxy.locator() else {
+                continue;
+            };
+            let destination = reader_proxy.remote_reader_guid.guid_prefix();
+            let reader_id = reader_proxy.remote_reader_guid.entity_id();
+            let mut irrelevant = Vec::new();
+            let mut send = |reader_proxy: &mut ReaderProxy, sequence_number| {
+                match cache
+                    .get_change(writer_guid, sequence_number)
+                    .filter(|_| reader_proxy.is_relevant(sequence_number))
+                {
+                    Some(change) => outgoing.extend(
+                        change_submessages(writer, reader_id, change)
+                            .into_iter()
+                            .map(|submessage| Outgoing {
+                                locator,
+                                destination,
+                                submessage,
+                            }),
+                    ),
+                    None => irrelevant.push(sequence_number),
+                }
+                reader_proxy.mark_sent(sequence_number, now);
+            };
+
+            while let Some(sequence_number) = reader_proxy.next_unsent_change() {
+                send(reader_proxy, sequence_number);
+            }
+            if reader_proxy.repair_at.is_some_and(|t| t <= now) {
+                reader_proxy.repair_at = None;
+                while let Some(sequence_number) = reader_proxy.next_requested_change() {
+                    send(reader_proxy, sequence_number);
+                }
+                for (sequence_number, fragments) in
+                    std::mem::take(&mut reader_proxy.requested_fragments)
+                {
+                    match cache
+                        .get_change(writer_guid, sequence_number)
+                        .filter(|_| reader_proxy.is_relevant(sequence_number))
+                    {
+                        Some(change) => outgoing.extend(
+                            fragments
+                                .into_iter()
+                                .filter_map(|n| data_frag_submessage(writer, reader_id, change, n))
+                                .map(|frag| Outgoing {
+                                    locator,
+                                    destination,
+                                    submessage: RtpsSubmessage::DataFrag(frag),
+                                }),
+                        ),
+                        None => irrelevant.push(sequence_number),
+                    }
+                }
+            }
+
+            // Changes that were filtered out, removed from the cache or never
+            // existed are announced with as few GAPs as possible.
+            irrelevant.sort();
+            irrelevant.dedup();
+            outgoing.extend(
+                gap_submessages(reader_id, writer_guid.entity_id(), &irrelevant)
+                    .into_iter()
+                    .map(|gap| Outgoing {
+                        locator,
+                        destination,
+                        submessage: RtpsSubmessage::Gap(gap),
+                    }),
+            );
+            // Once GAP-ed, changes absent from the cache need no tracking;
+            // filtered ones are kept so that they are never sent if requested.
+            for sequence_number in irrelevant {
+                if cache.get_change(writer_guid, sequence_number).is_none() {
+                    reader_proxy.forget(sequence_number);
+                }
+            }
+        }
+
+        if self.is_reliable() && self.next_heartbeat.is_none_or(|next| next <= now) {
+            let writer = &self.writer;
+            let pending: Vec<_> = self
+                .matched_readers
+                .iter()
+                .filter(|r| r.reliable && r.has_unacknowledged_changes())
+                .filter_map(|r| {
+                    let fragmented: Vec<_> = r
+                        .sent_unacked_changes()
+                        .filter_map(|sn| {
+                            let change = writer.writer_cache.get_change(writer_guid, sn)?;
+                            Some((sn, writer.fragment_count(change)?))
+                        })
+                        .collect();
+                    r.locator()
+                        .map(|locator| (locator, r.remote_reader_guid, fragmented))
+                })
+                .collect();
+            if pending.is_empty() {
+                self.next_heartbeat = None;
+            } else {
+                self.next_heartbeat = Some(self.writer.next_heartbeat(now));
+                let mut heartbeat = self.writer.heartbeat(ENTITYID_UNKNOWN, false);
+                for (locator, reader_guid, fragmented) in pending {
+                    let reader_id = reader_guid.entity_id();
+                    heartbeat.reader_id = reader_id;
+                    outgoing.push(Outgoing {
+                        locator,
+                        destination: reader_guid.guid_prefix(),
+                        submessage: RtpsSubmessage::Heartbeat(heartbeat.clone()),
+                    });
+                    for (sequence_number, last_fragment_num) in fragmented {
+                        let heartbeat_frag = self.writer.heartbeat_frag(
+                            reader_id,
+                            sequence_number,
+                            last_fragment_num,
+                        );
+                        outgoing.push(Outgoing {
+                            locator,
+                            destination: reader_guid.guid_prefix(),
+                            submessage: RtpsSubmessage::HeartbeatFrag(heartbeat_frag),
+                        });
+                    }
+                }
+            }
+        }
+        let outgoing = self.writer.batch(outgoing, now);
+        self.writer.pace(outgoing, now)
     }
 }
 
+impl Behavior for StatefulWriter {
+    fn guid(&self) -> Guid {
+        self.writer.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        match input {
+            Input::Received {
+                source, submessage, ..
+            } => match submessage {
+                RtpsSubmessage::AckNack(acknack) => self.on_acknack(source, &acknack, now),
+                RtpsSubmessage::NackFrag(nack_frag) => self.on_nack_frag(source, &nack_frag, now),
+                _ => {}
+            },
+            Input::NewChange(change) => self.add_change(stamp(change, now))?,
+            Input::Flush => self.writer.flush(),
+            Input::ParticipantRemoved(guid_prefix) => {
+                self.remove_participant(guid_prefix);
+            }
+            Input::TimerFired => {}
+        }
+        if !self.writer.remove_expired(now)?.is_empty() {
+            self.notify_acknowledgements();
+        }
+        let outgoing = self.poll(now);
+        if self.purge_acknowledged {
+            self.purge_acknowledged()?;
+        }
+        Ok(outputs(Vec::new(), outgoing, self.next_deadline()))
+    }
+}
+
+/// Lets other threads wait for the changes of a [`StatefulWriter`] to be
+/// acknowledged, e.g. for `wait_for_acknowledgments` or for a write blocked
+/// by the writer's resource limits. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct AckNotifier(Arc<(Mutex<AckState>, Condvar)>);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct AckState {
+    acknowledged: SequenceNumber,
+    cached: usize,
+}
+
+impl AckNotifier {
+    /// The highest sequence number up to which every change still in the
+    /// writer's cache has been acknowledged by all matched reliable readers.
+    #[must_use]
+    pub fn acknowledged(&self) -> SequenceNumber {
+        self.0 .0.lock().unwrap().acknowledged
+    }
+
+    /// The number of changes in the writer's cache.
+    #[must_use]
+    pub fn cached(&self) -> usize {
+        self.0 .0.lock().unwrap().cached
+    }
+
+    /// Blocks until the change with `sequence_number` is acknowledged or
+    /// `timeout` elapses, and returns whether it was acknowledged.
+    #[must_use]
+    pub fn wait_for_acknowledgement(
+        &self,
+        sequence_number: SequenceNumber,
+        timeout: Duration,
+    ) -> bool {
+        self.wait_until(timeout, |state| state.acknowledged >= sequence_number)
+    }
+
+    /// Blocks until the writer's cache holds fewer than `max_changes`
+    /// changes or `timeout` elapses, and returns whether it does.
+    #[must_use]
+    pub fn wait_for_space(&self, max_changes: usize, timeout: Duration) -> bool {
+        self.wait_until(timeout, |state| state.cached < max_changes)
+    }
+
+    fn wait_until(&self, timeout: Duration, condition: impl Fn(&AckState) -> bool) -> bool {
+        let (state, changed) = &*self.0;
+        let (state, _) = changed
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| !condition(state))
+            .unwrap();
+        condition(&state)
+    }
+
+    fn update(&self, acknowledged: SequenceNumber, cached: usize) {
+        let (state, changed) = &*self.0;
+        let new_state = AckState {
+            acknowledged,
+            cached,
+        };
+        let mut state = state.lock().unwrap();
+        if *state != new_state {
+            *state = new_state;
+            changed.notify_all();
+        }
+    }
+}
+
+/// The status of a change with respect to a matched reader.
+///
+/// See Section 8.4.7.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangeForReader {
+    status: ChangeForReaderStatusKind,
+    is_relevant: bool,
+    /// When the change was last sent, used for NACK suppression.
+    sent_at: Option<Time>,
+}
+
+impl ChangeForReader {
+    #[must_use]
+    pub const fn status(&self) -> ChangeForReaderStatusKind {
+        self.status
+    }
+
+    #[must_use]
+    pub const fn is_relevant(&self) -> bool {
+        self.is_relevant
+    }
+}
+
+/// Decides whether a change is relevant to a matched reader, e.g. a
+/// ContentFilteredTopic evaluated on the writer side.
+#[derive(Clone)]
+pub struct ContentFilter(Arc<dyn Fn(&CacheChange) -> bool + Send + Sync>);
+
+impl ContentFilter {
+    pub fn new(filter: impl Fn(&CacheChange) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(filter))
+    }
+
+    #[must_use]
+    pub fn matches(&self, change: &CacheChange) -> bool {
+        (self.0)(change)
+    }
+}
+
+impl fmt::Debug for ContentFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ContentFilter")
+    }
+}
+
+/// See Section 8.4.7.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Debug)]
 pub struct ReaderProxy {
     remote_reader_guid: Guid,
-    remote_group_guid: Guid,
+    remote_group_entity_id: EntityId,
     expects_inline_qos: bool,
     unicast_locator_list: Vec<Locator>,
     multicast_locator_list: Vec<Locator>,
-    highest_sent_change: Option<CacheChange>,
-    requested_changes: Vec<CacheChange>,
-    acknowledged_changes: Vec<CacheChange>,
+    is_active: bool,
+    /// Cleared for best-effort readers, which never acknowledge changes.
+    reliable: bool,
+    content_filter: Option<ContentFilter>,
+
+    changes_for_reader: BTreeMap<SequenceNumber, ChangeForReader>,
+    requested_fragments: BTreeMap<SequenceNumber, BTreeSet<FragmentNumber>>,
+    last_acknack_count: Option<Count>,
+    last_nack_frag_count: Option<Count>,
+    repair_at: Option<Time>,
 }
 
 impl ReaderProxy {
-    pub fn acked_changes_set(&self) -> &[SequenceNumber] {
-        todo!()
+    #[must_use]
+    pub fn new(
+        remote_reader_guid: Guid,
+        remote_group_entity_id: EntityId,
+        unicast_locator_list: Vec<Locator>,
+        multicast_locator_list: Vec<Locator>,
+        expects_inline_qos: bool,
+        is_active: bool,
+    ) -> Self {
+        Self {
+            remote_reader_guid,
+            remote_group_entity_id,
+            expects_inline_qos,
+            unicast_locator_list,
+            multicast_locator_list,
+            is_active,
+            reliable: true,
+            content_filter: None,
+            changes_for_reader: BTreeMap::new(),
+            requested_fragments: BTreeMap::new(),
+            last_acknack_count: None,
+            last_nack_frag_count: None,
+            repair_at: None,
+        }
+    }
+
+    /// Sets the reliability requested by the remote reader. A reliable
+    /// writer treats a best-effort reader as it would with a best-effort
+    /// writer: changes are sent once and never wait for acknowledgement.
+    #[must_use]
+    pub fn with_reliability_level(mut self, reliability_level: ReliabilityKind) -> Self {
+        self.reliable = reliability_level == ReliabilityKind::Reliable;
+        self
+    }
+
+    /// Sets the filter deciding which changes are relevant to the reader.
+    #[must_use]
+    pub fn with_content_filter(mut self, content_filter: ContentFilter) -> Self {
+        self.content_filter = Some(content_filter);
+        self
+    }
+
+    #[must_use]
+    pub const fn remote_reader_guid(&self) -> Guid {
+        self.remote_reader_guid
+    }
+
+    #[must_use]
+    pub const fn remote_group_entity_id(&self) -> EntityId {
+        self.remote_group_entity_id
+    }
+
+    #[must_use]
+    pub const fn expects_inline_qos(&self) -> bool {
+        self.expects_inline_qos
+    }
+
+    #[must_use]
+    pub fn unicast_locator_list(&self) -> &[Locator] {
+        &self.unicast_locator_list
+    }
+
+    #[must_use]
+    pub fn multicast_locator_list(&self) -> &[Locator] {
+        &self.multicast_locator_list
+    }
+
+    #[must_use]
+    pub const fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// The locator changes are sent to: the first unicast locator, or the
+    /// first multicast locator for readers without a unicast one.
+    fn locator(&self) -> Option<Locator> {
+        self.unicast_locator_list
+            .first()
+            .or_else(|| self.multicast_locator_list.first())
+            .copied()
+    }
+
+    /// The status of the change with `sequence_number` with respect to the
+    /// reader, or `None` if it is not tracked: acknowledged changes, those
+    /// sent to a best-effort reader and those announced with a GAP after
+    /// leaving the writer's cache are forgotten.
+    #[must_use]
+    pub fn change_for_reader(&self, sequence_number: SequenceNumber) -> Option<&ChangeForReader> {
+        self.changes_for_reader.get(&sequence_number)
+    }
+
+    /// Whether `change` is relevant to the reader: directed to it, if
+    /// directed at all, and accepted by its content filter.
+    fn filter(&self, change: &CacheChange) -> bool {
+        change.is_destined_for(&self.remote_reader_guid)
+            && self
+                .content_filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(change))
+    }
+
+    /// Tracks a new change. In pull mode a reliable reader is only
+    /// announced relevant changes by HEARTBEATs and sent them once it asks
+    /// for them; irrelevant ones are still sent as a GAP right away.
+    fn add_change(&mut self, sequence_number: SequenceNumber, is_relevant: bool, push_mode: bool) {
+        let status = if push_mode || !self.reliable || !is_relevant {
+            ChangeForReaderStatusKind::Unsent
+        } else {
+            ChangeForReaderStatusKind::Unacknowledged
+        };
+        self.changes_for_reader.insert(
+            sequence_number,
+            ChangeForReader {
+                status,
+                is_relevant,
+                sent_at: None,
+            },
+        );
     }
 
-    pub fn next_requested_change(&self) -> &CacheChange {
-        todo!()
+    fn is_relevant(&self, sequence_number: SequenceNumber) -> bool {
+        self.changes_for_reader
+            .get(&sequence_number)
+            .is_some_and(|c| c.is_relevant)
     }
 
-    pub fn next_unsent_change(&self) -> &CacheChange {
-        todo!()
+    fn is_acked(&self, sequence_number: SequenceNumber) -> bool {
+        self.changes_for_reader
+            .get(&sequence_number)
+            .is_none_or(|c| c.status == ChangeForReaderStatusKind::Acknowledged)
     }
 
-    pub fn requested_changes(&self) -> &[SequenceNumber] {
-        todo!()
+    fn has_unacknowledged_changes(&self) -> bool {
+        self.changes_for_reader
+            .values()
+            .any(|c| c.status != ChangeForReaderStatusKind::Acknowledged)
     }
 
-    pub fn requested_changes_set(&self) -> &[SequenceNumber] {
-        todo!()
+    /// Records that the change was sent at `now`. Best-effort readers never
+    /// acknowledge, so the change is considered acknowledged right away and
+    /// no longer tracked.
+    fn mark_sent(&mut self, sequence_number: SequenceNumber, now: Time) {
+        if !self.reliable {
+            self.changes_for_reader.remove(&sequence_number);
+        } else if let Some(change) = self.changes_for_reader.get_mut(&sequence_number) {
+            change.sent_at = Some(now);
+            change.status = ChangeForReaderStatusKind::Underway;
+        }
     }
 
-    pub fn unsent_changes(&self) -> &[SequenceNumber] {
-        todo!()
+    /// Moves changes sent at least `nack_suppression_delay` ago from
+    /// UNDERWAY to UNACKNOWLEDGED, so that NACKs for them are honored again.
+    fn end_suppression(&mut self, now: Time, nack_suppression_delay: Duration) {
+        for change in self.changes_for_reader.values_mut() {
+            if change.status == ChangeForReaderStatusKind::Underway
+                && change
+                    .sent_at
+                    .is_none_or(|t| t + nack_suppression_delay <= now)
+            {
+                change.status = ChangeForReaderStatusKind::Unacknowledged;
+            }
+        }
+    }
+
+    /// Marks every change with a sequence number below `committed_seq_num`
+    /// as acknowledged. They are no longer tracked.
+    pub fn acked_changes_set(&mut self, committed_seq_num: SequenceNumber) {
+        self.changes_for_reader = self.changes_for_reader.split_off(&committed_seq_num);
+        self.requested_fragments = self.requested_fragments.split_off(&committed_seq_num);
+    }
+
+    /// Stops tracking the change with `sequence_number`, e.g. once it left
+    /// the writer's cache and the reader was sent a GAP for it.
+    fn forget(&mut self, sequence_number: SequenceNumber) {
+        self.changes_for_reader.remove(&sequence_number);
+        self.requested_fragments.remove(&sequence_number);
+    }
+
+    /// The lowest requested sequence number.
+    #[must_use]
+    pub fn next_requested_change(&self) -> Option<SequenceNumber> {
+        self.requested_changes().next()
+    }
+
+    /// The lowest unsent sequence number.
+    #[must_use]
+    pub fn next_unsent_change(&self) -> Option<SequenceNumber> {
+        self.unsent_changes().next()
+    }
+
+    pub fn requested_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.with_status(ChangeForReaderStatusKind::Requested)
+    }
+
+    /// Marks the changes in `req_seq_num_set` as requested. Changes that are
+    /// still UNDERWAY are NACK-suppressed and left untouched. Sequence
+    /// numbers that were never sent to the reader are tracked from now on so
+    /// that they can be answered with a GAP.
+    pub fn requested_changes_set(
+        &mut self,
+        req_seq_num_set: impl IntoIterator<Item = SequenceNumber>,
+    ) {
+        for sequence_number in req_seq_num_set {
+            let change =
+                self.changes_for_reader
+                    .entry(sequence_number)
+                    .or_insert(ChangeForReader {
+                        status: ChangeForReaderStatusKind::Requested,
+                        is_relevant: true,
+                        sent_at: None,
+                    });
+            if change.status != ChangeForReaderStatusKind::Underway {
+                change.status = ChangeForReaderStatusKind::Requested;
+            }
+        }
+    }
+
+    /// Fragments requested by the reader, per sequence number.
+    pub fn requested_fragments(
+        &self,
+    ) -> impl Iterator<Item = (SequenceNumber, &BTreeSet<FragmentNumber>)> + '_ {
+        self.requested_fragments.iter().map(|(sn, f)| (*sn, f))
+    }
+
+    /// Marks `fragments` of the change with `sequence_number` as requested.
+    /// Only changes that were sent and are awaiting acknowledgement can have
+    /// fragments repaired; unsent and requested changes are sent whole.
+    pub fn requested_fragments_set(
+        &mut self,
+        sequence_number: SequenceNumber,
+        fragments: impl IntoIterator<Item = FragmentNumber>,
+    ) {
+        if self
+            .changes_for_reader
+            .get(&sequence_number)
+            .is_some_and(|c| c.status == ChangeForReaderStatusKind::Unacknowledged)
+        {
+            self.requested_fragments
+                .entry(sequence_number)
+                .or_default()
+                .extend(fragments);
+        }
+    }
+
+    /// Changes that were sent and are awaiting acknowledgement.
+    fn sent_unacked_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.changes_for_reader
+            .iter()
+            .filter(|(_, c)| {
+                matches!(
+                    c.status,
+                    ChangeForReaderStatusKind::Underway | ChangeForReaderStatusKind::Unacknowledged
+                )
+            })
+            .map(|(sn, _)| *sn)
+    }
+
+    pub fn unsent_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.with_status(ChangeForReaderStatusKind::Unsent)
+    }
+
+    pub fn unacked_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.with_status(ChangeForReaderStatusKind::Unacknowledged)
+    }
+
+    fn with_status(
+        &self,
+        status: ChangeForReaderStatusKind,
+    ) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.changes_for_reader
+            .iter()
+            .filter(move |(_, c)| c.status == status)
+            .map(|(sn, _)| *sn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::writer::{
+            batching::Batching,
+            flow_control::{PriorityScheduler, SharedFlowController, TokenBucket},
+            tests::writer,
+        },
+        messages::{FragmentNumberSet, SequenceNumberSet},
+        structure::{data::Data, participant::Endpoint, ChangeKind, InstanceHandle, ParameterList},
+    };
+
+    pub(crate) const READER_PREFIX: GuidPrefix = [2; 12];
+
+    pub(crate) fn reader_guid(key: u8) -> Guid {
+        Guid::new(READER_PREFIX, EntityId::new([0, 0, key], 0x07))
+    }
+
+    pub(crate) fn reader_proxy(key: u8) -> ReaderProxy {
+        ReaderProxy::new(
+            reader_guid(key),
+            ENTITYID_UNKNOWN,
+            vec![format!("127.0.0.1:{}", 7400 + u16::from(key))
+                .parse()
+                .unwrap()],
+            vec![],
+            false,
+            true,
+        )
+    }
+
+    pub(crate) fn acknack(key: u8, base: u64, missing: &[u64], count: Count) -> AckNackSubmessage {
+        AckNackSubmessage {
+            reader_id: reader_guid(key).entity_id(),
+            writer_id: ENTITYID_UNKNOWN,
+            reader_sn_state: SequenceNumberSet::new(
+                SequenceNumber::from(base),
+                missing.iter().map(|sn| SequenceNumber::from(*sn)),
+            ),
+            count,
+            final_flag: false,
+        }
+    }
+
+    pub(crate) fn add_change(writer: &mut StatefulWriter) -> SequenceNumber {
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 8])),
+            ParameterList,
+            InstanceHandle,
+        );
+        let sequence_number = change.sequence_number();
+        writer.add_change(change).unwrap();
+        sequence_number
+    }
+
+    pub(crate) fn data(outgoing: &[Outgoing]) -> Vec<(u16, u64)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Data(data) => Some((o.locator.port(), data.writer_sn.value())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn heartbeats(outgoing: &[Outgoing]) -> Vec<(u16, u64, u64)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Heartbeat(hb) => {
+                    Some((o.locator.port(), hb.first_sn.value(), hb.last_sn.value()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn gaps(outgoing: &[Outgoing]) -> Vec<(u16, u64, u64, Vec<u64>)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Gap(gap) => Some((
+                    o.locator.port(),
+                    gap.gap_start.value(),
+                    gap.gap_list.base().value(),
+                    gap.gap_list.iter().map(|sn| sn.value()).collect(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Changes that are no longer tracked are acknowledged.
+    fn status(writer: &StatefulWriter, key: u8, sn: u64) -> ChangeForReaderStatusKind {
+        writer
+            .matched_reader_lookup(&reader_guid(key))
+            .unwrap()
+            .change_for_reader(SequenceNumber::from(sn))
+            .map_or(
+                ChangeForReaderStatusKind::Acknowledged,
+                ChangeForReader::status,
+            )
+    }
+
+    fn tracked(writer: &StatefulWriter, key: u8) -> usize {
+        writer
+            .matched_reader_lookup(&reader_guid(key))
+            .unwrap()
+            .changes_for_reader
+            .len()
+    }
+
+    #[test]
+    fn test_reliable_acknowledgement() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        add_change(&mut writer);
+        writer.matched_reader_add(reader_proxy(1));
+        writer.matched_reader_add(reader_proxy(2));
+        add_change(&mut writer);
+
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(data(&out), vec![(7401, 1), (7401, 2), (7402, 1), (7402, 2)]);
+        assert_eq!(heartbeats(&out), vec![(7401, 1, 2), (7402, 1, 2)]);
+        assert_eq!(status(&writer, 1, 1), ChangeForReaderStatusKind::Underway);
+
+        writer.on_acknack(READER_PREFIX, &acknack(1, 3, &[], 1), Time::new(0, 0));
+        assert_eq!(
+            status(&writer, 1, 2),
+            ChangeForReaderStatusKind::Acknowledged
+        );
+        assert!(!writer.is_acked_by_all(SequenceNumber::from(2)));
+        writer.on_acknack(READER_PREFIX, &acknack(2, 3, &[], 1), Time::new(0, 0));
+        assert!(writer.is_acked_by_all(SequenceNumber::from(2)));
+
+        // Nothing is left unacknowledged, so heartbeats stop.
+        assert!(writer.poll(Time::new(1, 0)).is_empty());
+        assert_eq!(writer.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_nack_response_and_suppression() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.writer_mut().nack_suppression_delay = Duration::from_millis(100);
+        writer.matched_reader_add(reader_proxy(1));
+        add_change(&mut writer);
+        add_change(&mut writer);
+        writer.poll(Time::new(0, 0));
+
+        // Within the suppression window the NACK is ignored.
+        writer.on_acknack(READER_PREFIX, &acknack(1, 1, &[1, 2], 1), Time::new(0, 0));
+        assert_eq!(status(&writer, 1, 1), ChangeForReaderStatusKind::Underway);
+
+        let t = Time::from(Duration::from_millis(150));
+        assert!(data(&writer.poll(t)).is_empty());
+        assert_eq!(
+            status(&writer, 1, 1),
+            ChangeForReaderStatusKind::Unacknowledged
+        );
+        writer.on_acknack(READER_PREFIX, &acknack(1, 2, &[2], 2), t);
+        assert_eq!(
+            status(&writer, 1, 1),
+            ChangeForReaderStatusKind::Acknowledged
+        );
+        assert_eq!(status(&writer, 1, 2), ChangeForReaderStatusKind::Requested);
+
+        // The repair waits for nack_response_delay.
+        let repair_at = t + Duration::from_millis(200);
+        assert_eq!(writer.next_deadline(), Some(repair_at));
+        assert!(data(&writer.poll(t)).is_empty());
+        assert_eq!(data(&writer.poll(repair_at)), vec![(7401, 2)]);
+        assert_eq!(status(&writer, 1, 2), ChangeForReaderStatusKind::Underway);
+    }
+
+    #[test]
+    fn test_gaps_for_irrelevant_changes() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.matched_reader_add(reader_proxy(1));
+        writer.matched_reader_add(
+            reader_proxy(2)
+                .with_content_filter(ContentFilter::new(|c| c.sequence_number().value() % 3 == 0)),
+        );
+        for _ in 0..6 {
+            add_change(&mut writer);
+        }
+        let removed = writer
+            .writer()
+            .writer_cache()
+            .get_change(writer.writer().guid(), SequenceNumber::from(6))
+            .cloned()
+            .unwrap();
+        writer
+            .writer_mut()
+            .writer_cache_mut()
+            .remove_change(&removed)
+            .unwrap();
+
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(
+            data(&out),
+            vec![
+                (7401, 1),
+                (7401, 2),
+                (7401, 3),
+                (7401, 4),
+                (7401, 5),
+                (7402, 3)
+            ]
+        );
+        assert_eq!(
+            gaps(&out),
+            vec![(7401, 6, 7, vec![]), (7402, 1, 3, vec![4, 5, 6])]
+        );
+
+        // Requests for removed changes and for changes the reader was never
+        // sent are answered with a GAP; those beyond the last change are
+        // ignored.
+        writer.matched_reader_add(reader_proxy(3));
+        writer.poll(Time::new(0, 0));
+        let now = Time::new(0, 0);
+        writer.on_acknack(READER_PREFIX, &acknack(1, 1, &[1, 6, 7], 1), now);
+        writer.on_acknack(READER_PREFIX, &acknack(3, 6, &[6], 1), now);
+        let out = writer.poll(now + Duration::from_millis(200));
+        assert_eq!(data(&out), vec![(7401, 1)]);
+        assert_eq!(gaps(&out), vec![(7401, 6, 7, vec![]), (7403, 6, 7, vec![])]);
+    }
+
+    #[test]
+    fn test_gaps_for_evicted_changes() {
+        let mut writer =
+            StatefulWriter::new(writer(ReliabilityKind::Reliable).with_history_depth(2));
+        writer.matched_reader_add(reader_proxy(1));
+        add_change(&mut writer);
+        let now = Time::new(0, 0);
+        assert_eq!(data(&writer.poll(now)), vec![(7401, 1)]);
+
+        // Change 2 is evicted before it is sent; the sent change 1 is only
+        // sent as a GAP once requested.
+        for _ in 0..3 {
+            add_change(&mut writer);
+        }
+        assert_eq!(writer.writer().writer_cache().len(), 2);
+        let out = writer.poll(now);
+        assert_eq!(data(&out), vec![(7401, 3), (7401, 4)]);
+        assert_eq!(gaps(&out), vec![(7401, 2, 3, vec![])]);
+
+        writer.on_acknack(READER_PREFIX, &acknack(1, 1, &[1], 1), now);
+        let out = writer.poll(now + Duration::from_millis(200));
+        assert!(data(&out).is_empty());
+        assert_eq!(gaps(&out), vec![(7401, 1, 2, vec![])]);
+    }
+
+    #[test]
+    fn test_directed_changes() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        for key in 1..=3 {
+            writer.matched_reader_add(reader_proxy(key));
+        }
+        add_change(&mut writer);
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 8])),
+            ParameterList,
+            InstanceHandle,
+        );
+        writer
+            .add_change(change.with_destination([reader_guid(2), reader_guid(3)]))
+            .unwrap();
+
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(
+            data(&out),
+            vec![(7401, 1), (7402, 1), (7402, 2), (7403, 1), (7403, 2)]
+        );
+        assert_eq!(gaps(&out), vec![(7401, 2, 3, vec![])]);
+        assert!(!writer.is_acked_by_all(SequenceNumber::from(2)));
+
+        // Readers matched later are only sent the changes directed to them.
+        writer.matched_reader_add(reader_proxy(4));
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(data(&out), vec![(7404, 1)]);
+        assert_eq!(gaps(&out), vec![(7404, 2, 3, vec![])]);
+    }
+
+    #[test]
+    fn test_purge_acknowledged() {
+        let mut writer =
+            StatefulWriter::new(writer(ReliabilityKind::Reliable)).with_purge_acknowledged(true);
+        writer.matched_reader_add(reader_proxy(1));
+        writer.matched_reader_add(
+            reader_proxy(2).with_reliability_level(ReliabilityKind::BestEffort),
+        );
+        let notifier = writer.ack_notifier();
+        for _ in 0..3 {
+            add_change(&mut writer);
+        }
+        assert_eq!(notifier.cached(), 3);
+        let now = Time::new(0, 0);
+        writer.handle(Input::TimerFired, now).unwrap();
+        assert_eq!(notifier.acknowledged(), SequenceNumber::from(0));
+        assert!(!notifier.wait_for_acknowledgement(SequenceNumber::from(1), Duration::ZERO));
+
+        let waiter = notifier.clone();
+        let blocked = std::thread::spawn(move || {
+            waiter.wait_for_acknowledgement(SequenceNumber::from(2), Duration::from_secs(10))
+                && waiter.wait_for_space(2, Duration::from_secs(10))
+        });
+        let acknack = |base, missing: &[u64], count| Input::Received {
+            source: READER_PREFIX,
+            source_locator: "127.0.0.1:7401".parse().unwrap(),
+            submessage: RtpsSubmessage::AckNack(acknack(1, base, missing, count)),
+        };
+        writer.handle(acknack(3, &[], 1), now).unwrap();
+        assert!(blocked.join().unwrap());
+        assert_eq!(notifier.acknowledged(), SequenceNumber::from(2));
+        assert_eq!(
+            writer
+                .writer()
+                .writer_cache()
+                .changes()
+                .map(|c| c.sequence_number().value())
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+
+        // A purged change the reader requests again is answered with a GAP.
+        writer.handle(acknack(1, &[1, 2], 2), now).unwrap();
+        let out = writer.poll(now + Duration::from_millis(200));
+        assert_eq!(gaps(&out), vec![(7401, 1, 3, vec![])]);
+
+        writer.handle(acknack(4, &[], 3), now).unwrap();
+        assert!(writer.writer().writer_cache().is_empty());
+        assert!(notifier.wait_for_acknowledgement(SequenceNumber::from(3), Duration::ZERO));
+        assert!(notifier.wait_for_space(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_forgets_settled_changes() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.matched_reader_add(reader_proxy(1));
+        writer.matched_reader_add(
+            reader_proxy(2).with_reliability_level(ReliabilityKind::BestEffort),
+        );
+        for _ in 0..3 {
+            add_change(&mut writer);
+        }
+        let now = Time::new(0, 0);
+        writer.poll(now);
+        writer.poll(now);
+        // Best-effort readers forget changes once sent.
+        assert_eq!(tracked(&writer, 2), 0);
+        assert_eq!(tracked(&writer, 1), 3);
+
+        // Changes removed from the cache are forgotten once GAP-ed.
+        let first = writer.writer().writer_cache().changes().next().cloned();
+        writer
+            .writer_mut()
+            .writer_cache_mut()
+            .remove_change(&first.unwrap())
+            .unwrap();
+        writer.on_acknack(READER_PREFIX, &acknack(1, 1, &[1], 1), now);
+        let out = writer.poll(now + Duration::from_millis(200));
+        assert_eq!(gaps(&out), vec![(7401, 1, 2, vec![])]);
+        assert_eq!(tracked(&writer, 1), 2);
+
+        // Acknowledged changes are forgotten.
+        writer.on_acknack(READER_PREFIX, &acknack(1, 4, &[], 2), now);
+        assert_eq!(tracked(&writer, 1), 0);
+        assert!(writer.is_acked_by_all(SequenceNumber::from(3)));
+        assert_eq!(writer.next_deadline(), Some(Time::new(1, 0)));
+    }
+
+    #[test]
+    fn test_pull_mode() {
+        let guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let mut writer = StatefulWriter::new(Writer::new(
+            Endpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::from_secs(1),
+            Duration::ZERO,
+            Duration::ZERO,
+        ));
+        writer.matched_reader_add(reader_proxy(1));
+        writer.matched_reader_add(
+            reader_proxy(2)
+                .with_content_filter(ContentFilter::new(|c| c.sequence_number().value() != 2)),
+        );
+        writer.matched_reader_add(
+            reader_proxy(3).with_reliability_level(ReliabilityKind::BestEffort),
+        );
+        for _ in 0..3 {
+            add_change(&mut writer);
+        }
+
+        // Only best-effort readers are pushed the changes; reliable ones are
+        // told about them with a HEARTBEAT, and GAPs for the changes that
+        // are not relevant to them.
+        let now = Time::new(0, 0);
+        let out = writer.poll(now);
+        assert_eq!(data(&out), vec![(7403, 1), (7403, 2), (7403, 3)]);
+        assert_eq!(gaps(&out), vec![(7402, 2, 3, vec![])]);
+        assert_eq!(heartbeats(&out), vec![(7401, 1, 3), (7402, 1, 3)]);
+        assert_eq!(
+            status(&writer, 1, 1),
+            ChangeForReaderStatusKind::Unacknowledged
+        );
+
+        writer.on_acknack(READER_PREFIX, &acknack(1, 2, &[2, 3], 1), now);
+        writer.on_acknack(READER_PREFIX, &acknack(2, 1, &[], 1), now);
+        let out = writer.poll(now);
+        assert_eq!(data(&out), vec![(7401, 2), (7401, 3)]);
+        assert!(gaps(&out).is_empty());
+        // Changes nobody asks for are never sent.
+        assert_eq!(
+            status(&writer, 2, 1),
+            ChangeForReaderStatusKind::Unacknowledged
+        );
+    }
+
+    #[test]
+    fn test_multicast_fan_out() {
+        let group: Locator = "239.255.0.1:7500".parse().unwrap();
+        let multicast_reader = |key: u8| {
+            ReaderProxy::new(
+                reader_guid(key),
+                ENTITYID_UNKNOWN,
+                vec![format!("127.0.0.1:{}", 7400 + u16::from(key))
+                    .parse()
+                    .unwrap()],
+                vec![group],
+                false,
+                true,
+            )
+        };
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.matched_reader_add(multicast_reader(1));
+        writer.matched_reader_add(
+            multicast_reader(2)
+                .with_content_filter(ContentFilter::new(|c| c.sequence_number().value() != 2)),
+        );
+        writer.matched_reader_add(reader_proxy(3));
+        for _ in 0..3 {
+            add_change(&mut writer);
+        }
+
+        // Change 2 is filtered out for reader 2, so the group cannot be sent
+        // it.
+        let now = Time::new(0, 0);
+        let out = writer.poll(now);
+        assert_eq!(
+            data(&out),
+            vec![
+                (7500, 1),
+                (7500, 3),
+                (7401, 2),
+                (7403, 1),
+                (7403, 2),
+                (7403, 3)
+            ]
+        );
+        assert!(out
+            .iter()
+            .filter(|o| o.locator == group)
+            .all(|o| o.destination == GUIDPREFIX_UNKNOWN));
+        assert_eq!(gaps(&out), vec![(7402, 2, 3, vec![])]);
+        assert_eq!(status(&writer, 2, 1), ChangeForReaderStatusKind::Underway);
+
+        // Repairs are unicast.
+        writer.on_acknack(READER_PREFIX, &acknack(2, 1, &[1], 1), now);
+        let out = writer.poll(now + Duration::from_millis(200));
+        assert_eq!(data(&out), vec![(7402, 1)]);
+    }
+
+    #[test]
+    fn test_fragment_repair() {
+        let mut writer =
+            StatefulWriter::new(writer(ReliabilityKind::Reliable).with_fragment_size(4));
+        writer.matched_reader_add(reader_proxy(1));
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 10])),
+            ParameterList,
+            InstanceHandle,
+        );
+        writer.add_change(change).unwrap();
+
+        let fragments = |outgoing: &[Outgoing]| -> Vec<(u64, FragmentNumber)> {
+            outgoing
+                .iter()
+                .filter_map(|o| match &o.submessage {
+                    RtpsSubmessage::DataFrag(frag) => {
+                        Some((frag.writer_sn.value(), frag.fragment_starting_num))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(fragments(&out), vec![(1, 1), (1, 2), (1, 3)]);
+        assert!(data(&out).is_empty());
+        let heartbeat_frags: Vec<_> = out
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::HeartbeatFrag(hb) => {
+                    Some((hb.writer_sn.value(), hb.last_fragment_num))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(heartbeat_frags, vec![(1, 3)]);
+
+        let nack_frag = NackFragSubmessage {
+            reader_id: reader_guid(1).entity_id(),
+            writer_id: writer.writer().guid().entity_id(),
+            writer_sn: SequenceNumber::from(1),
+            fragment_number_state: FragmentNumberSet::new(2, [2]),
+            count: 1,
+        };
+        writer.on_nack_frag(READER_PREFIX, &nack_frag, Time::new(0, 0));
+        // A duplicate NACK_FRAG is ignored.
+        writer.on_nack_frag(READER_PREFIX, &nack_frag, Time::new(0, 0));
+        let out = writer.poll(Time::from(Duration::from_millis(200)));
+        assert_eq!(fragments(&out), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_best_effort_reader_of_reliable_writer() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.matched_reader_add(
+            reader_proxy(1).with_reliability_level(ReliabilityKind::BestEffort),
+        );
+        add_change(&mut writer);
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(data(&out), vec![(7401, 1)]);
+        assert!(heartbeats(&out).is_empty());
+        assert!(writer.is_acked_by_all(SequenceNumber::from(1)));
+    }
+
+    #[test]
+    fn test_lifespan() {
+        let lifespan = Duration::from_millis(500);
+        let mut writer =
+            StatefulWriter::new(writer(ReliabilityKind::Reliable).with_lifespan(lifespan));
+        writer.matched_reader_add(reader_proxy(1));
+        let now = Time::new(10, 0);
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 8])),
+            ParameterList,
+            InstanceHandle,
+        );
+        let outputs = writer.handle(Input::NewChange(change), now).unwrap();
+        // The change is stamped with the time it was written.
+        assert!(outputs.iter().any(|o| matches!(
+            o,
+            Output::Send(Outgoing {
+                submessage: RtpsSubmessage::Data(data),
+                ..
+            }) if data.source_timestamp == Some(now)
+        )));
+        assert_eq!(writer.writer().expiry_deadline(), Some(now + lifespan));
+
+        writer.handle(Input::TimerFired, now + lifespan).unwrap();
+        assert!(writer.writer().writer_cache().is_empty());
+        assert_eq!(writer.next_deadline(), Some(Time::new(11, 0)));
+
+        // The expired change is answered with a GAP.
+        writer.on_acknack(READER_PREFIX, &acknack(1, 1, &[1], 1), now + lifespan);
+        let out = writer.poll(Time::new(11, 0));
+        assert_eq!(gaps(&out), vec![(7401, 1, 2, vec![])]);
+    }
+
+    #[test]
+    fn test_batching() {
+        let at = |millis| Time::from(Duration::from_millis(millis));
+        let batching = Batching::new(1024, 3, Duration::from_millis(10));
+        let mut writer =
+            StatefulWriter::new(writer(ReliabilityKind::Reliable).with_batching(batching));
+        writer.matched_reader_add(reader_proxy(1));
+
+        // The HEARTBEAT waits for the DATA it announces.
+        add_change(&mut writer);
+        add_change(&mut writer);
+        assert!(writer.poll(at(0)).is_empty());
+        assert_eq!(writer.writer().batched_len(), 3);
+        assert_eq!(writer.writer().batch_deadline(), Some(at(10)));
+
+        // The third sample fills the batch.
+        add_change(&mut writer);
+        let outgoing = writer.poll(at(1));
+        assert_eq!(data(&outgoing), vec![(7401, 1), (7401, 2), (7401, 3)]);
+        assert_eq!(heartbeats(&outgoing), vec![(7401, 1, 2)]);
+        assert_eq!(writer.writer().batched_len(), 0);
+
+        // A batch is sent when flushed or once its delay has elapsed.
+        add_change(&mut writer);
+        assert!(writer.poll(at(2)).is_empty());
+        let outputs = writer.handle(Input::Flush, at(3)).unwrap();
+        assert!(outputs.iter().any(|o| matches!(
+            o,
+            Output::Send(Outgoing {
+                submessage: RtpsSubmessage::Data(d),
+                ..
+            }) if d.writer_sn == SequenceNumber::from(4)
+        )));
+
+        add_change(&mut writer);
+        assert!(writer.poll(at(4)).is_empty());
+        assert!(writer.poll(at(13)).is_empty());
+        assert_eq!(data(&writer.poll(at(14))), vec![(7401, 5)]);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        // Each DATA is charged 32 bytes: two fit in a burst, then one more
+        // every 100ms.
+        let bucket = SharedFlowController::new(TokenBucket::new(320, 64));
+        let mut paced = StatefulWriter::new(
+            writer(ReliabilityKind::Reliable).with_flow_controller(bucket.clone(), 0),
+        );
+        paced.matched_reader_add(reader_proxy(1));
+        for _ in 0..5 {
+            add_change(&mut paced);
+        }
+        let mut now = Time::new(0, 0);
+        let outgoing = paced.poll(now);
+        assert_eq!(data(&outgoing), vec![(7401, 1), (7401, 2)]);
+        assert_eq!(heartbeats(&outgoing), vec![(7401, 1, 5)]);
+        assert_eq!(paced.writer().paced_len(), 3);
+
+        for sn in 3..=5 {
+            now = paced.writer().pace_deadline().unwrap();
+            assert!(paced.next_deadline() <= Some(now));
+            assert_eq!(data(&paced.poll(now)), vec![(7401, sn)]);
+        }
+        assert!((now.as_duration().as_secs_f64() - 0.3).abs() < 1e-6);
+        assert_eq!(paced.writer().paced_len(), 0);
+        assert_eq!(bucket.next_available(), None);
+
+        // A higher priority writer sharing the controller goes first.
+        let shared = SharedFlowController::new(PriorityScheduler::new(
+            TokenBucket::new(320, 64),
+            Duration::from_millis(10),
+        ));
+        let high_guid = Guid::new([1; 12], EntityId::new([0, 0, 9], 0x02));
+        let mut low = StatefulWriter::new(
+            writer(ReliabilityKind::BestEffort).with_flow_controller(shared.clone(), 1),
+        );
+        let mut high = StatefulWriter::new(
+            Writer::new(
+                Endpoint::builder(high_guid).build(),
+                true,
+                Duration::from_secs(1),
+                Duration::ZERO,
+                Duration::ZERO,
+            )
+            .with_flow_controller(shared, 2),
+        );
+        for w in [&mut low, &mut high] {
+            w.matched_reader_add(reader_proxy(1));
+            for _ in 0..3 {
+                add_change(w);
+            }
+        }
+        let now = Time::new(0, 0);
+        assert_eq!(data(&high.poll(now)).len(), 2);
+        assert!(data(&low.poll(now)).is_empty());
+        let now = now + Duration::from_millis(100);
+        assert_eq!(data(&low.poll(now)), vec![]);
+        assert_eq!(data(&high.poll(now)), vec![(7401, 3)]);
+        let now = now + Duration::from_millis(100);
+        assert_eq!(data(&low.poll(now)), vec![(7401, 1)]);
     }
 
-    pub fn unacked_changes(&self) -> &[SequenceNumber] {
-        todo!()
+    #[test]
+    fn test_matched_reader_remove() {
+        let mut writer = StatefulWriter::new(writer(ReliabilityKind::Reliable));
+        writer.matched_reader_add(reader_proxy(1));
+        add_change(&mut writer);
+        assert!(!writer.is_acked_by_all(SequenceNumber::from(1)));
+        assert!(writer.matched_reader_remove(&reader_guid(1)).is_some());
+        assert!(writer.is_acked_by_all(SequenceNumber::from(1)));
+        assert!(writer.poll(Time::new(0, 0)).is_empty());
     }
 }
@@ -1,49 +1,606 @@
-use crate::structure::{historycache::CacheChange, SequenceNumber};
+//! The RTPS StatelessWriter has no knowledge of the matched readers. It keeps
+//! a [`ReaderLocator`] per destination and sends every change to each of
+//! them.
+//!
+//! A best-effort StatelessWriter only pushes changes. A reliable one also
+//! sends periodic HEARTBEATs and repairs the changes requested in ACKNACKs.
+//! As it keeps no per-reader state, an ACKNACK is attributed to the
+//! [`ReaderLocator`] matching the locator it was received from.
+//!
+//! ACKNACKs from a reader that sends from another address than the locator
+//! it is reached through, such as a multicast locator or one of several
+//! interfaces, match no [`ReaderLocator`] and are ignored. Such readers only
+//! recover missed changes when they are resent, see
+//! [`StatelessWriter::with_resend_period`]; a
+//! [`StatefulWriter`](super::stateful::StatefulWriter), which attributes
+//! ACKNACKs by reader GUID, suits them better.
+//!
+//! See Sections 8.4.7.3, 8.4.8.1 and 8.4.8.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
 
-use super::Writer;
+use std::{collections::BTreeSet, io};
 
+use crate::{
+    behavior::{outputs, Behavior, Duration, Input, Outgoing, Output},
+    messages::{AckNackSubmessage, Count, GapSubmessage, RtpsSubmessage, SequenceNumberSet, Time},
+    structure::{
+        historycache::{CacheChange, HistoryCache},
+        Guid, Locator, ReliabilityKind, SequenceNumber, ENTITYID_UNKNOWN, GUIDPREFIX_UNKNOWN,
+    },
+};
+
+use super::{change_submessages, gap_submessages, stamp, Writer};
+
+#[derive(Debug)]
 pub struct StatelessWriter {
     writer: Writer,
     reader_locators: Vec<ReaderLocator>,
+
+    resend_period: Option<Duration>,
+    next_resend: Option<Time>,
+    next_heartbeat: Option<Time>,
 }
 
 impl StatelessWriter {
-    pub fn reader_locator_add(&mut self, _reader: ReaderLocator) {
-        todo!()
+    #[must_use]
+    pub fn new(writer: Writer) -> Self {
+        Self {
+            writer,
+            reader_locators: Vec::new(),
+            resend_period: None,
+            next_resend: None,
+            next_heartbeat: None,
+        }
+    }
+
+    /// Periodically calls [`StatelessWriter::unsent_changes_reset`] so that
+    /// every change in the cache is sent again, as SPDP does to announce a
+    /// participant.
+    #[must_use]
+    pub fn with_resend_period(mut self, resend_period: Duration) -> Self {
+        self.resend_period = Some(resend_period);
+        self
+    }
+
+    #[must_use]
+    pub const fn writer(&self) -> &Writer {
+        &self.writer
+    }
+
+    pub fn writer_mut(&mut self) -> &mut Writer {
+        &mut self.writer
+    }
+
+    #[must_use]
+    pub fn reader_locators(&self) -> &[ReaderLocator] {
+        &self.reader_locators
+    }
+
+    /// Adds a change created with [`Writer::new_change`] to the writer's
+    /// cache. It is sent to every locator on the next
+    /// [`StatelessWriter::poll`]. Older changes beyond the history depth are
+    /// evicted; reliable locators are sent a GAP for those not yet sent.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot store the change.
+    pub fn add_change(&mut self, change: CacheChange) -> io::Result<()> {
+        self.writer.writer_cache.add_change(change)?;
+        self.writer.remove_evicted()?;
+        Ok(())
+    }
+
+    /// Removes `change` from the writer's cache. Locators that requested it
+    /// are sent a GAP instead.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the HistoryCache cannot record the removal.
+    pub fn remove_change(&mut self, change: &CacheChange) -> io::Result<Option<CacheChange>> {
+        self.writer.writer_cache.remove_change(change)
     }
 
-    pub fn reader_locator_remove(&mut self, _reader: &ReaderLocator) {
-        todo!()
+    /// Adds a locator. Locators already known are ignored so that the
+    /// locator does not receive every change twice.
+    pub fn reader_locator_add(&mut self, reader: ReaderLocator) {
+        if !self
+            .reader_locators
+            .iter()
+            .any(|r| r.locator == reader.locator)
+        {
+            self.reader_locators.push(reader);
+        }
     }
 
+    pub fn reader_locator_remove(&mut self, locator: &Locator) -> Option<ReaderLocator> {
+        self.reader_locators
+            .iter()
+            .position(|r| r.locator == *locator)
+            .map(|index| self.reader_locators.remove(index))
+    }
+
+    /// Marks every change as unsent to every locator.
     pub fn unsent_changes_reset(&mut self) {
-        todo!()
+        for reader_locator in &mut self.reader_locators {
+            reader_locator.unsent_changes_reset();
+        }
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.writer.endpoint.reliability_level() == ReliabilityKind::Reliable
+    }
+
+    /// Handles an ACKNACK received from `source`. The requested changes are
+    /// repaired by the first [`StatelessWriter::poll`] after
+    /// `nack_response_delay` has elapsed. Best-effort writers ignore
+    /// ACKNACKs, as do all writers when `source` is not the locator of a
+    /// [`ReaderLocator`].
+    pub fn on_acknack(&mut self, source: Locator, acknack: &AckNackSubmessage, now: Time) {
+        if !self.is_reliable() {
+            return;
+        }
+        let nack_response_delay = self.writer.nack_response_delay;
+        let Some(reader_locator) = self
+            .reader_locators
+            .iter_mut()
+            .find(|r| r.locator == source)
+        else {
+            return;
+        };
+        if reader_locator
+            .last_acknack_count
+            .is_some_and(|count| acknack.count <= count)
+        {
+            return;
+        }
+        reader_locator.last_acknack_count = Some(acknack.count);
+        reader_locator.requested_changes_set(acknack.reader_sn_state.iter());
+        if !reader_locator.requested_changes.is_empty() && reader_locator.repair_at.is_none() {
+            reader_locator.repair_at = Some(now + nack_response_delay);
+        }
+    }
+
+    /// The next time at which [`StatelessWriter::poll`] has periodic or
+    /// delayed work to do.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Time> {
+        self.reader_locators
+            .iter()
+            .filter_map(|r| r.repair_at)
+            .chain(self.next_resend)
+            .chain(self.next_heartbeat)
+            .chain(self.writer.batch_deadline())
+            .chain(self.writer.pace_deadline())
+            .chain(self.writer.expiry_deadline())
+            .min()
+    }
+
+    /// Performs the behavior of Section 8.4.8: every unsent change is sent
+    /// to every locator in sequence order. If a resend period is configured
+    /// and has elapsed, all changes are sent again.
+    ///
+    /// A reliable writer additionally repairs requested changes once
+    /// `nack_response_delay` has passed and sends a HEARTBEAT to every
+    /// locator each `heartbeat_period`. In pull mode it only announces its
+    /// changes with HEARTBEATs and sends those that are requested.
+    pub fn poll(&mut self, now: Time) -> Vec<Outgoing> {
+        if let Some(period) = self.resend_period {
+            if self.next_resend.is_some_and(|next| next <= now) {
+                self.unsent_changes_reset();
+            }
+            if self.next_resend.is_none_or(|next| next <= now) {
+                self.next_resend = Some(now + period);
+            }
+        }
+
+        let mut outgoing = Vec::new();
+        let reliable = self.is_reliable();
+        let pushing = self.writer.push_mode || !reliable;
+        let writer = &self.writer;
+        let writer_guid = writer.guid();
+        let cache = &writer.writer_cache;
+        let last_sequence_number = writer.last_change_sequence_number;
+        for reader_locator in &mut self.reader_locators {
+            let locator = reader_locator.locator;
+            let mut send = |submessage| {
+                outgoing.push(Outgoing {
+                    locator,
+                    destination: GUIDPREFIX_UNKNOWN,
+                    submessage,
+                });
+            };
+            loop {
+                let previous = reader_locator.highest_sent_change_sn;
+                let Some(change) = pushing
+                    .then(|| reader_locator.next_unsent_change(writer_guid, cache))
+                    .flatten()
+                else {
+                    break;
+                };
+                // Tell reliable readers about changes removed before they
+                // were sent, so they need not wait for them.
+                if reliable && change.sequence_number().value() > previous.value() + 1 {
+                    send(RtpsSubmessage::Gap(GapSubmessage {
+                        reader_id: ENTITYID_UNKNOWN,
+                        writer_id: writer_guid.entity_id(),
+                        gap_start: SequenceNumber::from(previous.value() + 1),
+                        gap_list: SequenceNumberSet::new(change.sequence_number(), []),
+                        gap_start_gsn: None,
+                        gap_end_gsn: None,
+                    }));
+                }
+                change_submessages(writer, ENTITYID_UNKNOWN, change)
+                    .into_iter()
+                    .for_each(&mut send);
+            }
+            if reliable && reader_locator.repair_at.is_some_and(|t| t <= now) {
+                reader_locator.repair_at = None;
+                let mut irrelevant = Vec::new();
+                while let Some(sequence_number) = reader_locator.next_requested_change() {
+                    match cache.get_change(writer_guid, sequence_number) {
+                        Some(change) => change_submessages(writer, ENTITYID_UNKNOWN, change)
+                            .into_iter()
+                            .for_each(&mut send),
+                        None if sequence_number <= last_sequence_number => {
+                            irrelevant.push(sequence_number);
+                        }
+                        None => {}
+                    }
+                }
+                for gap in gap_submessages(ENTITYID_UNKNOWN, writer_guid.entity_id(), &irrelevant) {
+                    send(RtpsSubmessage::Gap(gap));
+                }
+            }
+        }
+
+        if reliable && self.next_heartbeat.is_none_or(|next| next <= now) {
+            self.next_heartbeat = Some(self.writer.next_heartbeat(now));
+            let heartbeat = self.writer.heartbeat(ENTITYID_UNKNOWN, false);
+            outgoing.extend(self.reader_locators.iter().map(|r| Outgoing {
+                locator: r.locator,
+                destination: GUIDPREFIX_UNKNOWN,
+                submessage: RtpsSubmessage::Heartbeat(heartbeat.clone()),
+            }));
+        }
+        let outgoing = self.writer.batch(outgoing, now);
+        self.writer.pace(outgoing, now)
     }
 }
 
+impl Behavior for StatelessWriter {
+    fn guid(&self) -> Guid {
+        self.writer.guid()
+    }
+
+    /// ACKNACKs are attributed to the [`ReaderLocator`] matching the locator
+    /// they were received from.
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        match input {
+            Input::Received {
+                source_locator,
+                submessage: RtpsSubmessage::AckNack(acknack),
+                ..
+            } => self.on_acknack(source_locator, &acknack, now),
+            Input::NewChange(change) => self.add_change(stamp(change, now))?,
+            Input::Flush => self.writer.flush(),
+            Input::Received { .. } | Input::ParticipantRemoved(_) | Input::TimerFired => {}
+        }
+        self.writer.remove_expired(now)?;
+        let outgoing = self.poll(now);
+        Ok(outputs(Vec::new(), outgoing, self.next_deadline()))
+    }
+}
+
+/// See Section 8.4.7.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Debug)]
 pub struct ReaderLocator {
-    requested_changes: Vec<CacheChange>,
-    highest_sent_change: Option<CacheChange>,
+    locator: Locator,
+    expects_inline_qos: bool,
+    highest_sent_change_sn: SequenceNumber,
+    requested_changes: BTreeSet<SequenceNumber>,
+    last_acknack_count: Option<Count>,
+    repair_at: Option<Time>,
 }
 
 impl ReaderLocator {
-    pub fn next_requested_change(&self) -> &CacheChange {
-        todo!()
+    #[must_use]
+    pub fn new(locator: Locator, expects_inline_qos: bool) -> Self {
+        Self {
+            locator,
+            expects_inline_qos,
+            highest_sent_change_sn: SequenceNumber::default(),
+            requested_changes: BTreeSet::new(),
+            last_acknack_count: None,
+            repair_at: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn locator(&self) -> Locator {
+        self.locator
+    }
+
+    #[must_use]
+    pub const fn expects_inline_qos(&self) -> bool {
+        self.expects_inline_qos
+    }
+
+    /// Removes and returns the lowest requested sequence number.
+    pub fn next_requested_change(&mut self) -> Option<SequenceNumber> {
+        self.requested_changes.pop_first()
+    }
+
+    /// Returns the unsent change of `writer_guid` with the lowest sequence
+    /// number and marks it as sent.
+    pub fn next_unsent_change<'a>(
+        &mut self,
+        writer_guid: Guid,
+        cache: &'a HistoryCache,
+    ) -> Option<&'a CacheChange> {
+        let next = cache
+            .changes_after(writer_guid, self.highest_sent_change_sn)
+            .next()?;
+        self.highest_sent_change_sn = next.sequence_number();
+        Some(next)
+    }
+
+    /// Sequence numbers requested by the reader and not yet repaired, in
+    /// ascending order.
+    pub fn requested_changes(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.requested_changes.iter().copied()
+    }
+
+    /// Adds `req_seq_num_set` to the requested changes.
+    pub fn requested_changes_set(
+        &mut self,
+        req_seq_num_set: impl IntoIterator<Item = SequenceNumber>,
+    ) {
+        self.requested_changes.extend(req_seq_num_set);
+    }
+
+    /// Sequence numbers of the changes of `writer_guid` in `cache` not yet
+    /// sent to this locator.
+    pub fn unsent_changes(&self, writer_guid: Guid, cache: &HistoryCache) -> Vec<SequenceNumber> {
+        cache
+            .changes_after(writer_guid, self.highest_sent_change_sn)
+            .map(CacheChange::sequence_number)
+            .collect()
+    }
+
+    /// Marks every change as unsent.
+    pub fn unsent_changes_reset(&mut self) {
+        self.highest_sent_change_sn = SequenceNumber::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::writer::tests::writer,
+        structure::{
+            data::Data, participant::Endpoint, ChangeKind, EntityId, InstanceHandle, ParameterList,
+            ReliabilityKind,
+        },
+    };
+
+    fn sent(outgoing: &[Outgoing]) -> Vec<(u16, u64)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Data(data) => Some((o.locator.port(), data.writer_sn.value())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn heartbeats(outgoing: &[Outgoing]) -> Vec<(u16, u64, u64)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Heartbeat(hb) => {
+                    Some((o.locator.port(), hb.first_sn.value(), hb.last_sn.value()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn gaps(outgoing: &[Outgoing]) -> Vec<(u64, u64, Vec<u64>)> {
+        outgoing
+            .iter()
+            .filter_map(|o| match &o.submessage {
+                RtpsSubmessage::Gap(gap) => Some((
+                    gap.gap_start.value(),
+                    gap.gap_list.base().value(),
+                    gap.gap_list.iter().map(|sn| sn.value()).collect(),
+                )),
+                _ => None,
+            })
+            .collect()
     }
 
-    pub fn next_unsent_change(&self) -> &CacheChange {
-        todo!()
+    fn acknack(base: u64, missing: &[u64], count: Count) -> AckNackSubmessage {
+        AckNackSubmessage {
+            reader_id: ENTITYID_UNKNOWN,
+            writer_id: ENTITYID_UNKNOWN,
+            reader_sn_state: SequenceNumberSet::new(
+                SequenceNumber::from(base),
+                missing.iter().map(|sn| SequenceNumber::from(*sn)),
+            ),
+            count,
+            final_flag: false,
+        }
     }
 
-    pub fn requested_changes(&self) -> &[SequenceNumber] {
-        todo!()
+    fn add_change(writer: &mut StatelessWriter) -> CacheChange {
+        let change = writer.writer_mut().new_change(
+            ChangeKind::Alive,
+            Some(Data::from(vec![0; 8])),
+            ParameterList,
+            InstanceHandle,
+        );
+        writer.add_change(change.clone()).unwrap();
+        change
     }
 
-    pub fn requested_changes_set(&self) -> &[SequenceNumber] {
-        todo!()
+    #[test]
+    fn test_pushes_changes_in_order_to_every_locator() {
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::BestEffort));
+        writer.reader_locator_add(ReaderLocator::new("127.0.0.1:7400".parse().unwrap(), false));
+        writer.reader_locator_add(ReaderLocator::new("127.0.0.1:7401".parse().unwrap(), false));
+        writer.reader_locator_add(ReaderLocator::new("127.0.0.1:7401".parse().unwrap(), false));
+        add_change(&mut writer);
+        add_change(&mut writer);
+
+        let now = Time::new(0, 0);
+        assert_eq!(
+            sent(&writer.poll(now)),
+            vec![(7400, 1), (7400, 2), (7401, 1), (7401, 2)]
+        );
+        assert!(writer.poll(now).is_empty());
+
+        add_change(&mut writer);
+        assert_eq!(sent(&writer.poll(now)), vec![(7400, 3), (7401, 3)]);
+        assert!(writer
+            .reader_locator_remove(&"127.0.0.1:7400".parse().unwrap())
+            .is_some());
+        assert_eq!(
+            writer.reader_locators()[0]
+                .unsent_changes(writer.guid(), writer.writer().writer_cache()),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_periodic_resend() {
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::BestEffort))
+            .with_resend_period(Duration::from_secs(1));
+        writer.reader_locator_add(ReaderLocator::new("127.0.0.1:7400".parse().unwrap(), false));
+        add_change(&mut writer);
+
+        assert_eq!(sent(&writer.poll(Time::new(10, 0))), vec![(7400, 1)]);
+        assert_eq!(writer.next_deadline(), Some(Time::new(11, 0)));
+        assert!(writer.poll(Time::new(10, 1 << 31)).is_empty());
+        assert_eq!(sent(&writer.poll(Time::new(11, 0))), vec![(7400, 1)]);
+        assert_eq!(writer.next_deadline(), Some(Time::new(12, 0)));
+    }
+
+    #[test]
+    fn test_reliable_heartbeats_and_repairs() {
+        let locator = "127.0.0.1:7400".parse().unwrap();
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::Reliable));
+        writer.reader_locator_add(ReaderLocator::new(locator, false));
+        add_change(&mut writer);
+        add_change(&mut writer);
+        add_change(&mut writer);
+
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(sent(&out), vec![(7400, 1), (7400, 2), (7400, 3)]);
+        assert_eq!(heartbeats(&out), vec![(7400, 1, 3)]);
+        assert_eq!(writer.next_deadline(), Some(Time::new(1, 0)));
+
+        writer.on_acknack(locator, &acknack(1, &[1, 3], 1), Time::new(0, 0));
+        // A duplicate ACKNACK is ignored.
+        writer.on_acknack(locator, &acknack(1, &[2], 1), Time::new(0, 0));
+        let repair_at = Time::from(Duration::from_millis(200));
+        assert_eq!(writer.next_deadline(), Some(repair_at));
+        assert_eq!(
+            writer.reader_locators()[0]
+                .requested_changes()
+                .collect::<Vec<_>>(),
+            vec![SequenceNumber::from(1), SequenceNumber::from(3)]
+        );
+        assert!(writer.poll(Time::new(0, 1)).is_empty());
+
+        let out = writer.poll(repair_at);
+        assert_eq!(sent(&out), vec![(7400, 1), (7400, 3)]);
+        assert!(heartbeats(&out).is_empty());
+        assert_eq!(
+            heartbeats(&writer.poll(Time::new(1, 0))),
+            vec![(7400, 1, 3)]
+        );
+    }
+
+    #[test]
+    fn test_acknack_from_unknown_locator() {
+        let multicast = "239.255.0.1:7400".parse().unwrap();
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::Reliable));
+        writer.reader_locator_add(ReaderLocator::new(multicast, false));
+        add_change(&mut writer);
+        assert_eq!(sent(&writer.poll(Time::new(0, 0))), vec![(7400, 1)]);
+
+        // The reader behind the multicast locator replies from its unicast
+        // address, which matches no locator.
+        writer.on_acknack(
+            "192.168.1.2:7410".parse().unwrap(),
+            &acknack(1, &[1], 1),
+            Time::new(0, 0),
+        );
+        assert_eq!(writer.reader_locators()[0].requested_changes().count(), 0);
+        assert_eq!(writer.next_deadline(), Some(Time::new(1, 0)));
+        assert!(sent(&writer.poll(Time::new(1, 0))).is_empty());
+    }
+
+    #[test]
+    fn test_pull_mode() {
+        let locator = "127.0.0.1:7400".parse().unwrap();
+        let guid = Guid::new([1; 12], EntityId::new([0, 0, 1], 0x02));
+        let mut writer = StatelessWriter::new(Writer::new(
+            Endpoint::builder(guid)
+                .reliability_level(ReliabilityKind::Reliable)
+                .build(),
+            false,
+            Duration::from_secs(1),
+            Duration::ZERO,
+            Duration::ZERO,
+        ));
+        writer.reader_locator_add(ReaderLocator::new(locator, false));
+        add_change(&mut writer);
+        add_change(&mut writer);
+
+        let now = Time::new(0, 0);
+        let out = writer.poll(now);
+        assert!(sent(&out).is_empty());
+        assert_eq!(heartbeats(&out), vec![(7400, 1, 2)]);
+
+        writer.on_acknack(locator, &acknack(2, &[2], 1), now);
+        assert_eq!(sent(&writer.poll(now)), vec![(7400, 2)]);
+    }
+
+    #[test]
+    fn test_gaps_for_removed_changes() {
+        let locator = "127.0.0.1:7400".parse().unwrap();
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::Reliable));
+        writer.reader_locator_add(ReaderLocator::new(locator, false));
+        let changes: Vec<_> = (0..4).map(|_| add_change(&mut writer)).collect();
+        for change in &changes[..2] {
+            writer
+                .writer_mut()
+                .writer_cache_mut()
+                .remove_change(change)
+                .unwrap();
+        }
+
+        let out = writer.poll(Time::new(0, 0));
+        assert_eq!(gaps(&out), vec![(1, 3, vec![])]);
+        assert_eq!(sent(&out), vec![(7400, 3), (7400, 4)]);
+
+        // Requested changes that were removed or never existed are gapped.
+        writer.on_acknack(locator, &acknack(1, &[1, 2, 4, 9], 1), Time::new(0, 0));
+        let out = writer.poll(Time::from(Duration::from_millis(200)));
+        assert_eq!(gaps(&out), vec![(1, 3, vec![])]);
+        assert_eq!(sent(&out), vec![(7400, 4)]);
     }
 
-    pub fn unsent_changes(&self) -> &[SequenceNumber] {
-        todo!()
+    #[test]
+    fn test_best_effort_ignores_acknacks() {
+        let locator = "127.0.0.1:7400".parse().unwrap();
+        let mut writer = StatelessWriter::new(writer(ReliabilityKind::BestEffort));
+        writer.reader_locator_add(ReaderLocator::new(locator, false));
+        add_change(&mut writer);
+        assert_eq!(writer.poll(Time::new(0, 0)).len(), 1);
+        writer.on_acknack(locator, &acknack(1, &[1], 1), Time::new(0, 0));
+        assert_eq!(writer.next_deadline(), None);
+        assert!(writer.poll(Time::new(5, 0)).is_empty());
     }
 }
@@ -1 +1,194 @@
+//! The discovery protocols let participants find each other and match their
+//! endpoints. Discovery data is exchanged as parameter lists (PL_CDR), in
+//! which each value is tagged with a parameter id so that receivers can skip
+//! the ones they do not know.
+//!
+//! See Sections 8.5 and 9.6.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+
+use std::io;
+
+use crate::messages::ParameterId;
+
 pub mod spdp;
+
+pub const PID_PAD: ParameterId = 0x0000;
+pub const PID_SENTINEL: ParameterId = 0x0001;
+pub const PID_PARTICIPANT_LEASE_DURATION: ParameterId = 0x0002;
+pub const PID_DOMAIN_ID: ParameterId = 0x000f;
+pub const PID_PROTOCOL_VERSION: ParameterId = 0x0015;
+pub const PID_VENDORID: ParameterId = 0x0016;
+pub const PID_USER_DATA: ParameterId = 0x002c;
+pub const PID_DEFAULT_UNICAST_LOCATOR: ParameterId = 0x0031;
+pub const PID_METATRAFFIC_UNICAST_LOCATOR: ParameterId = 0x0032;
+pub const PID_METATRAFFIC_MULTICAST_LOCATOR: ParameterId = 0x0033;
+pub const PID_PARTICIPANT_MANUAL_LIVELINESS_COUNT: ParameterId = 0x0034;
+pub const PID_EXPECTS_INLINE_QOS: ParameterId = 0x0043;
+pub const PID_DEFAULT_MULTICAST_LOCATOR: ParameterId = 0x0048;
+pub const PID_PARTICIPANT_GUID: ParameterId = 0x0050;
+pub const PID_BUILTIN_ENDPOINT_SET: ParameterId = 0x0058;
+pub const PID_BUILTIN_ENDPOINT_QOS: ParameterId = 0x0077;
+pub const PID_DOMAIN_TAG: ParameterId = 0x4014;
+
+/// Parameter ids with this bit set must be understood by the receiver, which
+/// drops the whole parameter list otherwise.
+const PID_MUST_UNDERSTAND: ParameterId = 0x4000;
+
+/// The encapsulation identifiers of parameter lists.
+const PL_CDR_BE: [u8; 4] = [0x00, 0x02, 0x00, 0x00];
+const PL_CDR_LE: [u8; 4] = [0x00, 0x03, 0x00, 0x00];
+
+/// Builds a little-endian parameter list.
+#[derive(Debug)]
+pub(crate) struct ParameterListWriter {
+    bytes: Vec<u8>,
+}
+
+impl ParameterListWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            bytes: PL_CDR_LE.to_vec(),
+        }
+    }
+
+    /// Appends a parameter, padding `value` to a multiple of four bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` does not fit in a parameter.
+    pub(crate) fn parameter(&mut self, parameter_id: ParameterId, value: &[u8]) {
+        let padded = value.len().next_multiple_of(4);
+        let length = u16::try_from(padded).expect("parameter exceeds 64 KiB");
+        self.bytes.extend_from_slice(&parameter_id.to_le_bytes());
+        self.bytes.extend_from_slice(&length.to_le_bytes());
+        self.bytes.extend_from_slice(value);
+        self.bytes
+            .resize(self.bytes.len() + padded - value.len(), 0);
+    }
+
+    /// Ends the list with a sentinel.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.bytes.extend_from_slice(&PID_SENTINEL.to_le_bytes());
+        self.bytes.extend_from_slice(&[0, 0]);
+        self.bytes
+    }
+}
+
+/// Decodes the values of a parameter list in its byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Endianness {
+    little: bool,
+}
+
+impl Endianness {
+    pub(crate) fn u16(self, bytes: &[u8]) -> Option<u16> {
+        let bytes = bytes.get(..2)?.try_into().ok()?;
+        Some(if self.little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn u32(self, bytes: &[u8]) -> Option<u32> {
+        let bytes = bytes.get(..4)?.try_into().ok()?;
+        Some(if self.little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn i32(self, bytes: &[u8]) -> Option<i32> {
+        self.u32(bytes).map(|value| value as i32)
+    }
+}
+
+/// The parameters of a list, in order, with their padded values.
+pub(crate) type Parameters<'a> = Vec<(ParameterId, &'a [u8])>;
+
+/// Splits a parameter list encapsulated as PL_CDR_LE or PL_CDR_BE into its
+/// parameters, up to the sentinel. Vendor-specific parameters are left out.
+///
+/// # Errors
+///
+/// Fails if the list is truncated, not a parameter list, or has a parameter
+/// that must be understood but is not in `known`.
+pub(crate) fn parameters<'a>(
+    bytes: &'a [u8],
+    known: &[ParameterId],
+) -> io::Result<(Endianness, Parameters<'a>)> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let endianness = match bytes.get(..4) {
+        Some(header) if header == PL_CDR_LE => Endianness { little: true },
+        Some(header) if header == PL_CDR_BE => Endianness { little: false },
+        _ => return Err(invalid("unsupported encapsulation")),
+    };
+    let mut parameters = Vec::new();
+    let mut rest = &bytes[4..];
+    loop {
+        let (Some(parameter_id), Some(length)) = (
+            endianness.u16(rest).map(|id| id as ParameterId),
+            rest.get(2..).and_then(|b| endianness.u16(b)),
+        ) else {
+            return Err(invalid("truncated parameter list"));
+        };
+        if parameter_id == PID_SENTINEL {
+            return Ok((endianness, parameters));
+        }
+        let value = rest
+            .get(4..4 + usize::from(length))
+            .ok_or_else(|| invalid("truncated parameter list"))?;
+        rest = &rest[4 + usize::from(length)..];
+        if parameter_id < 0 || parameter_id == PID_PAD {
+            continue;
+        }
+        if parameter_id & PID_MUST_UNDERSTAND != 0 && !known.contains(&parameter_id) {
+            return Err(invalid("unknown parameter that must be understood"));
+        }
+        parameters.push((parameter_id, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameters() {
+        let mut writer = ParameterListWriter::new();
+        writer.parameter(PID_DOMAIN_ID, &7u32.to_le_bytes());
+        writer.parameter(PID_USER_DATA, &[1, 2, 3, 4, 5]);
+        writer.parameter(-0x7fff, &[9; 4]);
+        let bytes = writer.finish();
+        assert_eq!(bytes.len(), 4 + 8 + 12 + 8 + 4);
+
+        let (endianness, list) = parameters(&bytes, &[]).unwrap();
+        assert_eq!(endianness, Endianness { little: true });
+        assert_eq!(
+            list,
+            vec![
+                (PID_DOMAIN_ID, &7u32.to_le_bytes()[..]),
+                (PID_USER_DATA, &[1, 2, 3, 4, 5, 0, 0, 0][..]),
+            ]
+        );
+
+        // Big-endian lists are read as well.
+        let bytes = [
+            &PL_CDR_BE[..],
+            &[0x00, 0x0f, 0x00, 0x04, 0, 0, 0, 7],
+            &[0x00, 0x01, 0x00, 0x00],
+        ]
+        .concat();
+        let (endianness, list) = parameters(&bytes, &[]).unwrap();
+        assert_eq!(endianness.u32(list[0].1), Some(7));
+
+        // A truncated list, or one with an unknown parameter that must be
+        // understood, is rejected.
+        assert!(parameters(&bytes[..bytes.len() - 4], &[]).is_err());
+        let mut writer = ParameterListWriter::new();
+        writer.parameter(0x4fff, &[]);
+        let bytes = writer.finish();
+        assert!(parameters(&bytes, &[]).is_err());
+        assert!(parameters(&bytes, &[0x4fff]).is_ok());
+    }
+}
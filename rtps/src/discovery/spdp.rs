@@ -1,53 +1,925 @@
 //! The RTPS Simple Participant Discovery Protocol (SPDP) uses a simple approach
 //! to announce and detect the presence of Participants in a domain.
+//!
+//! Every participant periodically sends its [`SPDPdiscoveredParticipantData`]
+//! through a best-effort [`SPDPbuiltinParticipantWriter`] to the well-known
+//! multicast locator of its domain, and keeps the participants announced to
+//! its [`SPDPbuiltinParticipantReader`] in a table. A participant is removed
+//! from the table when it is not announced again within its lease duration,
+//! or as soon as it announces its departure with
+//! [`SPDPbuiltinParticipantWriter::dispose`].
+//!
+//! See Sections 8.5.3 and 9.6.2.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::BitOr,
+};
 
 use crate::{
-    behavior::{writer::stateless::StatelessWriter, Duration},
-    messages::Count,
-    structure::{GuidPrefix, Locator, ProtocolVersion, VendorId},
+    behavior::{
+        reader::{stateless::StatelessReader, Reader},
+        rearm,
+        timer::TimerQueue,
+        writer::{
+            stateless::{ReaderLocator, StatelessWriter},
+            Writer,
+        },
+        Behavior, Duration, Input, Output,
+    },
+    messages::{Count, RtpsSubmessage, Time, TIME_INFINITE},
+    structure::{
+        data::Data, historycache::CacheChange, participant::Endpoint, ChangeKind, Guid, GuidPrefix,
+        InstanceHandle, Locator, ParameterList, ProtocolVersion, ReliabilityKind, VendorId,
+        ENTITYID_PARTICIPANT, ENTITYID_SPDP_BUILTIN_PARTICIPANT_ANNOUNCER,
+        ENTITYID_SPDP_BUILTIN_PARTICIPANT_DETECTOR,
+    },
 };
 
+use super::{
+    parameters, Endianness, ParameterListWriter, PID_BUILTIN_ENDPOINT_QOS,
+    PID_BUILTIN_ENDPOINT_SET, PID_DEFAULT_MULTICAST_LOCATOR, PID_DEFAULT_UNICAST_LOCATOR,
+    PID_DOMAIN_ID, PID_DOMAIN_TAG, PID_EXPECTS_INLINE_QOS, PID_METATRAFFIC_MULTICAST_LOCATOR,
+    PID_METATRAFFIC_UNICAST_LOCATOR, PID_PARTICIPANT_GUID, PID_PARTICIPANT_LEASE_DURATION,
+    PID_PARTICIPANT_MANUAL_LIVELINESS_COUNT, PID_PROTOCOL_VERSION, PID_USER_DATA, PID_VENDORID,
+};
+
+pub type DomainId = u32;
+
+/// The lease duration assumed for participants that do not announce one.
+pub const DEFAULT_LEASE_DURATION: Duration = Duration::from_secs(100);
+
+/// The multicast address SPDP announcements are sent to.
+pub const SPDP_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 1);
+
+/// The port numbers of Section 9.6.1.1: the base port and the gain between
+/// domains.
+const PORT_BASE: u32 = 7400;
+const DOMAIN_ID_GAIN: u32 = 250;
+
+const LOCATOR_KIND_UDPV4: i32 = 1;
+const LOCATOR_KIND_UDPV6: i32 = 2;
+
+/// The lease duration sent for participants that never expire.
+const DURATION_INFINITE: (i32, u32) = (0x7fff_ffff, 0xffff_ffff);
+
+/// The parameters of an announcement that must be understood.
+const KNOWN: [i16; 1] = [PID_DOMAIN_TAG];
+
+/// The well-known multicast locator of the SPDP in `domain_id`.
+///
+/// # Panics
+///
+/// Panics if the domain's port does not fit in a UDP port.
+#[must_use]
+pub fn spdp_multicast_locator(domain_id: DomainId) -> Locator {
+    let port = PORT_BASE + DOMAIN_ID_GAIN * domain_id;
+    Locator::new(
+        IpAddr::V4(SPDP_MULTICAST_ADDRESS),
+        u16::try_from(port).expect("domain id too large"),
+    )
+}
+
+/// The builtin endpoints a participant has.
+///
+/// See Section 9.3.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BuiltinEndpointSet(pub u32);
+
+impl BuiltinEndpointSet {
+    pub const PARTICIPANT_ANNOUNCER: Self = Self(1 << 0);
+    pub const PARTICIPANT_DETECTOR: Self = Self(1 << 1);
+    pub const PUBLICATIONS_ANNOUNCER: Self = Self(1 << 2);
+    pub const PUBLICATIONS_DETECTOR: Self = Self(1 << 3);
+    pub const SUBSCRIPTIONS_ANNOUNCER: Self = Self(1 << 4);
+    pub const SUBSCRIPTIONS_DETECTOR: Self = Self(1 << 5);
+    pub const PARTICIPANT_MESSAGE_DATA_WRITER: Self = Self(1 << 10);
+    pub const PARTICIPANT_MESSAGE_DATA_READER: Self = Self(1 << 11);
+    pub const TOPICS_ANNOUNCER: Self = Self(1 << 28);
+    pub const TOPICS_DETECTOR: Self = Self(1 << 29);
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for BuiltinEndpointSet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// The QoS of the builtin endpoints that differs from the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BuiltinEndpointQos(pub u32);
+
+impl BuiltinEndpointQos {
+    pub const BEST_EFFORT_PARTICIPANT_MESSAGE_DATA_READER: Self = Self(1 << 0);
+}
+
+/// What a participant needs to know about a remote one to communicate with
+/// it.
+///
+/// See Section 8.5.3.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParticipantProxy {
+    pub domain_id: DomainId,
+    pub domain_tag: String,
+    pub protocol_version: ProtocolVersion,
+    pub guid_prefix: GuidPrefix,
+    pub vendor_id: VendorId,
+    pub expects_inline_qos: bool,
+    pub available_builtin_endpoints: BuiltinEndpointSet,
+    pub builtin_endpoint_qos: BuiltinEndpointQos,
+    pub meta_traffic_unicast_locator_list: Vec<Locator>,
+    pub meta_traffic_multicast_locator_list: Vec<Locator>,
+    pub default_multicast_locator_list: Vec<Locator>,
+    pub default_unicast_locator_list: Vec<Locator>,
+    pub manual_liveliness_count: Count,
+}
+
+/// The data a participant announces with the SPDP.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SPDPdiscoveredParticipantData {
-    participant_data: DiscoveredParticipantData,
-    lease_duration: Duration,
+    pub proxy: ParticipantProxy,
+    pub user_data: Vec<u8>,
+    /// How long the participant is considered alive without a new
+    /// announcement.
+    pub lease_duration: Duration,
+}
+
+impl SPDPdiscoveredParticipantData {
+    /// The key of the participant: its GUID.
+    #[must_use]
+    pub const fn key(&self) -> Guid {
+        Guid::new(self.proxy.guid_prefix, ENTITYID_PARTICIPANT)
+    }
+
+    /// Serializes the data as a PL_CDR_LE parameter list. The domain tag and
+    /// user data are left out when empty, which is their default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the domain tag or user data does not fit in a parameter.
+    #[must_use]
+    pub fn to_data(&self) -> Data {
+        let proxy = &self.proxy;
+        let mut writer = ParameterListWriter::new();
+        writer.parameter(
+            PID_PROTOCOL_VERSION,
+            &[
+                proxy.protocol_version.major(),
+                proxy.protocol_version.minor(),
+            ],
+        );
+        writer.parameter(PID_VENDORID, &proxy.vendor_id);
+        let key = self.key();
+        let mut guid = key.guid_prefix().to_vec();
+        guid.extend_from_slice(&key.entity_id().entity_key());
+        guid.push(key.entity_id().entity_kind());
+        writer.parameter(PID_PARTICIPANT_GUID, &guid);
+        writer.parameter(PID_DOMAIN_ID, &proxy.domain_id.to_le_bytes());
+        if !proxy.domain_tag.is_empty() {
+            writer.parameter(PID_DOMAIN_TAG, &string(&proxy.domain_tag));
+        }
+        writer.parameter(
+            PID_EXPECTS_INLINE_QOS,
+            &[u8::from(proxy.expects_inline_qos)],
+        );
+        for (parameter_id, locators) in [
+            (
+                PID_METATRAFFIC_UNICAST_LOCATOR,
+                &proxy.meta_traffic_unicast_locator_list,
+            ),
+            (
+                PID_METATRAFFIC_MULTICAST_LOCATOR,
+                &proxy.meta_traffic_multicast_locator_list,
+            ),
+            (
+                PID_DEFAULT_UNICAST_LOCATOR,
+                &proxy.default_unicast_locator_list,
+            ),
+            (
+                PID_DEFAULT_MULTICAST_LOCATOR,
+                &proxy.default_multicast_locator_list,
+            ),
+        ] {
+            for locator in locators {
+                writer.parameter(parameter_id, &locator_bytes(locator));
+            }
+        }
+        let (seconds, fraction) = duration_parts(self.lease_duration);
+        writer.parameter(
+            PID_PARTICIPANT_LEASE_DURATION,
+            &[seconds.to_le_bytes(), fraction.to_le_bytes()].concat(),
+        );
+        writer.parameter(
+            PID_PARTICIPANT_MANUAL_LIVELINESS_COUNT,
+            &proxy.manual_liveliness_count.to_le_bytes(),
+        );
+        writer.parameter(
+            PID_BUILTIN_ENDPOINT_SET,
+            &proxy.available_builtin_endpoints.0.to_le_bytes(),
+        );
+        writer.parameter(
+            PID_BUILTIN_ENDPOINT_QOS,
+            &proxy.builtin_endpoint_qos.0.to_le_bytes(),
+        );
+        if !self.user_data.is_empty() {
+            let length = u32::try_from(self.user_data.len()).expect("user data exceeds a sequence");
+            writer.parameter(
+                PID_USER_DATA,
+                &[&length.to_le_bytes()[..], &self.user_data].concat(),
+            );
+        }
+        Data::from(writer.finish())
+    }
+
+    /// Parses a parameter list in either byte order. Parameters that are
+    /// not understood are skipped and missing ones take their default
+    /// values; announcements without a domain id are assumed to belong to
+    /// `domain_id`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the payload is not a valid parameter list, has no
+    /// participant GUID, or has a malformed standard parameter.
+    pub fn from_data(data: &Data, domain_id: DomainId) -> io::Result<Self> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let (endianness, list) = parameters(data.as_bytes(), &KNOWN)?;
+        let mut guid_prefix = None;
+        let mut proxy = ParticipantProxy {
+            domain_id,
+            domain_tag: String::new(),
+            protocol_version: ProtocolVersion::default(),
+            guid_prefix: GuidPrefix::default(),
+            vendor_id: VendorId::default(),
+            expects_inline_qos: false,
+            available_builtin_endpoints: BuiltinEndpointSet::default(),
+            builtin_endpoint_qos: BuiltinEndpointQos::default(),
+            meta_traffic_unicast_locator_list: Vec::new(),
+            meta_traffic_multicast_locator_list: Vec::new(),
+            default_multicast_locator_list: Vec::new(),
+            default_unicast_locator_list: Vec::new(),
+            manual_liveliness_count: 0,
+        };
+        let mut user_data = Vec::new();
+        let mut lease_duration = DEFAULT_LEASE_DURATION;
+        for (parameter_id, value) in list {
+            let malformed = || invalid("malformed participant data parameter");
+            let u32_value = || endianness.u32(value).ok_or_else(malformed);
+            match parameter_id {
+                PID_PROTOCOL_VERSION => {
+                    let [major, minor, ..] = *value else {
+                        return Err(malformed());
+                    };
+                    proxy.protocol_version = ProtocolVersion::new(major, minor);
+                }
+                PID_VENDORID => {
+                    proxy.vendor_id = value.get(..2).ok_or_else(malformed)?.try_into().unwrap();
+                }
+                PID_PARTICIPANT_GUID => {
+                    guid_prefix = Some(<GuidPrefix>::try_from(
+                        value.get(..12).ok_or_else(malformed)?,
+                    ));
+                }
+                PID_DOMAIN_ID => proxy.domain_id = u32_value()?,
+                PID_DOMAIN_TAG => {
+                    proxy.domain_tag = read_string(endianness, value).ok_or_else(malformed)?;
+                }
+                PID_EXPECTS_INLINE_QOS => {
+                    proxy.expects_inline_qos = *value.first().ok_or_else(malformed)? != 0;
+                }
+                PID_METATRAFFIC_UNICAST_LOCATOR
+                | PID_METATRAFFIC_MULTICAST_LOCATOR
+                | PID_DEFAULT_UNICAST_LOCATOR
+                | PID_DEFAULT_MULTICAST_LOCATOR => {
+                    // Locators of other kinds cannot be reached.
+                    let Some(locator) = read_locator(endianness, value) else {
+                        continue;
+                    };
+                    match parameter_id {
+                        PID_METATRAFFIC_UNICAST_LOCATOR => {
+                            proxy.meta_traffic_unicast_locator_list.push(locator);
+                        }
+                        PID_METATRAFFIC_MULTICAST_LOCATOR => {
+                            proxy.meta_traffic_multicast_locator_list.push(locator);
+                        }
+                        PID_DEFAULT_UNICAST_LOCATOR => {
+                            proxy.default_unicast_locator_list.push(locator);
+                        }
+                        _ => proxy.default_multicast_locator_list.push(locator),
+                    }
+                }
+                PID_PARTICIPANT_LEASE_DURATION => {
+                    let seconds = endianness.i32(value).ok_or_else(malformed)?;
+                    let fraction = value
+                        .get(4..)
+                        .and_then(|b| endianness.u32(b))
+                        .ok_or_else(malformed)?;
+                    lease_duration = duration_from_parts(seconds, fraction);
+                }
+                PID_PARTICIPANT_MANUAL_LIVELINESS_COUNT => {
+                    proxy.manual_liveliness_count = u32_value()?;
+                }
+                PID_BUILTIN_ENDPOINT_SET => {
+                    proxy.available_builtin_endpoints = BuiltinEndpointSet(u32_value()?);
+                }
+                PID_BUILTIN_ENDPOINT_QOS => {
+                    proxy.builtin_endpoint_qos = BuiltinEndpointQos(u32_value()?);
+                }
+                PID_USER_DATA => {
+                    let length = usize::try_from(u32_value()?).map_err(|_| malformed())?;
+                    user_data = value
+                        .get(4..)
+                        .and_then(|b| b.get(..length))
+                        .ok_or_else(malformed)?
+                        .to_vec();
+                }
+                _ => {}
+            }
+        }
+        proxy.guid_prefix = guid_prefix
+            .ok_or_else(|| invalid("participant data without a GUID"))?
+            .map_err(|_| invalid("malformed participant GUID"))?;
+        Ok(Self {
+            proxy,
+            user_data,
+            lease_duration,
+        })
+    }
+}
+
+/// A CDR string: its length including the terminating NUL, then its bytes.
+fn string(value: &str) -> Vec<u8> {
+    let length = u32::try_from(value.len() + 1).expect("string exceeds a sequence");
+    let mut bytes = length.to_le_bytes().to_vec();
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+fn read_string(endianness: Endianness, value: &[u8]) -> Option<String> {
+    let length = usize::try_from(endianness.u32(value)?).ok()?;
+    let bytes = value.get(4..)?.get(..length)?;
+    let (0, bytes) = bytes.split_last()? else {
+        return None;
+    };
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+/// A Locator_t: its kind, port and 16 byte address, in which IPv4
+/// addresses take the last four bytes.
+fn locator_bytes(locator: &Locator) -> Vec<u8> {
+    let (kind, address) = match locator.ip() {
+        IpAddr::V4(ip) => (LOCATOR_KIND_UDPV4, ip.to_ipv6_compatible().octets()),
+        IpAddr::V6(ip) => (LOCATOR_KIND_UDPV6, ip.octets()),
+    };
+    let mut bytes = kind.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&u32::from(locator.port()).to_le_bytes());
+    bytes.extend_from_slice(&address);
+    bytes
+}
+
+fn read_locator(endianness: Endianness, value: &[u8]) -> Option<Locator> {
+    let kind = endianness.i32(value)?;
+    let port = u16::try_from(endianness.u32(value.get(4..)?)?).ok()?;
+    let address: [u8; 16] = value.get(8..24)?.try_into().ok()?;
+    let ip = match kind {
+        LOCATOR_KIND_UDPV4 => IpAddr::V4(Ipv4Addr::new(
+            address[12],
+            address[13],
+            address[14],
+            address[15],
+        )),
+        LOCATOR_KIND_UDPV6 => IpAddr::V6(Ipv6Addr::from(address)),
+        _ => return None,
+    };
+    Some(Locator::new(ip, port))
+}
+
+/// A Duration_t: whole seconds and a fraction in units of 1/2^32 seconds.
+fn duration_parts(duration: Duration) -> (i32, u32) {
+    match i32::try_from(duration.as_secs()) {
+        Ok(seconds) if seconds < DURATION_INFINITE.0 => {
+            let time = Time::from(Duration::from_nanos(u64::from(duration.subsec_nanos())));
+            (seconds, time.fraction())
+        }
+        _ => DURATION_INFINITE,
+    }
+}
+
+fn duration_from_parts(seconds: i32, fraction: u32) -> Duration {
+    if (seconds, fraction) == DURATION_INFINITE {
+        return Duration::MAX;
+    }
+    let seconds = u32::try_from(seconds).unwrap_or(0);
+    Time::new(seconds, fraction).as_duration()
+}
+
+/// The SPDPbuiltinParticipantWriter. It announces the local participant to
+/// the well-known multicast locator of its domain, and to any peer added
+/// with [`SPDPbuiltinParticipantWriter::add_peer`], every `resend_period`.
+/// Only the latest announcement is kept in the writer's cache.
+#[derive(Debug)]
+pub struct SPDPbuiltinParticipantWriter {
+    writer: StatelessWriter,
+    announced: Option<CacheChange>,
+}
+
+impl SPDPbuiltinParticipantWriter {
+    /// A best-effort writer for the participant `guid_prefix` in
+    /// `domain_id`.
+    #[must_use]
+    pub fn new(guid_prefix: GuidPrefix, domain_id: DomainId, resend_period: Duration) -> Self {
+        let endpoint = Endpoint::builder(Guid::new(
+            guid_prefix,
+            ENTITYID_SPDP_BUILTIN_PARTICIPANT_ANNOUNCER,
+        ))
+        .reliability_level(ReliabilityKind::BestEffort)
+        .build();
+        let mut writer = StatelessWriter::new(Writer::new(
+            endpoint,
+            true,
+            resend_period,
+            Duration::ZERO,
+            Duration::ZERO,
+        ))
+        .with_resend_period(resend_period);
+        writer.reader_locator_add(ReaderLocator::new(spdp_multicast_locator(domain_id), false));
+        Self {
+            writer,
+            announced: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn writer(&self) -> &StatelessWriter {
+        &self.writer
+    }
+
+    pub fn writer_mut(&mut self) -> &mut StatelessWriter {
+        &mut self.writer
+    }
+
+    /// Also announces the participant to `locator`, e.g. a participant
+    /// reachable by unicast only.
+    pub fn add_peer(&mut self, locator: Locator) {
+        self.writer
+            .reader_locator_add(ReaderLocator::new(locator, false));
+    }
+
+    /// Announces `data` from now on, replacing the previous announcement.
+    /// It is sent right away.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer's HistoryCache cannot store the announcement.
+    pub fn announce(
+        &mut self,
+        data: &SPDPdiscoveredParticipantData,
+        now: Time,
+    ) -> io::Result<Vec<Output>> {
+        self.replace(ChangeKind::Alive, Some(data.to_data()), now)
+    }
+
+    /// Announces that the participant leaves the domain, so that the
+    /// participants that discovered it remove it without waiting for its
+    /// lease to expire. It replaces the previous announcement and is sent
+    /// right away; the participant is expected to shut down afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the writer's HistoryCache cannot store the change.
+    pub fn dispose(&mut self, now: Time) -> io::Result<Vec<Output>> {
+        self.replace(ChangeKind::NotAliveDisposedUnregistered, None, now)
+    }
+
+    /// Replaces the announcement in the writer's cache and sends it.
+    fn replace(
+        &mut self,
+        kind: ChangeKind,
+        data: Option<Data>,
+        now: Time,
+    ) -> io::Result<Vec<Output>> {
+        let change = self
+            .writer
+            .writer_mut()
+            .new_change(kind, data, ParameterList, InstanceHandle);
+        if let Some(previous) = self.announced.replace(change.clone()) {
+            self.writer.remove_change(&previous)?;
+        }
+        self.handle(Input::NewChange(change), now)
+    }
+}
+
+impl Behavior for SPDPbuiltinParticipantWriter {
+    fn guid(&self) -> Guid {
+        self.writer.guid()
+    }
+
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        self.writer.handle(input, now)
+    }
 }
 
-struct DiscoveredParticipantData {
-    proxy: ParticipantProxy,
-    topic_data: ParticipantBuiltinTopicData,
+/// A participant in the table of an [`SPDPbuiltinParticipantReader`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredParticipant {
+    pub data: SPDPdiscoveredParticipantData,
+    /// When the participant was last announced.
+    pub last_announced: Time,
 }
 
-struct ParticipantProxy {
+/// The SPDPbuiltinParticipantReader. It keeps a table of the participants
+/// announced in its domain with the same domain tag, updated with each
+/// announcement. The announcements are consumed rather than delivered, and
+/// those of the local participant are ignored.
+///
+/// A participant is removed when its lease expires or it announces its
+/// departure, which is reported with [`Output::ParticipantRemoved`] so that
+/// the local endpoints forget its endpoints.
+#[derive(Debug)]
+pub struct SPDPbuiltinParticipantReader {
+    reader: StatelessReader,
     domain_id: DomainId,
     domain_tag: String,
-    protocol_version: ProtocolVersion,
-    guid_prefix: GuidPrefix,
-    vendor_id: VendorId,
-    expects_inline_qos: bool,
-    available_builtin_endpoints: Vec<BuiltInEndpointKind>,
-    builtin_endpoint_qos: BuiltinEndpointQos,
-    meta_traffic_unicast_locator_list: Vec<Locator>,
-    meta_traffic_multicast_locator_list: Vec<Locator>,
-    default_multicast_locator_list: Vec<Locator>,
-    default_unicast_locator_list: Vec<Locator>,
-    manual_liveliness_count: Count,
+    discovered: BTreeMap<GuidPrefix, DiscoveredParticipant>,
+    leases: TimerQueue<GuidPrefix>,
 }
 
-type DomainId = u32;
+impl SPDPbuiltinParticipantReader {
+    /// A best-effort reader for the participant `guid_prefix` in
+    /// `domain_id`, which receives on the well-known multicast locator.
+    #[must_use]
+    pub fn new(guid_prefix: GuidPrefix, domain_id: DomainId, domain_tag: &str) -> Self {
+        let endpoint = Endpoint::builder(Guid::new(
+            guid_prefix,
+            ENTITYID_SPDP_BUILTIN_PARTICIPANT_DETECTOR,
+        ))
+        .reliability_level(ReliabilityKind::BestEffort)
+        .multicast_locator(spdp_multicast_locator(domain_id))
+        .build();
+        Self {
+            reader: StatelessReader::new(Reader::new(endpoint, false, Duration::ZERO)),
+            domain_id,
+            domain_tag: String::from(domain_tag),
+            discovered: BTreeMap::new(),
+            leases: TimerQueue::new(),
+        }
+    }
 
-struct BuiltInEndpointKind;
+    #[must_use]
+    pub const fn reader(&self) -> &StatelessReader {
+        &self.reader
+    }
 
-struct BuiltinEndpointQos;
+    /// The participants discovered so far.
+    pub fn discovered_participants(&self) -> impl Iterator<Item = &DiscoveredParticipant> {
+        self.discovered.values()
+    }
 
-struct ParticipantBuiltinTopicData {
-    key: BuiltinTopicKey,
-    user_data: UserDataQosPolicy,
+    #[must_use]
+    pub fn lookup(&self, guid_prefix: &GuidPrefix) -> Option<&DiscoveredParticipant> {
+        self.discovered.get(guid_prefix)
+    }
+
+    /// Records an announcement sent by the participant `source`. Returns
+    /// whether it came from a participant that was not known yet.
+    /// Announcements on behalf of another participant are ignored.
+    fn on_announcement(
+        &mut self,
+        source: GuidPrefix,
+        data: SPDPdiscoveredParticipantData,
+        now: Time,
+    ) -> bool {
+        if data.proxy.guid_prefix != source
+            || data.proxy.guid_prefix == self.reader.reader().guid().guid_prefix()
+            || data.proxy.domain_id != self.domain_id
+            || data.proxy.domain_tag != self.domain_tag
+        {
+            return false;
+        }
+        let guid_prefix = data.proxy.guid_prefix;
+        let discovered = DiscoveredParticipant {
+            data,
+            last_announced: now,
+        };
+        let new = self.discovered.insert(guid_prefix, discovered).is_none();
+        self.renew(guid_prefix, now);
+        new
+    }
+
+    /// Renews the lease of a discovered participant.
+    fn renew(&mut self, guid_prefix: GuidPrefix, now: Time) {
+        let Some(discovered) = self.discovered.get_mut(&guid_prefix) else {
+            return;
+        };
+        discovered.last_announced = now;
+        let expiry = now + discovered.data.lease_duration;
+        if expiry == TIME_INFINITE {
+            self.leases.cancel(&guid_prefix);
+        } else {
+            self.leases.schedule(guid_prefix, expiry);
+        }
+    }
+
+    /// Removes a participant from the table.
+    fn remove(&mut self, guid_prefix: GuidPrefix, outputs: &mut Vec<Output>) {
+        self.leases.cancel(&guid_prefix);
+        if self.discovered.remove(&guid_prefix).is_some() {
+            self.reader.remove_participant(guid_prefix);
+            outputs.push(Output::ParticipantRemoved(guid_prefix));
+        }
+    }
 }
 
-struct BuiltinTopicKey;
+impl Behavior for SPDPbuiltinParticipantReader {
+    fn guid(&self) -> Guid {
+        self.reader.guid()
+    }
 
-struct UserDataQosPolicy;
+    fn handle(&mut self, input: Input, now: Time) -> io::Result<Vec<Output>> {
+        if let Input::Received {
+            source,
+            submessage: RtpsSubmessage::Data(data),
+            ..
+        } = &input
+        {
+            let writer_guid = Guid::new(*source, data.writer_id);
+            if data.writer_id == ENTITYID_SPDP_BUILTIN_PARTICIPANT_ANNOUNCER
+                && self
+                    .reader
+                    .highest_received(&writer_guid)
+                    .is_some_and(|highest| data.writer_sn <= highest)
+            {
+                // A periodic resend, or an announcement of a participant that
+                // restarted with the same prefix. Receive it again so that it
+                // renews the lease and replaces the previous announcement.
+                self.reader.remove_participant(*source);
+            }
+        }
+        let mut outputs = Vec::new();
+        for output in self.reader.handle(input, now)? {
+            let Output::Deliver(change) = output else {
+                outputs.push(output);
+                continue;
+            };
+            self.reader
+                .reader_mut()
+                .reader_cache_mut()
+                .remove_change(&change)?;
+            if matches!(
+                change.kind(),
+                ChangeKind::NotAliveDisposed
+                    | ChangeKind::NotAliveUnregistered
+                    | ChangeKind::NotAliveDisposedUnregistered
+            ) {
+                // Only a participant can announce its own departure.
+                self.remove(change.writer_guid().guid_prefix(), &mut outputs);
+            } else if let Some(data) = change.data_value().and_then(|data| {
+                SPDPdiscoveredParticipantData::from_data(data, self.domain_id).ok()
+            }) {
+                self.on_announcement(change.writer_guid().guid_prefix(), data, now);
+            }
+        }
+        for guid_prefix in self.leases.expired(now) {
+            self.remove(guid_prefix, &mut outputs);
+        }
+        rearm(&mut outputs, self.leases.next_deadline());
+        Ok(outputs)
+    }
+}
 
-struct SPDPbuiltinParticipantWriter {
-    writer: StatelessWriter,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::Outgoing,
+        structure::{PROTOCOLVERSION, VENDORID_UNKNOWN},
+    };
+
+    fn participant_data(guid_prefix: GuidPrefix) -> SPDPdiscoveredParticipantData {
+        SPDPdiscoveredParticipantData {
+            proxy: ParticipantProxy {
+                domain_id: 0,
+                domain_tag: String::new(),
+                protocol_version: PROTOCOLVERSION,
+                guid_prefix,
+                vendor_id: VENDORID_UNKNOWN,
+                expects_inline_qos: false,
+                available_builtin_endpoints: BuiltinEndpointSet::PARTICIPANT_ANNOUNCER
+                    | BuiltinEndpointSet::PARTICIPANT_DETECTOR,
+                builtin_endpoint_qos: BuiltinEndpointQos::default(),
+                meta_traffic_unicast_locator_list: vec!["192.168.1.2:7410".parse().unwrap()],
+                meta_traffic_multicast_locator_list: vec![spdp_multicast_locator(0)],
+                default_multicast_locator_list: vec![],
+                default_unicast_locator_list: vec!["[fe80::1]:7411".parse().unwrap()],
+                manual_liveliness_count: 0,
+            },
+            user_data: Vec::new(),
+            lease_duration: Duration::from_secs(20),
+        }
+    }
+
+    /// Hands the submessages in `outputs` to `reader`. Returns them along
+    /// with the reader's outputs.
+    fn receive(
+        reader: &mut SPDPbuiltinParticipantReader,
+        source: GuidPrefix,
+        outputs: Vec<Output>,
+        now: Time,
+    ) -> (Vec<Outgoing>, Vec<Output>) {
+        let mut sent = Vec::new();
+        let mut received = Vec::new();
+        for output in outputs {
+            if let Output::Send(outgoing) = output {
+                let input = Input::Received {
+                    source,
+                    source_locator: "192.168.1.2:7410".parse().unwrap(),
+                    submessage: outgoing.submessage.clone(),
+                };
+                received.extend(reader.handle(input, now).unwrap());
+                sent.push(outgoing);
+            }
+        }
+        (sent, received)
+    }
+
+    #[test]
+    fn test_participant_data() {
+        let mut data = participant_data([1; 12]);
+        data.proxy.domain_id = 3;
+        data.proxy.domain_tag = String::from("lab");
+        data.proxy.expects_inline_qos = true;
+        data.proxy.manual_liveliness_count = 5;
+        data.proxy.builtin_endpoint_qos =
+            BuiltinEndpointQos::BEST_EFFORT_PARTICIPANT_MESSAGE_DATA_READER;
+        data.user_data = vec![1, 2, 3];
+        data.lease_duration = Duration::from_millis(1500);
+        assert_eq!(
+            SPDPdiscoveredParticipantData::from_data(&data.to_data(), 0).unwrap(),
+            data
+        );
+
+        data.lease_duration = Duration::MAX;
+        assert_eq!(
+            SPDPdiscoveredParticipantData::from_data(&data.to_data(), 0)
+                .unwrap()
+                .lease_duration,
+            Duration::MAX
+        );
+
+        // Missing parameters take their defaults, and the domain id is
+        // that of the receiver.
+        let mut writer = ParameterListWriter::new();
+        writer.parameter(
+            PID_PARTICIPANT_GUID,
+            &[[2; 12].as_slice(), &[0, 0, 1, 0xc1]].concat(),
+        );
+        let minimal = Data::from(writer.finish());
+        let parsed = SPDPdiscoveredParticipantData::from_data(&minimal, 7).unwrap();
+        assert_eq!(parsed.key(), Guid::new([2; 12], ENTITYID_PARTICIPANT));
+        assert_eq!(parsed.proxy.domain_id, 7);
+        assert_eq!(parsed.lease_duration, DEFAULT_LEASE_DURATION);
+
+        let empty = Data::from(ParameterListWriter::new().finish());
+        assert!(SPDPdiscoveredParticipantData::from_data(&empty, 0).is_err());
+    }
+
+    #[test]
+    fn test_announcement_and_detection() {
+        let resend_period = Duration::from_secs(3);
+        let mut writer = SPDPbuiltinParticipantWriter::new([1; 12], 0, resend_period);
+        let mut reader = SPDPbuiltinParticipantReader::new([2; 12], 0, "");
+        let now = Time::new(0, 0);
+
+        let data = participant_data([1; 12]);
+        let outputs = writer.announce(&data, now).unwrap();
+        assert!(outputs.contains(&Output::ArmTimer(now + resend_period)));
+        let (sent, _) = receive(&mut reader, [1; 12], outputs, now);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].locator, "239.255.0.1:7400".parse().unwrap());
+        assert_eq!(reader.lookup(&[1; 12]).unwrap().data, data);
+        assert!(reader
+            .reader()
+            .reader()
+            .reader_cache()
+            .changes()
+            .next()
+            .is_none());
+
+        // The announcement is repeated every resend period, and an updated
+        // one replaces it.
+        let later = now + resend_period;
+        let outputs = writer.handle(Input::TimerFired, later).unwrap();
+        assert_eq!(receive(&mut reader, [1; 12], outputs, later).0.len(), 1);
+        let mut updated = data.clone();
+        updated.user_data = vec![4];
+        writer.add_peer("10.0.0.2:7410".parse().unwrap());
+        let outputs = writer.announce(&updated, later).unwrap();
+        assert_eq!(receive(&mut reader, [1; 12], outputs, later).0.len(), 2);
+        assert_eq!(writer.writer().writer().writer_cache().changes().count(), 1);
+        let discovered = reader.lookup(&[1; 12]).unwrap();
+        assert_eq!(discovered.data.user_data, vec![4]);
+        assert_eq!(discovered.last_announced, later);
+
+        // The local participant and other domains are left out.
+        let mut other = SPDPbuiltinParticipantWriter::new([3; 12], 1, resend_period);
+        let mut data = participant_data([3; 12]);
+        data.proxy.domain_id = 1;
+        let outputs = other.announce(&data, later).unwrap();
+        receive(&mut reader, [3; 12], outputs, later);
+        let mut own = SPDPbuiltinParticipantWriter::new([2; 12], 0, resend_period);
+        let outputs = own.announce(&participant_data([2; 12]), later).unwrap();
+        receive(&mut reader, [2; 12], outputs, later);
+        assert_eq!(
+            reader
+                .discovered_participants()
+                .map(|p| p.data.proxy.guid_prefix)
+                .collect::<Vec<_>>(),
+            vec![[1; 12]]
+        );
+    }
+
+    #[test]
+    fn test_lease_expiry() {
+        let resend_period = Duration::from_secs(3);
+        let lease_duration = Duration::from_secs(10);
+        let mut writer = SPDPbuiltinParticipantWriter::new([1; 12], 0, resend_period);
+        let mut reader = SPDPbuiltinParticipantReader::new([2; 12], 0, "");
+        let mut data = participant_data([1; 12]);
+        data.lease_duration = lease_duration;
+        let at = |secs| Time::from(Duration::from_secs(secs));
+
+        let outputs = writer.announce(&data, at(0)).unwrap();
+        let (_, outputs) = receive(&mut reader, [1; 12], outputs, at(0));
+        assert_eq!(outputs, vec![Output::ArmTimer(at(10))]);
+
+        // Each announcement renews the lease.
+        let outputs = writer.handle(Input::TimerFired, at(3)).unwrap();
+        let (_, outputs) = receive(&mut reader, [1; 12], outputs, at(3));
+        assert_eq!(outputs, vec![Output::ArmTimer(at(13))]);
+        assert_eq!(
+            reader.handle(Input::TimerFired, at(12)).unwrap(),
+            vec![Output::ArmTimer(at(13))]
+        );
+        assert!(reader.lookup(&[1; 12]).is_some());
+
+        let outputs = reader.handle(Input::TimerFired, at(13)).unwrap();
+        assert_eq!(outputs, vec![Output::ParticipantRemoved([1; 12])]);
+        assert!(reader.lookup(&[1; 12]).is_none());
+
+        // Announcing another participant neither discovers nor renews it.
+        let outputs = writer.announce(&participant_data([3; 12]), at(14)).unwrap();
+        let (_, outputs) = receive(&mut reader, [1; 12], outputs, at(14));
+        assert!(outputs.is_empty());
+        assert!(reader.lookup(&[3; 12]).is_none());
+
+        // A participant announced again is discovered again, and one with an
+        // infinite lease never expires.
+        data.lease_duration = Duration::MAX;
+        let outputs = writer.announce(&data, at(20)).unwrap();
+        let (_, outputs) = receive(&mut reader, [1; 12], outputs, at(20));
+        assert!(outputs.is_empty());
+        assert!(reader.lookup(&[1; 12]).is_some());
+
+        // A participant that restarts with the same prefix numbers its
+        // announcements from 1 again, and replaces the previous one.
+        let mut restarted = SPDPbuiltinParticipantWriter::new([1; 12], 0, resend_period);
+        data.lease_duration = lease_duration;
+        data.proxy.default_unicast_locator_list = vec!["10.0.0.1:7411".parse().unwrap()];
+        let outputs = restarted.announce(&data, at(21)).unwrap();
+        let (_, outputs) = receive(&mut reader, [1; 12], outputs, at(21));
+        assert_eq!(outputs, vec![Output::ArmTimer(at(31))]);
+        assert_eq!(reader.lookup(&[1; 12]).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_departure() {
+        let resend_period = Duration::from_secs(3);
+        let mut writer = SPDPbuiltinParticipantWriter::new([1; 12], 0, resend_period);
+        let mut reader = SPDPbuiltinParticipantReader::new([2; 12], 0, "");
+        let now = Time::new(0, 0);
+
+        let outputs = writer.announce(&participant_data([1; 12]), now).unwrap();
+        receive(&mut reader, [1; 12], outputs, now);
+        assert!(reader.lookup(&[1; 12]).is_some());
+
+        let outputs = writer.dispose(now).unwrap();
+        let (sent, outputs) = receive(&mut reader, [1; 12], outputs, now);
+        let RtpsSubmessage::Data(data) = &sent[0].submessage else {
+            panic!("not a DATA");
+        };
+        assert_eq!(data.kind, ChangeKind::NotAliveDisposedUnregistered);
+        assert_eq!(data.serialized_payload, None);
+        assert_eq!(outputs, vec![Output::ParticipantRemoved([1; 12])]);
+        assert!(reader.lookup(&[1; 12]).is_none());
+        assert_eq!(writer.writer().writer().writer_cache().changes().count(), 1);
+    }
 }
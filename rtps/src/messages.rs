@@ -2,11 +2,16 @@
 //!
 //! See the Section 8.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=37).
 
-use std::net::{Ipv4Addr, SocketAddr};
+use std::{
+    collections::BTreeSet,
+    net::{Ipv4Addr, SocketAddr},
+    ops::Add,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::structure::{
-    ChangeCount, EntityId, GuidPrefix, Locator, ProtocolVersion, SequenceNumber, VendorId,
-    GUIDPREFIX_UNKNOWN, PROTOCOLVERSION, VENDORID_UNKNOWN,
+    data::Data, ChangeCount, ChangeKind, EntityId, GuidPrefix, Locator, ProtocolVersion,
+    SequenceNumber, VendorId, GUIDPREFIX_UNKNOWN, PROTOCOLVERSION, VENDORID_UNKNOWN,
 };
 
 type SubmessageFlag = bool;
@@ -41,6 +46,73 @@ impl Time {
     pub const fn new(seconds: u32, fraction: u32) -> Time {
         Self { seconds, fraction }
     }
+
+    /// The current wall-clock time, measured from the UNIX epoch.
+    #[must_use]
+    pub fn now() -> Time {
+        Self::from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        )
+    }
+
+    #[must_use]
+    pub const fn seconds(&self) -> u32 {
+        self.seconds
+    }
+
+    /// Fraction of a second in units of 1/2^32 seconds.
+    #[must_use]
+    pub const fn fraction(&self) -> u32 {
+        self.fraction
+    }
+
+    /// Returns `true` for [`TIME_INVALID`] and [`TIME_INFINITE`], which are
+    /// not points in time.
+    #[must_use]
+    pub fn is_special(&self) -> bool {
+        *self == TIME_INVALID || *self == TIME_INFINITE
+    }
+
+    /// Time elapsed since [`TIME_ZERO`].
+    #[must_use]
+    pub fn as_duration(&self) -> Duration {
+        let nanos = (u64::from(self.fraction) * 1_000_000_000) >> 32;
+        Duration::new(u64::from(self.seconds), nanos as u32)
+    }
+}
+
+/// Converts a duration since [`TIME_ZERO`]. Durations beyond the range of
+/// [`Time`] saturate to [`TIME_INFINITE`].
+impl From<Duration> for Time {
+    fn from(duration: Duration) -> Self {
+        match u32::try_from(duration.as_secs()) {
+            Ok(seconds) => {
+                let fraction = ((u64::from(duration.subsec_nanos()) << 32) / 1_000_000_000) as u32;
+                let time = Time::new(seconds, fraction);
+                if time.is_special() {
+                    TIME_INFINITE
+                } else {
+                    time
+                }
+            }
+            Err(_) => TIME_INFINITE,
+        }
+    }
+}
+
+/// Adding to [`TIME_INVALID`] or [`TIME_INFINITE`] leaves them unchanged.
+impl Add<Duration> for Time {
+    type Output = Time;
+
+    fn add(self, rhs: Duration) -> Time {
+        if self.is_special() {
+            self
+        } else {
+            Time::from(self.as_duration().saturating_add(rhs))
+        }
+    }
 }
 
 pub const TIME_ZERO: Time = Time::new(0, 0);
@@ -255,3 +327,230 @@ pub struct Parameter {
     length: i16,
     value: Vec<u8>,
 }
+
+/// A submessage with its elements decoded into typed fields. This is the
+/// form exchanged between the behavior module and the transport.
+///
+/// See Section 8.3.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=55).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RtpsSubmessage {
+    AckNack(AckNackSubmessage),
+    Data(DataSubmessage),
+    DataFrag(DataFragSubmessage),
+    Gap(GapSubmessage),
+    Heartbeat(HeartbeatSubmessage),
+    HeartbeatFrag(HeartbeatFragSubmessage),
+    NackFrag(NackFragSubmessage),
+}
+
+/// A set of sequence numbers within `[base, base + 255]`.
+///
+/// See Section 8.3.5.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceNumberSet {
+    base: SequenceNumber,
+    set: BTreeSet<SequenceNumber>,
+}
+
+impl SequenceNumberSet {
+    /// Maximum number of sequence numbers a set can span.
+    pub const MAX_SPAN: u64 = 256;
+
+    /// Creates a set starting at `base`. Sequence numbers outside
+    /// `[base, base + 255]` cannot be represented and are dropped.
+    pub fn new(base: SequenceNumber, set: impl IntoIterator<Item = SequenceNumber>) -> Self {
+        let set = set
+            .into_iter()
+            .filter(|sn| sn.value() >= base.value() && sn.value() - base.value() < Self::MAX_SPAN)
+            .collect();
+        Self { base, set }
+    }
+
+    #[must_use]
+    pub const fn base(&self) -> SequenceNumber {
+        self.base
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = SequenceNumber> + '_ {
+        self.set.iter().copied()
+    }
+
+    #[must_use]
+    pub fn contains(&self, sequence_number: SequenceNumber) -> bool {
+        self.set.contains(&sequence_number)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+}
+
+/// A set of fragment numbers within `[base, base + 255]`.
+///
+/// See Section 8.3.5.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FragmentNumberSet {
+    base: FragmentNumber,
+    set: BTreeSet<FragmentNumber>,
+}
+
+impl FragmentNumberSet {
+    /// Maximum number of fragment numbers a set can span.
+    pub const MAX_SPAN: u32 = 256;
+
+    /// Creates a set starting at `base`. Fragment numbers outside
+    /// `[base, base + 255]` cannot be represented and are dropped.
+    pub fn new(base: FragmentNumber, set: impl IntoIterator<Item = FragmentNumber>) -> Self {
+        let set = set
+            .into_iter()
+            .filter(|n| *n >= base && n - base < Self::MAX_SPAN)
+            .collect();
+        Self { base, set }
+    }
+
+    #[must_use]
+    pub const fn base(&self) -> FragmentNumber {
+        self.base
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = FragmentNumber> + '_ {
+        self.set.iter().copied()
+    }
+
+    #[must_use]
+    pub fn contains(&self, fragment_number: FragmentNumber) -> bool {
+        self.set.contains(&fragment_number)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+}
+
+/// See Section 8.3.7.1 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AckNackSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    /// `base` acknowledges every sequence number below it; the set lists the
+    /// sequence numbers the reader is missing.
+    pub reader_sn_state: SequenceNumberSet,
+    pub count: Count,
+    pub final_flag: bool,
+}
+
+/// See Section 8.3.7.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    pub writer_sn: SequenceNumber,
+    /// The kind of the change, sent as its status info. A change that is
+    /// not alive usually has no payload.
+    pub kind: ChangeKind,
+    /// The time at which the writer created the change, sent in a preceding
+    /// INFO_TS.
+    pub source_timestamp: Option<Time>,
+    pub serialized_payload: Option<Data>,
+}
+
+/// One or more consecutive fragments of a sample too large to be sent in a
+/// single DATA.
+///
+/// See Section 8.3.7.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataFragSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    pub writer_sn: SequenceNumber,
+    /// Number of the first fragment in the submessage, starting at 1.
+    pub fragment_starting_num: FragmentNumber,
+    pub fragments_in_submessage: u16,
+    /// Size of every fragment but the last one, in bytes.
+    pub fragment_size: u16,
+    /// Size of the complete serialized sample, in bytes.
+    pub sample_size: u32,
+    /// The time at which the writer created the change, sent in a preceding
+    /// INFO_TS.
+    pub source_timestamp: Option<Time>,
+    pub serialized_payload: Data,
+}
+
+/// See Section 8.3.7.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GapSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    /// First sequence number of the contiguous range `[gap_start,
+    /// gap_list.base)` that is irrelevant to the reader.
+    pub gap_start: SequenceNumber,
+    /// Further irrelevant sequence numbers at or above its base.
+    pub gap_list: SequenceNumberSet,
+    /// Group sequence number of `gap_start`, sent with the GroupInfo flag
+    /// (version 2.4 and later) by writers that belong to an ordered group.
+    pub gap_start_gsn: Option<SequenceNumber>,
+    /// Group sequence number of the last irrelevant change, sent along with
+    /// `gap_start_gsn`.
+    pub gap_end_gsn: Option<SequenceNumber>,
+}
+
+/// See Section 8.3.7.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeartbeatSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    pub first_sn: SequenceNumber,
+    pub last_sn: SequenceNumber,
+    pub count: Count,
+    /// Set when the writer does not require a response.
+    pub final_flag: bool,
+    /// Set when the heartbeat asserts the liveliness of the writer.
+    pub liveliness_flag: bool,
+}
+
+/// Tells a reader which fragments of a sample the writer has available.
+///
+/// See Section 8.3.7.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeartbeatFragSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    pub writer_sn: SequenceNumber,
+    /// Every fragment up to this one is available.
+    pub last_fragment_num: FragmentNumber,
+    pub count: Count,
+}
+
+/// Requests the fragments of a sample that a reader is missing.
+///
+/// See Section 8.3.7.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NackFragSubmessage {
+    pub reader_id: EntityId,
+    pub writer_id: EntityId,
+    pub writer_sn: SequenceNumber,
+    pub fragment_number_state: FragmentNumberSet,
+    pub count: Count,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_duration_conversion() {
+        let time = Time::from(Duration::from_millis(1500));
+        assert_eq!(time, Time::new(1, 1 << 31));
+        assert_eq!(time.as_duration(), Duration::from_millis(1500));
+        assert_eq!(time + Duration::from_millis(500), Time::new(2, 0));
+    }
+
+    #[test]
+    fn test_time_special_values() {
+        assert_eq!(TIME_INFINITE + Duration::from_secs(1), TIME_INFINITE);
+        assert_eq!(TIME_INVALID + Duration::from_secs(1), TIME_INVALID);
+        assert_eq!(Time::new(u32::MAX - 1, 0) + Duration::MAX, TIME_INFINITE);
+    }
+}
@@ -0,0 +1,300 @@
+//! Serialized payloads carried by a
+//! [`CacheChange`](super::historycache::CacheChange).
+//!
+//! A [`Data`] is a reference-counted, immutable byte buffer. Cloning it is
+//! cheap, so a single serialized sample can be shared by the writer's
+//! [`HistoryCache`](super::historycache::HistoryCache), every ReaderProxy that
+//! still has to send it and the transport without copying the bytes.
+//!
+//! Fixed-size types can avoid allocating per sample by loaning buffers from a
+//! [`DataPool`]. The buffer goes back to the pool once the last [`Data`]
+//! referencing it is dropped.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, Weak},
+};
+
+/// Reference-counted serialized payload.
+#[derive(Clone)]
+pub struct Data {
+    buffer: Buffer,
+}
+
+#[derive(Clone)]
+enum Buffer {
+    Shared(Arc<[u8]>),
+    Loaned(Arc<PooledBuffer>),
+}
+
+impl Data {
+    #[must_use]
+    pub fn new(bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self {
+            buffer: Buffer::Shared(bytes.into()),
+        }
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.buffer {
+            Buffer::Shared(bytes) => bytes,
+            Buffer::Loaned(pooled) => &pooled.bytes,
+        }
+    }
+
+    /// Returns `true` if the payload lives in a buffer loaned from a
+    /// [`DataPool`].
+    #[must_use]
+    pub fn is_loaned(&self) -> bool {
+        matches!(self.buffer, Buffer::Loaned(_))
+    }
+
+    /// Returns `true` if both values share the same underlying buffer.
+    #[must_use]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.buffer, &other.buffer) {
+            (Buffer::Shared(a), Buffer::Shared(b)) => Arc::ptr_eq(a, b),
+            (Buffer::Loaned(a), Buffer::Loaned(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<&[u8]> for Data {
+    fn from(bytes: &[u8]) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl fmt::Debug for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Data")
+            .field("len", &self.len())
+            .field("loaned", &self.is_loaned())
+            .finish()
+    }
+}
+
+impl PartialEq for Data {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Data {}
+
+impl PartialOrd for Data {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Data {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl Hash for Data {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+type FreeList = Mutex<Vec<Box<[u8]>>>;
+
+/// A buffer taken from a [`DataPool`] that returns itself, zeroed, to the
+/// pool when dropped.
+struct PooledBuffer {
+    bytes: Box<[u8]>,
+    pool: Weak<FreeList>,
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            self.bytes.fill(0);
+            if let Ok(mut free) = pool.lock() {
+                free.push(std::mem::take(&mut self.bytes));
+            }
+        }
+    }
+}
+
+/// Pool of fixed-size buffers for types whose serialized size is known up
+/// front.
+///
+/// ```
+/// use rtps::structure::data::DataPool;
+///
+/// let pool = DataPool::new(4, 1);
+/// let mut loan = pool.loan().unwrap();
+/// loan.copy_from_slice(&[1, 2, 3, 4]);
+/// let data = loan.into_data();
+/// assert!(pool.loan().is_none());
+/// drop(data);
+/// assert!(pool.loan().is_some());
+/// ```
+#[derive(Clone)]
+pub struct DataPool {
+    sample_size: usize,
+    free: Arc<FreeList>,
+}
+
+impl DataPool {
+    /// Creates a pool holding `capacity` zeroed buffers of `sample_size`
+    /// bytes each.
+    #[must_use]
+    pub fn new(sample_size: usize, capacity: usize) -> Self {
+        let free = (0..capacity)
+            .map(|_| vec![0; sample_size].into_boxed_slice())
+            .collect();
+        Self {
+            sample_size,
+            free: Arc::new(Mutex::new(free)),
+        }
+    }
+
+    #[must_use]
+    pub const fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    /// Number of buffers currently available for loan.
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.free.lock().map_or(0, |free| free.len())
+    }
+
+    /// Loans a zeroed buffer from the pool, or returns `None` if every buffer
+    /// is in use.
+    #[must_use]
+    pub fn loan(&self) -> Option<DataLoan> {
+        let bytes = self.free.lock().ok()?.pop()?;
+        Some(DataLoan {
+            buffer: PooledBuffer {
+                bytes,
+                pool: Arc::downgrade(&self.free),
+            },
+        })
+    }
+}
+
+impl fmt::Debug for DataPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataPool")
+            .field("sample_size", &self.sample_size)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+/// A writable buffer loaned from a [`DataPool`]. Fill it in place, then
+/// freeze it with [`DataLoan::into_data`].
+pub struct DataLoan {
+    buffer: PooledBuffer,
+}
+
+impl DataLoan {
+    #[must_use]
+    pub fn into_data(self) -> Data {
+        Data {
+            buffer: Buffer::Loaned(Arc::new(self.buffer)),
+        }
+    }
+}
+
+impl Deref for DataLoan {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer.bytes
+    }
+}
+
+impl DerefMut for DataLoan {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.bytes
+    }
+}
+
+impl fmt::Debug for DataLoan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataLoan")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_shares_buffer() {
+        let data = Data::from(vec![1, 2, 3]);
+        let clone = data.clone();
+        assert!(data.ptr_eq(&clone));
+        assert_eq!(&*clone, &[1, 2, 3]);
+        assert_eq!(data, Data::from(&[1, 2, 3][..]));
+        assert!(!data.ptr_eq(&Data::from(&[1, 2, 3][..])));
+    }
+
+    #[test]
+    fn test_pool_recycles_buffers() {
+        let pool = DataPool::new(2, 2);
+        let mut loan = pool.loan().unwrap();
+        loan.copy_from_slice(&[7, 8]);
+        let data = loan.into_data();
+        let other = pool.loan().unwrap();
+        assert_eq!(pool.available(), 0);
+        assert!(pool.loan().is_none());
+
+        let clone = data.clone();
+        drop(data);
+        assert_eq!(pool.available(), 0);
+        assert!(clone.is_loaned());
+        assert_eq!(&*clone, &[7, 8]);
+        drop(clone);
+        assert_eq!(pool.available(), 1);
+        // The recycled buffer does not leak the previous sample.
+        let loan = pool.loan().unwrap();
+        assert_eq!(&*loan, &[0, 0]);
+        drop(loan);
+
+        drop(other);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn test_buffer_outlives_pool() {
+        let pool = DataPool::new(1, 1);
+        let data = pool.loan().unwrap().into_data();
+        drop(pool);
+        assert_eq!(&*data, &[0]);
+    }
+}
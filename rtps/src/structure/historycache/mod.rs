@@ -0,0 +1,489 @@
+//! The HistoryCache and the CacheChanges it holds.
+//!
+//! See Sections 8.2.2 and 8.2.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=25).
+//!
+//! A [`HistoryCache`] keeps its changes in a [`HistoryCacheStorage`]
+//! backend. [`InMemoryStorage`] is used by default; endpoints with
+//! `PERSISTENT` durability can use [`persistent::LogStorage`] instead so that
+//! their history, and the sequence numbers already handed out, survive a
+//! process restart.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    ops::Bound::{Excluded, Unbounded},
+    time::Duration,
+};
+
+use super::{data::Data, ChangeKind, Guid, InstanceHandle, ParameterList, SequenceNumber};
+use crate::messages::Time;
+
+pub mod persistent;
+
+/// See Section 8.2.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=25)
+#[derive(Debug)]
+pub struct HistoryCache {
+    storage: Box<dyn HistoryCacheStorage>,
+}
+
+impl Default for HistoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HistoryCache {
+    /// See section 8.2.2.1 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_storage(InMemoryStorage::new())
+    }
+
+    /// Creates a cache on top of `storage`. Changes already present in the
+    /// storage, e.g. recovered from disk, are part of the cache.
+    #[must_use]
+    pub fn with_storage(storage: impl HistoryCacheStorage + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+        }
+    }
+
+    /// See section 8.2.2.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    ///
+    /// # Errors
+    ///
+    /// Fails if the storage backend cannot record the change.
+    pub fn add_change(&mut self, change: CacheChange) -> io::Result<()> {
+        self.storage.insert(change)
+    }
+
+    /// See section 8.2.2.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    ///
+    /// # Errors
+    ///
+    /// Fails if the storage backend cannot record the removal.
+    pub fn remove_change(&mut self, change: &CacheChange) -> io::Result<Option<CacheChange>> {
+        self.storage
+            .remove(change.writer_guid, change.sequence_number)
+    }
+
+    #[must_use]
+    pub fn get_change(
+        &self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> Option<&CacheChange> {
+        self.storage.get(writer_guid, sequence_number)
+    }
+
+    /// Iterates over the changes ordered by writer and sequence number.
+    pub fn changes(&self) -> impl Iterator<Item = &CacheChange> {
+        self.storage.iter()
+    }
+
+    /// Iterates, in sequence order, over the changes of `writer_guid` whose
+    /// sequence number is above `sequence_number`.
+    pub fn changes_after(
+        &self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> impl Iterator<Item = &CacheChange> {
+        self.storage.iter_after(writer_guid, sequence_number)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// See section 8.2.2.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    #[must_use]
+    pub fn get_seq_num_min(&self) -> Option<SequenceNumber> {
+        self.changes().map(|c| c.sequence_number).min()
+    }
+
+    /// See section 8.2.2.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
+    #[must_use]
+    pub fn get_seq_num_max(&self) -> Option<SequenceNumber> {
+        self.changes().map(|c| c.sequence_number).max()
+    }
+
+    /// The earliest time at which a change expires under a Lifespan of
+    /// `lifespan`, used to schedule the next [`HistoryCache::remove_expired`].
+    #[must_use]
+    pub fn next_expiry(&self, lifespan: Duration) -> Option<Time> {
+        self.changes().filter_map(|c| c.expiry(lifespan)).min()
+    }
+
+    /// Removes and returns every change whose [`CacheChange::expiry`] is not
+    /// later than `now`. Applies to writer and reader caches alike.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the storage backend cannot record a removal. Changes removed
+    /// before the failure stay removed.
+    pub fn remove_expired(
+        &mut self,
+        now: Time,
+        lifespan: Duration,
+    ) -> io::Result<Vec<CacheChange>> {
+        let expired: Vec<_> = self
+            .changes()
+            .filter(|c| c.expiry(lifespan).is_some_and(|expiry| expiry <= now))
+            .map(|c| (c.writer_guid, c.sequence_number))
+            .collect();
+        let mut removed = Vec::with_capacity(expired.len());
+        for (writer_guid, sequence_number) in expired {
+            removed.extend(self.storage.remove(writer_guid, sequence_number)?);
+        }
+        Ok(removed)
+    }
+
+    /// The highest sequence number ever added to the cache, even if that
+    /// change has since been removed. A writer resumes numbering after it.
+    #[must_use]
+    pub fn last_sequence_number(&self) -> Option<SequenceNumber> {
+        self.storage.last_sequence_number()
+    }
+}
+
+/// Storage backend of a [`HistoryCache`].
+///
+/// Changes are identified by their writer GUID and sequence number, so a
+/// reader's cache can hold changes from several writers.
+pub trait HistoryCacheStorage: fmt::Debug + Send {
+    /// Stores `change`, replacing any change with the same identity.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the change cannot be recorded.
+    fn insert(&mut self, change: CacheChange) -> io::Result<()>;
+
+    /// Removes and returns the change, if present.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the removal cannot be recorded.
+    fn remove(
+        &mut self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> io::Result<Option<CacheChange>>;
+
+    fn get(&self, writer_guid: Guid, sequence_number: SequenceNumber) -> Option<&CacheChange>;
+
+    /// Iterates over the stored changes ordered by writer and sequence
+    /// number.
+    fn iter(&self) -> Box<dyn Iterator<Item = &CacheChange> + '_>;
+
+    /// Iterates over the changes of `writer_guid` whose sequence number is
+    /// above `sequence_number`, ordered by sequence number.
+    fn iter_after(
+        &self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> Box<dyn Iterator<Item = &CacheChange> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The highest sequence number ever inserted, including removed changes.
+    fn last_sequence_number(&self) -> Option<SequenceNumber>;
+}
+
+/// Volatile [`HistoryCacheStorage`] backed by an ordered map.
+#[derive(Debug, Default)]
+pub struct InMemoryStorage {
+    changes: BTreeMap<(Guid, SequenceNumber), CacheChange>,
+    last_sequence_number: Option<SequenceNumber>,
+}
+
+impl InMemoryStorage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raises the recorded last sequence number without storing a change.
+    fn observe_sequence_number(&mut self, sequence_number: SequenceNumber) {
+        self.last_sequence_number = self.last_sequence_number.max(Some(sequence_number));
+    }
+}
+
+impl HistoryCacheStorage for InMemoryStorage {
+    fn insert(&mut self, change: CacheChange) -> io::Result<()> {
+        self.observe_sequence_number(change.sequence_number);
+        self.changes
+            .insert((change.writer_guid, change.sequence_number), change);
+        Ok(())
+    }
+
+    fn remove(
+        &mut self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> io::Result<Option<CacheChange>> {
+        Ok(self.changes.remove(&(writer_guid, sequence_number)))
+    }
+
+    fn get(&self, writer_guid: Guid, sequence_number: SequenceNumber) -> Option<&CacheChange> {
+        self.changes.get(&(writer_guid, sequence_number))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &CacheChange> + '_> {
+        Box::new(self.changes.values())
+    }
+
+    fn iter_after(
+        &self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> Box<dyn Iterator<Item = &CacheChange> + '_> {
+        Box::new(
+            self.changes
+                .range((Excluded((writer_guid, sequence_number)), Unbounded))
+                .take_while(move |((guid, _), _)| *guid == writer_guid)
+                .map(|(_, change)| change),
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    fn last_sequence_number(&self) -> Option<SequenceNumber> {
+        self.last_sequence_number
+    }
+}
+
+/// See section 8.2.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=28).
+#[derive(Clone, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct CacheChange {
+    kind: ChangeKind,
+    writer_guid: Guid,
+    instance_handle: InstanceHandle,
+    sequence_number: SequenceNumber,
+    data_value: Option<Data>,
+    inline_qos: ParameterList,
+    source_timestamp: Option<Time>,
+    reception_timestamp: Option<Time>,
+    destination: Option<Vec<Guid>>,
+}
+
+impl CacheChange {
+    #[must_use]
+    pub const fn new(
+        kind: ChangeKind,
+        writer_guid: Guid,
+        instance_handle: InstanceHandle,
+        sequence_number: SequenceNumber,
+        data_value: Option<Data>,
+        inline_qos: ParameterList,
+    ) -> Self {
+        Self {
+            kind,
+            writer_guid,
+            instance_handle,
+            sequence_number,
+            data_value,
+            inline_qos,
+            source_timestamp: None,
+            reception_timestamp: None,
+            destination: None,
+        }
+    }
+
+    /// Sets the time at which the writer created the change.
+    #[must_use]
+    pub const fn with_source_timestamp(mut self, timestamp: Time) -> Self {
+        self.source_timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the time at which the reader received the change.
+    #[must_use]
+    pub const fn with_reception_timestamp(mut self, timestamp: Time) -> Self {
+        self.reception_timestamp = Some(timestamp);
+        self
+    }
+
+    /// Directs the change to the readers in `readers`. A StatefulWriter
+    /// only sends it to those and a GAP to its other matched readers.
+    #[must_use]
+    pub fn with_destination(mut self, readers: impl IntoIterator<Item = Guid>) -> Self {
+        self.destination = Some(readers.into_iter().collect());
+        self
+    }
+
+    #[must_use]
+    pub const fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    #[must_use]
+    pub const fn writer_guid(&self) -> Guid {
+        self.writer_guid
+    }
+
+    #[must_use]
+    pub const fn instance_handle(&self) -> InstanceHandle {
+        self.instance_handle
+    }
+
+    #[must_use]
+    pub const fn sequence_number(&self) -> SequenceNumber {
+        self.sequence_number
+    }
+
+    /// The serialized payload. Cloning the returned [`Data`] shares the
+    /// buffer rather than copying it.
+    #[must_use]
+    pub const fn data_value(&self) -> Option<&Data> {
+        self.data_value.as_ref()
+    }
+
+    #[must_use]
+    pub const fn inline_qos(&self) -> &ParameterList {
+        &self.inline_qos
+    }
+
+    #[must_use]
+    pub const fn source_timestamp(&self) -> Option<Time> {
+        self.source_timestamp
+    }
+
+    #[must_use]
+    pub const fn reception_timestamp(&self) -> Option<Time> {
+        self.reception_timestamp
+    }
+
+    /// The readers the change is directed to, or `None` if it is meant for
+    /// every matched reader.
+    #[must_use]
+    pub fn destination(&self) -> Option<&[Guid]> {
+        self.destination.as_deref()
+    }
+
+    /// Whether the change is meant for `reader`.
+    #[must_use]
+    pub fn is_destined_for(&self, reader: &Guid) -> bool {
+        self.destination
+            .as_ref()
+            .is_none_or(|destination| destination.contains(reader))
+    }
+
+    /// The time at which the change expires under a Lifespan of `lifespan`.
+    /// Lifespan is measured from the source timestamp, falling back to the
+    /// reception timestamp for changes received without one. Changes with
+    /// neither never expire.
+    #[must_use]
+    pub fn expiry(&self, lifespan: Duration) -> Option<Time> {
+        self.source_timestamp
+            .or(self.reception_timestamp)
+            .map(|timestamp| timestamp + lifespan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::{ChangeKind, EntityId};
+
+    pub(super) fn change(writer: u8, sequence_number: u64) -> CacheChange {
+        CacheChange::new(
+            ChangeKind::Alive,
+            Guid::new([writer; 12], EntityId::new([0, 0, 1], 0x02)),
+            InstanceHandle,
+            SequenceNumber::from(sequence_number),
+            Some(Data::from(vec![writer; 4])),
+            ParameterList,
+        )
+    }
+
+    #[test]
+    fn test_changes_keyed_by_writer() {
+        let mut cache = HistoryCache::new();
+        cache.add_change(change(1, 1)).unwrap();
+        cache.add_change(change(2, 1)).unwrap();
+        cache.add_change(change(1, 2)).unwrap();
+        assert_eq!(cache.len(), 3);
+
+        assert_eq!(
+            cache.remove_change(&change(2, 1)).unwrap(),
+            Some(change(2, 1))
+        );
+        assert!(cache
+            .get_change(change(1, 1).writer_guid(), SequenceNumber::from(1))
+            .is_some());
+        assert_eq!(cache.get_seq_num_min(), Some(SequenceNumber::from(1)));
+        assert_eq!(cache.get_seq_num_max(), Some(SequenceNumber::from(2)));
+    }
+
+    #[test]
+    fn test_changes_after() {
+        let mut cache = HistoryCache::new();
+        for (writer, sequence_number) in [(1, 1), (1, 3), (1, 4), (2, 2), (3, 1)] {
+            cache.add_change(change(writer, sequence_number)).unwrap();
+        }
+        let after = |writer, sequence_number| {
+            cache
+                .changes_after(
+                    change(writer, 0).writer_guid(),
+                    SequenceNumber::from(sequence_number),
+                )
+                .map(|c| c.sequence_number().value())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(after(1, 0), vec![1, 3, 4]);
+        assert_eq!(after(1, 1), vec![3, 4]);
+        assert_eq!(after(1, 4), vec![]);
+        assert_eq!(after(2, 0), vec![2]);
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let lifespan = Duration::from_secs(10);
+        let mut cache = HistoryCache::new();
+        cache
+            .add_change(change(1, 1).with_source_timestamp(Time::new(100, 0)))
+            .unwrap();
+        cache
+            .add_change(change(2, 1).with_reception_timestamp(Time::new(105, 0)))
+            .unwrap();
+        cache.add_change(change(3, 1)).unwrap();
+        assert_eq!(cache.next_expiry(lifespan), Some(Time::new(110, 0)));
+
+        let expired = cache.remove_expired(Time::new(110, 0), lifespan).unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].writer_guid(), change(1, 1).writer_guid());
+        assert_eq!(cache.next_expiry(lifespan), Some(Time::new(115, 0)));
+
+        assert_eq!(
+            cache
+                .remove_expired(Time::new(200, 0), lifespan)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.next_expiry(lifespan), None);
+    }
+
+    #[test]
+    fn test_last_sequence_number_survives_removal() {
+        let mut cache = HistoryCache::new();
+        cache.add_change(change(1, 1)).unwrap();
+        cache.add_change(change(1, 2)).unwrap();
+        cache.remove_change(&change(1, 2)).unwrap();
+        assert_eq!(cache.get_seq_num_max(), Some(SequenceNumber::from(1)));
+        assert_eq!(cache.last_sequence_number(), Some(SequenceNumber::from(2)));
+    }
+}
@@ -0,0 +1,749 @@
+//! On-disk [`HistoryCacheStorage`] for endpoints with `PERSISTENT`
+//! durability.
+//!
+//! [`LogStorage`] keeps every change in memory and journals each insertion
+//! and removal to an append-only log file. Each record is framed with its
+//! length and a CRC-32, so a record torn by a crash is detected on the next
+//! [`LogStorage::open`] and the log is truncated back to the last complete
+//! record.
+//!
+//! Removals leave dead records behind. Once they outnumber the live changes
+//! the log is compacted: the live changes are written to a temporary file
+//! which then atomically replaces the log.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{CacheChange, HistoryCacheStorage, InMemoryStorage};
+use crate::{
+    messages::Time,
+    structure::{
+        data::Data, ChangeKind, EntityId, Guid, InstanceHandle, ParameterList, SequenceNumber,
+    },
+};
+
+const MAGIC: &[u8; 8] = b"RTPSHC03";
+/// The first version of the log, whose changes have no timestamps.
+const MAGIC_V1: &[u8; 8] = b"RTPSHC01";
+/// The second version of the log, whose changes have no destination.
+const MAGIC_V2: &[u8; 8] = b"RTPSHC02";
+
+const RECORD_INSERT: u8 = 1;
+const RECORD_REMOVE: u8 = 2;
+const RECORD_WATERMARK: u8 = 3;
+
+/// Dead records tolerated before an automatic compaction, regardless of how
+/// many changes are live.
+const MIN_DEAD_RECORDS_TO_COMPACT: usize = 64;
+
+/// [`HistoryCacheStorage`] journaled to an append-only log file.
+#[derive(Debug)]
+pub struct LogStorage {
+    path: PathBuf,
+    file: File,
+    changes: InMemoryStorage,
+    dead_records: usize,
+    sync_writes: bool,
+}
+
+impl LogStorage {
+    /// Opens the log at `path`, creating it if it does not exist, and
+    /// recovers the changes it holds. A torn or corrupt tail left by a crash
+    /// is discarded.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or written, is not a history log, or
+    /// holds an intact record that cannot be decoded. The file is left
+    /// untouched in the last two cases.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut changes = InMemoryStorage::new();
+        let mut dead_records = 0;
+        let mut legacy = false;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+            file.sync_all()?;
+        } else {
+            let mut reader = BufReader::new(&mut file);
+            let mut magic = [0; MAGIC.len()];
+            reader.read_exact(&mut magic)?;
+            legacy = &magic == MAGIC_V1 || &magic == MAGIC_V2;
+            if &magic != MAGIC && !legacy {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a history cache log",
+                ));
+            }
+            let file_len = reader.get_ref().metadata()?.len();
+            let mut valid_len = MAGIC.len() as u64;
+            while let Some((record, len)) = read_record(&mut reader, file_len - valid_len)? {
+                dead_records += apply(&mut changes, record);
+                valid_len += len;
+            }
+            drop(reader);
+            if valid_len < file_len {
+                file.set_len(valid_len)?;
+                file.sync_all()?;
+            }
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let mut storage = Self {
+            path,
+            file,
+            changes,
+            dead_records,
+            sync_writes: true,
+        };
+        // Rewrite a log of an earlier version in the current format, so that
+        // it is never appended records it does not describe.
+        if legacy {
+            storage.compact()?;
+        }
+        Ok(storage)
+    }
+
+    /// Controls whether every record is flushed to disk before the operation
+    /// returns. Enabled by default; disabling it trades the durability of the
+    /// most recent changes for throughput.
+    #[must_use]
+    pub fn sync_writes(mut self, sync_writes: bool) -> Self {
+        self.sync_writes = sync_writes;
+        self
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rewrites the log so it only holds the live changes.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the new log cannot be written. The existing log is left
+    /// untouched in that case.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("compact");
+        // The new log is opened for appending before it replaces the old
+        // one, so that `self.file` never refers to the unlinked old log.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        {
+            let mut tmp = BufWriter::new(&mut file);
+            tmp.write_all(MAGIC)?;
+            if let Some(last) = self.changes.last_sequence_number() {
+                write_record(&mut tmp, &Record::Watermark(last))?;
+            }
+            for change in self.changes.iter() {
+                write_record(&mut tmp, &Record::Insert(change.clone()))?;
+            }
+            tmp.flush()?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        // Persist the rename itself. Not every platform supports syncing a
+        // directory, and the data is already safe either way.
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+
+        self.file = file;
+        self.dead_records = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut buf = Vec::new();
+        write_record(&mut buf, record)?;
+        self.file.write_all(&buf)?;
+        if self.sync_writes {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        if self.dead_records >= MIN_DEAD_RECORDS_TO_COMPACT
+            && self.dead_records > self.changes.len()
+        {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl HistoryCacheStorage for LogStorage {
+    fn insert(&mut self, change: CacheChange) -> io::Result<()> {
+        let record = Record::Insert(change);
+        self.append(&record)?;
+        self.dead_records += apply(&mut self.changes, record);
+        self.maybe_compact()
+    }
+
+    fn remove(
+        &mut self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> io::Result<Option<CacheChange>> {
+        if self.changes.get(writer_guid, sequence_number).is_none() {
+            return Ok(None);
+        }
+        self.append(&Record::Remove(writer_guid, sequence_number))?;
+        let removed = self.changes.remove(writer_guid, sequence_number)?;
+        // Both the insertion and the removal record are now dead.
+        self.dead_records += 2;
+        self.maybe_compact()?;
+        Ok(removed)
+    }
+
+    fn get(&self, writer_guid: Guid, sequence_number: SequenceNumber) -> Option<&CacheChange> {
+        self.changes.get(writer_guid, sequence_number)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &CacheChange> + '_> {
+        self.changes.iter()
+    }
+
+    fn iter_after(
+        &self,
+        writer_guid: Guid,
+        sequence_number: SequenceNumber,
+    ) -> Box<dyn Iterator<Item = &CacheChange> + '_> {
+        self.changes.iter_after(writer_guid, sequence_number)
+    }
+
+    fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    fn last_sequence_number(&self) -> Option<SequenceNumber> {
+        self.changes.last_sequence_number()
+    }
+}
+
+enum Record {
+    Insert(CacheChange),
+    Remove(Guid, SequenceNumber),
+    /// Carries the last sequence number across a compaction that dropped the
+    /// change it belonged to.
+    Watermark(SequenceNumber),
+}
+
+/// Applies a recovered or freshly written record and returns how many
+/// records it made dead.
+fn apply(changes: &mut InMemoryStorage, record: Record) -> usize {
+    match record {
+        Record::Insert(change) => {
+            let replaced = changes
+                .get(change.writer_guid, change.sequence_number)
+                .is_some();
+            // Inserting into memory cannot fail.
+            let _ = changes.insert(change);
+            usize::from(replaced)
+        }
+        Record::Remove(guid, sequence_number) => {
+            let _ = changes.remove(guid, sequence_number);
+            2
+        }
+        Record::Watermark(sequence_number) => {
+            changes.observe_sequence_number(sequence_number);
+            0
+        }
+    }
+}
+
+/// Frames a record as `length | crc32 | payload`, both integers
+/// little-endian.
+fn write_record(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    let mut payload = Vec::new();
+    match record {
+        Record::Insert(change) => {
+            payload.push(RECORD_INSERT);
+            encode_change(&mut payload, change)?;
+        }
+        Record::Remove(guid, sequence_number) => {
+            payload.push(RECORD_REMOVE);
+            encode_guid(&mut payload, *guid);
+            encode_sequence_number(&mut payload, *sequence_number);
+        }
+        Record::Watermark(sequence_number) => {
+            payload.push(RECORD_WATERMARK);
+            encode_sequence_number(&mut payload, *sequence_number);
+        }
+    }
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "change too large to log"))?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(&crc32(&payload).to_le_bytes())?;
+    out.write_all(&payload)
+}
+
+/// Reads the next record and the number of bytes it occupied, out of the
+/// `remaining` bytes of the log. Returns `None` at the end of the log or at
+/// the first record torn by a crash: one that is incomplete or fails its
+/// CRC.
+///
+/// # Errors
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if an intact record cannot be
+/// decoded.
+fn read_record(input: &mut impl Read, remaining: u64) -> io::Result<Option<(Record, u64)>> {
+    let mut frame = [0; 8];
+    if !read_full(input, &mut frame)? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    let crc = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+    // A length beyond the end of the log is as torn as a short read, and
+    // must not be allocated before the record is verified.
+    if u64::from(len) + 8 > remaining {
+        return Ok(None);
+    }
+    let mut payload = vec![0; len as usize];
+    if !read_full(input, &mut payload)? || crc32(&payload) != crc {
+        return Ok(None);
+    }
+    let mut decoder = Decoder(&payload);
+    let record = match decoder.u8() {
+        Some(RECORD_INSERT) => decode_change(&mut decoder).map(Record::Insert),
+        Some(RECORD_REMOVE) => decoder
+            .guid()
+            .zip(decoder.sequence_number())
+            .map(|(guid, sequence_number)| Record::Remove(guid, sequence_number)),
+        Some(RECORD_WATERMARK) => decoder.sequence_number().map(Record::Watermark),
+        _ => None,
+    };
+    let record = record.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "undecodable history log record")
+    })?;
+    Ok(Some((record, u64::from(len) + 8)))
+}
+
+/// Like `read_exact`, but reports a short read as `false` instead of an
+/// error.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn encode_guid(out: &mut Vec<u8>, guid: Guid) {
+    out.extend_from_slice(&guid.guid_prefix);
+    out.extend_from_slice(&guid.entity_id.entity_key);
+    out.push(guid.entity_id.entity_kind);
+}
+
+fn encode_sequence_number(out: &mut Vec<u8>, sequence_number: SequenceNumber) {
+    out.extend_from_slice(&sequence_number.high.to_le_bytes());
+    out.extend_from_slice(&sequence_number.low.to_le_bytes());
+}
+
+fn encode_change(out: &mut Vec<u8>, change: &CacheChange) -> io::Result<()> {
+    let too_large = |_| io::Error::new(io::ErrorKind::InvalidInput, "change too large to log");
+    out.push(match change.kind {
+        ChangeKind::Alive => 0,
+        ChangeKind::AliveFiltered => 1,
+        ChangeKind::NotAliveDisposed => 2,
+        ChangeKind::NotAliveUnregistered => 3,
+        ChangeKind::NotAliveDisposedUnregistered => 4,
+    });
+    encode_guid(out, change.writer_guid);
+    encode_sequence_number(out, change.sequence_number);
+    match &change.data_value {
+        Some(data) => {
+            out.push(1);
+            out.extend_from_slice(&u32::try_from(data.len()).map_err(too_large)?.to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        None => out.push(0),
+    }
+    for timestamp in [change.source_timestamp, change.reception_timestamp] {
+        match timestamp {
+            Some(time) => {
+                out.push(1);
+                out.extend_from_slice(&time.seconds().to_le_bytes());
+                out.extend_from_slice(&time.fraction().to_le_bytes());
+            }
+            None => out.push(0),
+        }
+    }
+    // Optional, so that records written before it was added still decode.
+    if let Some(destination) = &change.destination {
+        out.extend_from_slice(
+            &u32::try_from(destination.len())
+                .map_err(too_large)?
+                .to_le_bytes(),
+        );
+        for reader in destination {
+            encode_guid(out, *reader);
+        }
+    }
+    Ok(())
+}
+
+fn decode_change(decoder: &mut Decoder<'_>) -> Option<CacheChange> {
+    let kind = match decoder.u8()? {
+        0 => ChangeKind::Alive,
+        1 => ChangeKind::AliveFiltered,
+        2 => ChangeKind::NotAliveDisposed,
+        3 => ChangeKind::NotAliveUnregistered,
+        4 => ChangeKind::NotAliveDisposedUnregistered,
+        _ => return None,
+    };
+    let writer_guid = decoder.guid()?;
+    let sequence_number = decoder.sequence_number()?;
+    let data_value = match decoder.u8()? {
+        0 => None,
+        _ => {
+            let len = u32::from_le_bytes(decoder.array()?);
+            Some(Data::from(decoder.bytes(len as usize)?))
+        }
+    };
+    let mut change = CacheChange::new(
+        kind,
+        writer_guid,
+        InstanceHandle,
+        sequence_number,
+        data_value,
+        ParameterList,
+    );
+    // The timestamps and the destination are optional, so that records
+    // written before they were added still decode.
+    if !decoder.0.is_empty() {
+        change.source_timestamp = decoder.time()?;
+        change.reception_timestamp = decoder.time()?;
+    }
+    if !decoder.0.is_empty() {
+        let len = u32::from_le_bytes(decoder.array()?);
+        let destination = (0..len)
+            .map(|_| decoder.guid())
+            .collect::<Option<Vec<_>>>()?;
+        change.destination = Some(destination);
+    }
+    Some(change)
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.array::<1>().map(|[b]| b)
+    }
+
+    fn guid(&mut self) -> Option<Guid> {
+        let guid_prefix = self.array()?;
+        let entity_key = self.array()?;
+        let entity_kind = self.u8()?;
+        Some(Guid::new(
+            guid_prefix,
+            EntityId::new(entity_key, entity_kind),
+        ))
+    }
+
+    fn time(&mut self) -> Option<Option<Time>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => {
+                let seconds = u32::from_le_bytes(self.array()?);
+                let fraction = u32::from_le_bytes(self.array()?);
+                Some(Some(Time::new(seconds, fraction)))
+            }
+        }
+    }
+
+    fn sequence_number(&mut self) -> Option<SequenceNumber> {
+        let high = i32::from_le_bytes(self.array()?);
+        let low = u32::from_le_bytes(self.array()?);
+        Some(SequenceNumber::new(high, low))
+    }
+}
+
+/// CRC-32 (IEEE 802.3) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::historycache::{tests::change, HistoryCache};
+
+    /// A log file in the system temp directory, removed on drop.
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rtps-historycache-{}-{name}.log",
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(self.0.with_extension("compact"));
+        }
+    }
+
+    #[test]
+    fn test_recovers_after_restart() {
+        let log = TempLog::new("restart");
+        {
+            let mut cache = HistoryCache::with_storage(LogStorage::open(&log.0).unwrap());
+            for sn in 1..=3 {
+                cache
+                    .add_change(change(1, sn).with_source_timestamp(Time::new(sn as u32, 0)))
+                    .unwrap();
+            }
+            cache.remove_change(&change(1, 3)).unwrap();
+        }
+
+        let cache = HistoryCache::with_storage(LogStorage::open(&log.0).unwrap());
+        assert_eq!(
+            cache.changes().cloned().collect::<Vec<_>>(),
+            vec![
+                change(1, 1).with_source_timestamp(Time::new(1, 0)),
+                change(1, 2).with_source_timestamp(Time::new(2, 0))
+            ]
+        );
+        assert_eq!(cache.last_sequence_number(), Some(SequenceNumber::from(3)));
+    }
+
+    #[test]
+    fn test_recovers_destination() {
+        let log = TempLog::new("destination");
+        let directed =
+            change(1, 1).with_destination([Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07))]);
+        {
+            let mut cache = HistoryCache::with_storage(LogStorage::open(&log.0).unwrap());
+            cache.add_change(directed.clone()).unwrap();
+            cache.add_change(change(1, 2)).unwrap();
+        }
+
+        let cache = HistoryCache::with_storage(LogStorage::open(&log.0).unwrap());
+        assert_eq!(
+            cache.changes().cloned().collect::<Vec<_>>(),
+            vec![directed, change(1, 2)]
+        );
+    }
+
+    #[test]
+    fn test_discards_torn_tail() {
+        let log = TempLog::new("torn");
+        {
+            let mut storage = LogStorage::open(&log.0).unwrap();
+            storage.insert(change(1, 1)).unwrap();
+            storage.insert(change(1, 2)).unwrap();
+        }
+        let intact_len = fs::metadata(&log.0).unwrap().len();
+        {
+            // Simulate a crash halfway through appending a record.
+            let mut buf = Vec::new();
+            write_record(&mut buf, &Record::Insert(change(1, 3))).unwrap();
+            let mut file = OpenOptions::new().append(true).open(&log.0).unwrap();
+            file.write_all(&buf[..buf.len() / 2]).unwrap();
+        }
+
+        let mut storage = LogStorage::open(&log.0).unwrap();
+        assert_eq!(storage.len(), 2);
+        assert_eq!(fs::metadata(&log.0).unwrap().len(), intact_len);
+
+        storage.insert(change(1, 3)).unwrap();
+        drop(storage);
+        assert_eq!(LogStorage::open(&log.0).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_discards_oversized_frame() {
+        let log = TempLog::new("oversized");
+        {
+            let mut storage = LogStorage::open(&log.0).unwrap();
+            storage.insert(change(1, 1)).unwrap();
+        }
+        let intact_len = fs::metadata(&log.0).unwrap().len();
+        {
+            // A corrupt frame claiming a 4 GiB record.
+            let mut file = OpenOptions::new().append(true).open(&log.0).unwrap();
+            file.write_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1])
+                .unwrap();
+        }
+
+        let storage = LogStorage::open(&log.0).unwrap();
+        assert_eq!(storage.len(), 1);
+        assert_eq!(fs::metadata(&log.0).unwrap().len(), intact_len);
+    }
+
+    #[test]
+    fn test_rejects_undecodable_record() {
+        let log = TempLog::new("undecodable");
+        {
+            let mut storage = LogStorage::open(&log.0).unwrap();
+            storage.insert(change(1, 1)).unwrap();
+        }
+        {
+            // An intact record of an unknown type.
+            let payload = [0xee];
+            let mut file = OpenOptions::new().append(true).open(&log.0).unwrap();
+            file.write_all(&1u32.to_le_bytes()).unwrap();
+            file.write_all(&crc32(&payload).to_le_bytes()).unwrap();
+            file.write_all(&payload).unwrap();
+        }
+        let len = fs::metadata(&log.0).unwrap().len();
+
+        assert_eq!(
+            LogStorage::open(&log.0).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(fs::metadata(&log.0).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_compaction_keeps_live_changes_and_watermark() {
+        let log = TempLog::new("compact");
+        let mut storage = LogStorage::open(&log.0).unwrap().sync_writes(false);
+        for sn in 1..=100 {
+            storage.insert(change(1, sn)).unwrap();
+        }
+        for sn in 1..=99 {
+            storage
+                .remove(change(1, sn).writer_guid(), SequenceNumber::from(sn))
+                .unwrap();
+        }
+        storage.compact().unwrap();
+        storage
+            .remove(change(1, 100).writer_guid(), SequenceNumber::from(100))
+            .unwrap();
+        storage.compact().unwrap();
+        let compacted_len = fs::metadata(&log.0).unwrap().len();
+        drop(storage);
+
+        let storage = LogStorage::open(&log.0).unwrap();
+        assert!(storage.is_empty());
+        assert_eq!(
+            storage.last_sequence_number(),
+            Some(SequenceNumber::from(100))
+        );
+        assert!(compacted_len < 64);
+    }
+
+    #[test]
+    fn test_migrates_first_version() {
+        let log = TempLog::new("v1");
+        {
+            // A change as logged by the first version, without timestamps.
+            let data = change(1, 1).data_value().unwrap().to_vec();
+            let mut payload = vec![RECORD_INSERT, 0];
+            encode_guid(&mut payload, change(1, 1).writer_guid());
+            encode_sequence_number(&mut payload, SequenceNumber::from(1));
+            payload.push(1);
+            payload.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+            payload.extend_from_slice(&data);
+            let mut file = File::create(&log.0).unwrap();
+            file.write_all(MAGIC_V1).unwrap();
+            file.write_all(&u32::try_from(payload.len()).unwrap().to_le_bytes())
+                .unwrap();
+            file.write_all(&crc32(&payload).to_le_bytes()).unwrap();
+            file.write_all(&payload).unwrap();
+        }
+
+        let mut storage = LogStorage::open(&log.0).unwrap();
+        assert_eq!(
+            storage.iter().cloned().collect::<Vec<_>>(),
+            vec![change(1, 1)]
+        );
+        assert!(fs::read(&log.0).unwrap().starts_with(MAGIC));
+        storage.insert(change(1, 2)).unwrap();
+        drop(storage);
+        assert_eq!(LogStorage::open(&log.0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_migrates_second_version() {
+        let log = TempLog::new("v2");
+        {
+            // The second version logged the same records, without
+            // destinations.
+            let mut file = File::create(&log.0).unwrap();
+            file.write_all(MAGIC_V2).unwrap();
+            write_record(&mut file, &Record::Insert(change(1, 1))).unwrap();
+        }
+
+        let mut storage = LogStorage::open(&log.0).unwrap();
+        assert_eq!(
+            storage.iter().cloned().collect::<Vec<_>>(),
+            vec![change(1, 1)]
+        );
+        assert!(fs::read(&log.0).unwrap().starts_with(MAGIC));
+        let directed =
+            change(1, 2).with_destination([Guid::new([2; 12], EntityId::new([0, 0, 1], 0x07))]);
+        storage.insert(directed.clone()).unwrap();
+        drop(storage);
+        let storage = LogStorage::open(&log.0).unwrap();
+        assert_eq!(
+            storage.iter().cloned().collect::<Vec<_>>(),
+            vec![change(1, 1), directed]
+        );
+    }
+
+    #[test]
+    fn test_rejects_foreign_file() {
+        let log = TempLog::new("foreign");
+        fs::write(&log.0, b"not a log").unwrap();
+        assert_eq!(
+            LogStorage::open(&log.0).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
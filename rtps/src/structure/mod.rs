@@ -4,71 +4,31 @@
 
 use std::cmp::Ordering;
 
-/// See Section 8.2.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=25)
-#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct HistoryCache {
-    changes: Vec<CacheChange>,
-}
-
-impl HistoryCache {
-    /// See section 8.2.2.1 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
-    pub fn new() -> Self {
-        Self {
-            changes: Vec::new(),
-        }
-    }
-
-    /// See section 8.2.2.2 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
-    pub fn add_change(&mut self, change: CacheChange) {
-        self.changes.push(change);
-    }
-
-    /// See section 8.2.2.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
-    pub fn remove_change(&mut self, change: &CacheChange) -> Option<CacheChange> {
-        match self
-            .changes
-            .iter()
-            .position(|c| c.sequence_number == change.sequence_number)
-        {
-            Some(index) => Some(self.changes.remove(index)),
-            None => None,
-        }
-    }
-
-    /// See section 8.2.2.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
-    pub fn get_seq_num_min(&self) -> Option<SequenceNumber> {
-        self.changes.iter().map(|c| c.sequence_number).min()
-    }
-
-    /// See section 8.2.2.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/).
-    pub fn get_seq_num_max(&self) -> Option<SequenceNumber> {
-        self.changes.iter().map(|c| c.sequence_number).max()
-    }
-}
-
-/// See section 8.2.3 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=28).
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct CacheChange {
-    kind: ChangeKind,
-    writer_guid: Guid,
-    instance_handle: InstanceHandle,
-    sequence_number: SequenceNumber,
-    data_value: Option<Data>,
-    inline_qos: ParameterList,
-}
+pub mod data;
+pub mod historycache;
+pub mod participant;
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct ParameterList;
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct Data;
-
 /// See section 8.2.4 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=28).
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct Entity {
     guid: Guid,
 }
 
+impl Entity {
+    #[must_use]
+    pub const fn new(guid: Guid) -> Self {
+        Self { guid }
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.guid
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct Guid {
     guid_prefix: GuidPrefix,
@@ -82,6 +42,16 @@ impl Guid {
             entity_id,
         }
     }
+
+    #[must_use]
+    pub const fn guid_prefix(&self) -> GuidPrefix {
+        self.guid_prefix
+    }
+
+    #[must_use]
+    pub const fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
 }
 
 pub type GuidPrefix = [u8; 12];
@@ -102,6 +72,16 @@ impl EntityId {
             entity_kind,
         }
     }
+
+    #[must_use]
+    pub const fn entity_key(&self) -> [u8; 3] {
+        self.entity_key
+    }
+
+    #[must_use]
+    pub const fn entity_kind(&self) -> u8 {
+        self.entity_kind
+    }
 }
 
 pub const ENTITYID_UNKNOWN: EntityId = EntityId::new([0, 0, 0], 0);
@@ -117,13 +97,18 @@ pub const ENTITYID_SEDP_BUILTIN_TOPICS_DETECTOR: EntityId = EntityId::new([0, 0,
 pub const ENTITYID_SEDP_BUILTIN_MESSAGE_WRITER: EntityId = EntityId::new([0, 2, 0], 0xc2);
 pub const ENTITYID_SEDP_BUILTIN_MESSAGE_READER: EntityId = EntityId::new([0, 2, 0], 0xc7);
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Hash, Eq, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Hash, Eq)]
 pub struct SequenceNumber {
     high: i32,
     low: u32,
 }
 
 impl SequenceNumber {
+    #[must_use]
+    pub const fn new(high: i32, low: u32) -> Self {
+        Self { high, low }
+    }
+
     #[must_use]
     pub fn value(&self) -> u64 {
         u64::from(self.low) + ((self.high as u64) << 32)
@@ -139,9 +124,24 @@ impl SequenceNumber {
     }
 }
 
+impl From<u64> for SequenceNumber {
+    fn from(value: u64) -> Self {
+        Self {
+            high: (value >> 32) as i32,
+            low: value as u32,
+        }
+    }
+}
+
 impl PartialOrd for SequenceNumber {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.value().cmp(&other.value()))
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SequenceNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value().cmp(&other.value())
     }
 }
 
@@ -178,6 +178,7 @@ pub enum ChangeKind {
     AliveFiltered,
     NotAliveDisposed,
     NotAliveUnregistered,
+    NotAliveDisposedUnregistered,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
@@ -209,6 +210,23 @@ pub struct ProtocolVersion {
     minor: u8,
 }
 
+impl ProtocolVersion {
+    #[must_use]
+    pub const fn new(major: u8, minor: u8) -> Self {
+        Self { major, minor }
+    }
+
+    #[must_use]
+    pub const fn major(&self) -> u8 {
+        self.major
+    }
+
+    #[must_use]
+    pub const fn minor(&self) -> u8 {
+        self.minor
+    }
+}
+
 pub const PROTOCOLVERSION: ProtocolVersion = PROTOCOLVERSION_2_5;
 pub const PROTOCOLVERSION_1_0: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
 pub const PROTOCOLVERSION_1_1: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
@@ -228,41 +246,6 @@ pub type VendorId = [u8; 2];
 
 pub const VENDORID_UNKNOWN: VendorId = [0; 2];
 
-/// See Section 8.2.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=30)
-#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct Participant {
-    entity: Entity,
-
-    protocol_version: ProtocolVersion,
-    vendor_id: VendorId,
-    default_unicast_locator_list: Vec<Locator>,
-    default_multicast_locator_list: Vec<Locator>,
-    guid_prefix: GuidPrefix,
-
-    publishers: Vec<Group>,
-    subscribers: Vec<Group>,
-}
-
-/// See Section 8.2.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=31)
-#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct Group {
-    entity: Entity,
-
-    endpoints: Vec<Endpoint>,
-}
-
-/// See Section 8.2.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=31)
-#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
-pub struct Endpoint {
-    entity: Entity,
-
-    topic_kind: TopicKind,
-    reliability_level: ReliabilityKind,
-    unicast_locator_list: Vec<Locator>,
-    multicast_locator_list: Vec<Locator>,
-    endpoint_group: EntityId,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
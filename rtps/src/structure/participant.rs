@@ -0,0 +1,571 @@
+//! Participant, Group and Endpoint entities and the builders used to create
+//! them.
+//!
+//! See Sections 8.2.5 through 8.2.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=30).
+
+use std::{error::Error, fmt};
+
+use super::{
+    Entity, EntityId, Guid, GuidPrefix, Locator, ProtocolVersion, ReliabilityKind, TopicKind,
+    VendorId, ENTITYID_PARTICIPANT, ENTITYID_UNKNOWN, PROTOCOLVERSION, VENDORID_UNKNOWN,
+};
+
+/// Errors returned when adding an entity to a container would leave the
+/// containment hierarchy with inconsistent GUIDs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContainmentError {
+    /// The contained entity does not share the container's GUID prefix.
+    GuidPrefixMismatch {
+        expected: GuidPrefix,
+        found: GuidPrefix,
+    },
+    /// The endpoint already names a different group as its `endpoint_group`.
+    EndpointGroupMismatch { expected: EntityId, found: EntityId },
+    /// An entity with the same GUID is already part of the participant.
+    DuplicateGuid(Guid),
+    /// The participant has no group with the given GUID.
+    UnknownGroup(Guid),
+}
+
+impl fmt::Display for ContainmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GuidPrefixMismatch { expected, found } => write!(
+                f,
+                "guid prefix {found:?} does not match the container's guid prefix {expected:?}"
+            ),
+            Self::EndpointGroupMismatch { expected, found } => write!(
+                f,
+                "endpoint belongs to group {found:?} but was added to group {expected:?}"
+            ),
+            Self::DuplicateGuid(guid) => write!(f, "an entity with guid {guid:?} already exists"),
+            Self::UnknownGroup(guid) => write!(f, "no group with guid {guid:?}"),
+        }
+    }
+}
+
+impl Error for ContainmentError {}
+
+/// See Section 8.2.5 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=30)
+#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct Participant {
+    entity: Entity,
+
+    protocol_version: ProtocolVersion,
+    vendor_id: VendorId,
+    default_unicast_locator_list: Vec<Locator>,
+    default_multicast_locator_list: Vec<Locator>,
+    guid_prefix: GuidPrefix,
+
+    publishers: Vec<Group>,
+    subscribers: Vec<Group>,
+}
+
+impl Participant {
+    #[must_use]
+    pub fn builder(guid_prefix: GuidPrefix) -> ParticipantBuilder {
+        ParticipantBuilder::new(guid_prefix)
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.entity.guid()
+    }
+
+    #[must_use]
+    pub const fn guid_prefix(&self) -> GuidPrefix {
+        self.guid_prefix
+    }
+
+    #[must_use]
+    pub const fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    #[must_use]
+    pub const fn vendor_id(&self) -> VendorId {
+        self.vendor_id
+    }
+
+    #[must_use]
+    pub fn default_unicast_locator_list(&self) -> &[Locator] {
+        &self.default_unicast_locator_list
+    }
+
+    #[must_use]
+    pub fn default_multicast_locator_list(&self) -> &[Locator] {
+        &self.default_multicast_locator_list
+    }
+
+    #[must_use]
+    pub fn publishers(&self) -> &[Group] {
+        &self.publishers
+    }
+
+    #[must_use]
+    pub fn subscribers(&self) -> &[Group] {
+        &self.subscribers
+    }
+
+    /// Adds a publisher [`Group`] to the participant.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the group or one of its endpoints does not share the
+    /// participant's GUID prefix, or reuses a GUID already in the participant.
+    pub fn add_publisher(&mut self, group: Group) -> Result<(), ContainmentError> {
+        self.check_group(&group)?;
+        self.publishers.push(group);
+        Ok(())
+    }
+
+    /// Adds a subscriber [`Group`] to the participant.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the group or one of its endpoints does not share the
+    /// participant's GUID prefix, or reuses a GUID already in the participant.
+    pub fn add_subscriber(&mut self, group: Group) -> Result<(), ContainmentError> {
+        self.check_group(&group)?;
+        self.subscribers.push(group);
+        Ok(())
+    }
+
+    /// Removes the publisher or subscriber group identified by `guid`.
+    pub fn remove_group(&mut self, guid: &Guid) -> Option<Group> {
+        for groups in [&mut self.publishers, &mut self.subscribers] {
+            if let Some(index) = groups.iter().position(|g| g.guid() == *guid) {
+                return Some(groups.remove(index));
+            }
+        }
+        None
+    }
+
+    #[must_use]
+    pub fn lookup_group(&self, guid: &Guid) -> Option<&Group> {
+        self.groups().find(|g| g.guid() == *guid)
+    }
+
+    pub fn lookup_group_mut(&mut self, guid: &Guid) -> Option<&mut Group> {
+        self.publishers
+            .iter_mut()
+            .chain(self.subscribers.iter_mut())
+            .find(|g| g.guid() == *guid)
+    }
+
+    /// Looks up an endpoint in any of the participant's groups.
+    #[must_use]
+    pub fn lookup_endpoint(&self, guid: &Guid) -> Option<&Endpoint> {
+        self.groups().find_map(|g| g.lookup_endpoint(guid))
+    }
+
+    /// Adds `endpoint` to the group identified by `group`. Unlike
+    /// [`Group::add_endpoint`], this also rejects GUIDs used anywhere else
+    /// in the participant.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the group does not exist, or if the endpoint is not
+    /// consistent with the group and participant.
+    pub fn add_endpoint(
+        &mut self,
+        group: &Guid,
+        endpoint: Endpoint,
+    ) -> Result<(), ContainmentError> {
+        self.check_prefix(endpoint.guid().guid_prefix())?;
+        if self.contains(&endpoint.guid()) {
+            return Err(ContainmentError::DuplicateGuid(endpoint.guid()));
+        }
+        self.lookup_group_mut(group)
+            .ok_or(ContainmentError::UnknownGroup(*group))
+            .and_then(|g| g.add_endpoint(endpoint))
+    }
+
+    /// Removes the endpoint identified by `guid` from whichever group holds it.
+    pub fn remove_endpoint(&mut self, guid: &Guid) -> Option<Endpoint> {
+        self.publishers
+            .iter_mut()
+            .chain(self.subscribers.iter_mut())
+            .find_map(|g| g.remove_endpoint(guid))
+    }
+
+    fn groups(&self) -> impl Iterator<Item = &Group> {
+        self.publishers.iter().chain(self.subscribers.iter())
+    }
+
+    fn contains(&self, guid: &Guid) -> bool {
+        *guid == self.guid()
+            || self
+                .groups()
+                .any(|g| g.guid() == *guid || g.lookup_endpoint(guid).is_some())
+    }
+
+    fn check_prefix(&self, found: GuidPrefix) -> Result<(), ContainmentError> {
+        if found == self.guid_prefix {
+            Ok(())
+        } else {
+            Err(ContainmentError::GuidPrefixMismatch {
+                expected: self.guid_prefix,
+                found,
+            })
+        }
+    }
+
+    fn check_group(&self, group: &Group) -> Result<(), ContainmentError> {
+        self.check_prefix(group.guid().guid_prefix())?;
+        for guid in std::iter::once(group.guid()).chain(group.endpoints.iter().map(Endpoint::guid))
+        {
+            if self.contains(&guid) {
+                return Err(ContainmentError::DuplicateGuid(guid));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builder for [`Participant`].
+#[derive(Debug)]
+pub struct ParticipantBuilder {
+    guid_prefix: GuidPrefix,
+    protocol_version: ProtocolVersion,
+    vendor_id: VendorId,
+    default_unicast_locator_list: Vec<Locator>,
+    default_multicast_locator_list: Vec<Locator>,
+}
+
+impl ParticipantBuilder {
+    #[must_use]
+    pub fn new(guid_prefix: GuidPrefix) -> Self {
+        Self {
+            guid_prefix,
+            protocol_version: PROTOCOLVERSION,
+            vendor_id: VENDORID_UNKNOWN,
+            default_unicast_locator_list: Vec::new(),
+            default_multicast_locator_list: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    #[must_use]
+    pub fn vendor_id(mut self, vendor_id: VendorId) -> Self {
+        self.vendor_id = vendor_id;
+        self
+    }
+
+    #[must_use]
+    pub fn default_unicast_locator(mut self, locator: Locator) -> Self {
+        self.default_unicast_locator_list.push(locator);
+        self
+    }
+
+    #[must_use]
+    pub fn default_multicast_locator(mut self, locator: Locator) -> Self {
+        self.default_multicast_locator_list.push(locator);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> Participant {
+        Participant {
+            entity: Entity::new(Guid::new(self.guid_prefix, ENTITYID_PARTICIPANT)),
+            protocol_version: self.protocol_version,
+            vendor_id: self.vendor_id,
+            default_unicast_locator_list: self.default_unicast_locator_list,
+            default_multicast_locator_list: self.default_multicast_locator_list,
+            guid_prefix: self.guid_prefix,
+            publishers: Vec::new(),
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+/// See Section 8.2.6 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=31)
+#[derive(Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct Group {
+    entity: Entity,
+
+    endpoints: Vec<Endpoint>,
+}
+
+impl Group {
+    #[must_use]
+    pub const fn new(guid: Guid) -> Self {
+        Self {
+            entity: Entity::new(guid),
+            endpoints: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.entity.guid()
+    }
+
+    #[must_use]
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Adds `endpoint` to the group. An endpoint built without an
+    /// `endpoint_group` is assigned to this group.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the endpoint does not share the group's GUID prefix, names a
+    /// different group, or reuses the GUID of an endpoint in the group.
+    pub fn add_endpoint(&mut self, mut endpoint: Endpoint) -> Result<(), ContainmentError> {
+        let guid = self.guid();
+        if endpoint.guid().guid_prefix() != guid.guid_prefix() {
+            return Err(ContainmentError::GuidPrefixMismatch {
+                expected: guid.guid_prefix(),
+                found: endpoint.guid().guid_prefix(),
+            });
+        }
+        if endpoint.endpoint_group == ENTITYID_UNKNOWN {
+            endpoint.endpoint_group = guid.entity_id();
+        } else if endpoint.endpoint_group != guid.entity_id() {
+            return Err(ContainmentError::EndpointGroupMismatch {
+                expected: guid.entity_id(),
+                found: endpoint.endpoint_group,
+            });
+        }
+        if endpoint.guid() == guid || self.lookup_endpoint(&endpoint.guid()).is_some() {
+            return Err(ContainmentError::DuplicateGuid(endpoint.guid()));
+        }
+        self.endpoints.push(endpoint);
+        Ok(())
+    }
+
+    pub fn remove_endpoint(&mut self, guid: &Guid) -> Option<Endpoint> {
+        self.endpoints
+            .iter()
+            .position(|e| e.guid() == *guid)
+            .map(|index| self.endpoints.remove(index))
+    }
+
+    #[must_use]
+    pub fn lookup_endpoint(&self, guid: &Guid) -> Option<&Endpoint> {
+        self.endpoints.iter().find(|e| e.guid() == *guid)
+    }
+}
+
+/// See Section 8.2.7 of the [specification](https://www.omg.org/spec/DDSI-RTPS/2.5/PDF#page=31)
+#[derive(Clone, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
+pub struct Endpoint {
+    entity: Entity,
+
+    topic_kind: TopicKind,
+    reliability_level: ReliabilityKind,
+    unicast_locator_list: Vec<Locator>,
+    multicast_locator_list: Vec<Locator>,
+    endpoint_group: EntityId,
+}
+
+impl Endpoint {
+    #[must_use]
+    pub fn builder(guid: Guid) -> EndpointBuilder {
+        EndpointBuilder::new(guid)
+    }
+
+    #[must_use]
+    pub const fn guid(&self) -> Guid {
+        self.entity.guid()
+    }
+
+    #[must_use]
+    pub const fn topic_kind(&self) -> TopicKind {
+        self.topic_kind
+    }
+
+    #[must_use]
+    pub const fn reliability_level(&self) -> ReliabilityKind {
+        self.reliability_level
+    }
+
+    #[must_use]
+    pub fn unicast_locator_list(&self) -> &[Locator] {
+        &self.unicast_locator_list
+    }
+
+    #[must_use]
+    pub fn multicast_locator_list(&self) -> &[Locator] {
+        &self.multicast_locator_list
+    }
+
+    /// The entity id of the [`Group`] the endpoint belongs to, or
+    /// [`ENTITYID_UNKNOWN`] if it has not been added to one.
+    #[must_use]
+    pub const fn endpoint_group(&self) -> EntityId {
+        self.endpoint_group
+    }
+}
+
+/// Builder for [`Endpoint`]. Endpoints default to best-effort reliability and
+/// [`TopicKind::NoKey`].
+#[derive(Debug)]
+pub struct EndpointBuilder {
+    guid: Guid,
+    topic_kind: TopicKind,
+    reliability_level: ReliabilityKind,
+    unicast_locator_list: Vec<Locator>,
+    multicast_locator_list: Vec<Locator>,
+    endpoint_group: EntityId,
+}
+
+impl EndpointBuilder {
+    #[must_use]
+    pub const fn new(guid: Guid) -> Self {
+        Self {
+            guid,
+            topic_kind: TopicKind::NoKey,
+            reliability_level: ReliabilityKind::BestEffort,
+            unicast_locator_list: Vec::new(),
+            multicast_locator_list: Vec::new(),
+            endpoint_group: ENTITYID_UNKNOWN,
+        }
+    }
+
+    #[must_use]
+    pub const fn topic_kind(mut self, topic_kind: TopicKind) -> Self {
+        self.topic_kind = topic_kind;
+        self
+    }
+
+    #[must_use]
+    pub const fn reliability_level(mut self, reliability_level: ReliabilityKind) -> Self {
+        self.reliability_level = reliability_level;
+        self
+    }
+
+    #[must_use]
+    pub fn unicast_locator(mut self, locator: Locator) -> Self {
+        self.unicast_locator_list.push(locator);
+        self
+    }
+
+    #[must_use]
+    pub fn multicast_locator(mut self, locator: Locator) -> Self {
+        self.multicast_locator_list.push(locator);
+        self
+    }
+
+    #[must_use]
+    pub const fn endpoint_group(mut self, endpoint_group: EntityId) -> Self {
+        self.endpoint_group = endpoint_group;
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> Endpoint {
+        Endpoint {
+            entity: Entity::new(self.guid),
+            topic_kind: self.topic_kind,
+            reliability_level: self.reliability_level,
+            unicast_locator_list: self.unicast_locator_list,
+            multicast_locator_list: self.multicast_locator_list,
+            endpoint_group: self.endpoint_group,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: GuidPrefix = [1; 12];
+
+    fn guid(key: u8, kind: u8) -> Guid {
+        Guid::new(PREFIX, EntityId::new([0, 0, key], kind))
+    }
+
+    #[test]
+    fn test_builders() {
+        let locator = "127.0.0.1:7411".parse().unwrap();
+        let participant = Participant::builder(PREFIX)
+            .vendor_id([1, 2])
+            .default_unicast_locator(locator)
+            .build();
+        assert_eq!(participant.guid(), Guid::new(PREFIX, ENTITYID_PARTICIPANT));
+        assert_eq!(participant.vendor_id(), [1, 2]);
+        assert_eq!(participant.default_unicast_locator_list(), &[locator]);
+
+        let endpoint = Endpoint::builder(guid(1, 0x02))
+            .reliability_level(ReliabilityKind::Reliable)
+            .topic_kind(TopicKind::WithKey)
+            .build();
+        assert_eq!(endpoint.reliability_level(), ReliabilityKind::Reliable);
+        assert_eq!(endpoint.topic_kind(), TopicKind::WithKey);
+        assert_eq!(endpoint.endpoint_group(), ENTITYID_UNKNOWN);
+    }
+
+    #[test]
+    fn test_group_containment() {
+        let mut group = Group::new(guid(2, 0x08));
+        group
+            .add_endpoint(Endpoint::builder(guid(1, 0x02)).build())
+            .unwrap();
+        assert_eq!(
+            group
+                .lookup_endpoint(&guid(1, 0x02))
+                .unwrap()
+                .endpoint_group(),
+            guid(2, 0x08).entity_id()
+        );
+        assert_eq!(
+            group.add_endpoint(Endpoint::builder(guid(1, 0x02)).build()),
+            Err(ContainmentError::DuplicateGuid(guid(1, 0x02)))
+        );
+        assert!(matches!(
+            group.add_endpoint(
+                Endpoint::builder(Guid::new([2; 12], guid(3, 0x02).entity_id())).build()
+            ),
+            Err(ContainmentError::GuidPrefixMismatch { .. })
+        ));
+        assert!(matches!(
+            group.add_endpoint(
+                Endpoint::builder(guid(3, 0x02))
+                    .endpoint_group(guid(9, 0x08).entity_id())
+                    .build()
+            ),
+            Err(ContainmentError::EndpointGroupMismatch { .. })
+        ));
+        assert!(group.remove_endpoint(&guid(1, 0x02)).is_some());
+        assert!(group.endpoints().is_empty());
+    }
+
+    #[test]
+    fn test_participant_containment() {
+        let mut participant = Participant::builder(PREFIX).build();
+        let mut publisher = Group::new(guid(2, 0x08));
+        publisher
+            .add_endpoint(Endpoint::builder(guid(1, 0x02)).build())
+            .unwrap();
+        participant.add_publisher(publisher).unwrap();
+        participant
+            .add_subscriber(Group::new(guid(3, 0x09)))
+            .unwrap();
+
+        assert_eq!(
+            participant.add_endpoint(&guid(3, 0x09), Endpoint::builder(guid(1, 0x02)).build()),
+            Err(ContainmentError::DuplicateGuid(guid(1, 0x02)))
+        );
+        participant
+            .add_endpoint(&guid(3, 0x09), Endpoint::builder(guid(1, 0x07)).build())
+            .unwrap();
+        assert!(participant.lookup_endpoint(&guid(1, 0x07)).is_some());
+        assert!(matches!(
+            participant.add_subscriber(Group::new(Guid::new([2; 12], ENTITYID_UNKNOWN))),
+            Err(ContainmentError::GuidPrefixMismatch { .. })
+        ));
+
+        assert!(participant.remove_endpoint(&guid(1, 0x02)).is_some());
+        assert!(participant.lookup_endpoint(&guid(1, 0x02)).is_none());
+        assert!(participant.remove_group(&guid(2, 0x08)).is_some());
+        assert!(participant.publishers().is_empty());
+    }
+}